@@ -0,0 +1,44 @@
+# Strings of the built-in chat messages. Every locale must define the same
+# messages, `{ $name }` placeables are filled in by the notifiers.
+
+state-ok = OK
+state-alarm = ALARM
+state-insufficient-data = INSUFFICIENT_DATA
+# Previous state, after the new one.
+was-state = (was { $state })
+# Discord footer.
+transition = { $previous } -> { $new } at { $time }
+
+comparison-greater-than-or-equal = >=
+comparison-greater-than = >
+comparison-less-than = <
+comparison-less-than-or-equal = <=
+# "UnHealthyHostCount >= 1"
+condition = { $metric } { $comparison } { $threshold }
+threshold = { $comparison } { $threshold }
+period = { $count } x { $seconds }s
+
+label-state = State
+label-metric = Metric
+label-namespace = Namespace
+label-dimensions = Dimensions
+label-threshold = Threshold
+label-period = Period
+label-changed-at = Changed at
+
+open-in-cloudwatch = Open in CloudWatch
+metric-graph = Metric graph
+runbook = Runbook
+# Alternative text of the sparkline.
+datapoints = { $metric } datapoints
+# Added to the message of an alarm once it recovers.
+resolved-after = Resolved after { $duration }
+# Posted instead of the transitions of an alarm which flaps, then once it
+# settles.
+flapping-started = { $name } is flapping: { $count } state changes within { $window }, its transitions are muted until it settles.
+flapping-settled = { $name } settled in { $state } after flapping for { $duration } (muted transitions: { $count }).
+# Header of the message of a group of alarms, followed by one line per alarm.
+group-summary = { $count } alarms for { $key }
+
+# strftime format of the state change time, in the destination's time zone.
+timestamp-format = %Y-%m-%d %H:%M:%S %Z
@@ -0,0 +1,32 @@
+state-ok = OK
+state-alarm = ALARME
+state-insufficient-data = DONNÉES INSUFFISANTES
+was-state = (précédemment { $state })
+transition = { $previous } -> { $new } le { $time }
+
+comparison-greater-than-or-equal = supérieur ou égal à
+comparison-greater-than = supérieur à
+comparison-less-than = inférieur à
+comparison-less-than-or-equal = inférieur ou égal à
+condition = { $metric } { $comparison } { $threshold }
+threshold = { $comparison } { $threshold }
+period = { $count } x { $seconds } s
+
+label-state = État
+label-metric = Métrique
+label-namespace = Espace de noms
+label-dimensions = Dimensions
+label-threshold = Seuil
+label-period = Période
+label-changed-at = Modifiée le
+
+open-in-cloudwatch = Ouvrir dans CloudWatch
+metric-graph = Graphique de la métrique
+runbook = Procédure
+datapoints = Points de données { $metric }
+resolved-after = Résolue après { $duration }
+flapping-started = { $name } oscille : { $count } changements d'état en { $window }, ses transitions ne sont plus publiées jusqu'à ce qu'elle se stabilise.
+flapping-settled = { $name } s'est stabilisée en { $state } après avoir oscillé pendant { $duration } (transitions non publiées : { $count }).
+group-summary = { $count } alarmes pour { $key }
+
+timestamp-format = %d/%m/%Y %H:%M:%S %Z
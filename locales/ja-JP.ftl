@@ -0,0 +1,33 @@
+state-ok = 正常
+state-alarm = アラーム
+state-insufficient-data = データ不足
+was-state = (変更前: { $state })
+transition = { $time } に { $previous } -> { $new }
+
+comparison-greater-than-or-equal = 以上
+comparison-greater-than = より大きい
+comparison-less-than = 未満
+comparison-less-than-or-equal = 以下
+# The threshold comes before the comparison in Japanese.
+condition = { $metric } が { $threshold } { $comparison }
+threshold = { $threshold } { $comparison }
+period = { $count } x { $seconds } 秒
+
+label-state = 状態
+label-metric = メトリクス
+label-namespace = 名前空間
+label-dimensions = ディメンション
+label-threshold = しきい値
+label-period = 期間
+label-changed-at = 変更日時
+
+open-in-cloudwatch = CloudWatch で開く
+metric-graph = メトリクスのグラフ
+runbook = ランブック
+datapoints = { $metric } のデータポイント
+resolved-after = { $duration } 後に解消
+flapping-started = { $name } がフラッピングしています: { $window } 以内に { $count } 回の状態変化。安定するまで遷移を通知しません。
+flapping-settled = { $name } は { $duration } のフラッピングの後 { $state } で安定しました (通知しなかった遷移: { $count })。
+group-summary = { $key } のアラーム { $count } 件
+
+timestamp-format = %Y年%m月%d日 %H:%M:%S %Z
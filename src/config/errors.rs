@@ -0,0 +1,170 @@
+use std::fmt::{Display, Error, Formatter};
+use toml;
+
+#[derive(Debug)]
+pub(crate) struct ConfigError {
+    pub line: Option<usize>,
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        match (self.line, self.key.as_ref()) {
+            (Some(line), Some(key)) => write!(fmt, "line {}, key `{}`: {}", line, key, self.message),
+            (Some(line), None) => write!(fmt, "line {}: {}", line, self.message),
+            (None, Some(key)) => write!(fmt, "key `{}`: {}", key, self.message),
+            (None, None) => write!(fmt, "{}", self.message),
+        }
+    }
+}
+
+impl ConfigError {
+    pub fn new(message: String) -> ConfigError {
+        ConfigError {
+            line: None,
+            key: None,
+            message: message,
+        }
+    }
+
+    // `index` is the position of the `[[array]]` table the key belongs to,
+    // which the TOML deserializer does not report.
+    pub fn from_toml(source: &str, err: &toml::de::Error, index: Option<usize>) -> ConfigError {
+        let mut message = err.to_string();
+        if let Some(pos) = message.rfind(" at line ") {
+            message.truncate(pos);
+        }
+        let key = message.find(" for key `").map(|pos| {
+            let key = message[pos + 10..].trim_right_matches('`').to_string();
+            message.truncate(pos);
+            key
+        });
+        let line = match err.line_col() {
+            Some((line, _)) => Some(line + 1),
+            None => key.as_ref().and_then(|key| {
+                let path: Vec<&str> = key.split('.').collect();
+                locate_path(source, &path, index)
+            }),
+        };
+
+        ConfigError {
+            line: line,
+            key: key,
+            message: message,
+        }
+    }
+}
+
+// Parses a `[table]` or `[[table]]` header into its path.
+fn header(line: &str) -> Option<(Vec<String>, bool)> {
+    if !line.starts_with('[') {
+        return None;
+    }
+    let array = line.starts_with("[[");
+    let inner = line.trim_left_matches('[').split(']').next().unwrap_or("");
+    let path = inner
+        .split('.')
+        .map(|part| part.trim().trim_matches('"').to_string())
+        .collect();
+    Some((path, array))
+}
+
+fn scan<F>(source: &str, table: &[&str], index: Option<usize>, mut found: F) -> Option<usize>
+where
+    F: FnMut(&str, bool) -> bool,
+{
+    let mut in_table = table.is_empty();
+    let mut occurrences = 0;
+
+    for (n, line) in source.lines().enumerate() {
+        let line = line.trim();
+        match header(line) {
+            Some((path, array)) => {
+                in_table = path == table;
+                if in_table && array {
+                    in_table = index.unwrap_or(0) == occurrences;
+                    occurrences += 1;
+                }
+                if in_table && found(line, true) {
+                    return Some(n + 1);
+                }
+            }
+            None => {
+                if in_table && found(line, false) {
+                    return Some(n + 1);
+                }
+            }
+        }
+    }
+    None
+}
+
+// Line (1-based) of `key = ...` in the given table.
+pub(crate) fn find_key(source: &str, table: &[&str], index: Option<usize>, key: &str) -> Option<usize> {
+    scan(source, table, index, |line, is_header| {
+        !is_header && line.contains('=') &&
+            line.split('=').next().map(|k| k.trim().trim_matches('"')) == Some(key)
+    })
+}
+
+// Line (1-based) of the header of the given table.
+pub(crate) fn find_table(source: &str, table: &[&str], index: Option<usize>) -> Option<usize> {
+    if table.is_empty() {
+        return None;
+    }
+    scan(source, table, index, |_, is_header| is_header)
+}
+
+// Best effort line of a dotted key path: the key itself if it can be found,
+// otherwise the closest enclosing table.
+pub(crate) fn locate_path(source: &str, path: &[&str], index: Option<usize>) -> Option<usize> {
+    (1..path.len() + 1).rev().filter_map(|n| {
+        find_key(source, &path[..n - 1], index, path[n - 1]).or_else(|| find_table(source, &path[..n], index))
+    }).next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+default_destinations = [\"ops\"]
+
+[notifiers.ops]
+type = \"discord\"
+webhook_url = \"${DISCORD_WEBHOOK}\"
+
+[[routes]]
+name = \"dba\"
+
+[[routes]]
+name = \"rtb\"
+flow = \"stop\"
+dimensions = { LoadBalancerName = \"rtb\" }
+";
+
+    #[test]
+    fn test_locate_path() {
+        assert_eq!(locate_path(SOURCE, &["default_destinations"], None), Some(1));
+        assert_eq!(locate_path(SOURCE, &["notifiers", "ops", "webhook_url"], None), Some(5));
+        assert_eq!(locate_path(SOURCE, &["notifiers", "ops", "missing"], None), Some(3));
+        assert_eq!(locate_path(SOURCE, &["routes", "name"], Some(1)), Some(11));
+        assert_eq!(locate_path(SOURCE, &["routes", "dimensions", "LoadBalancerName"], Some(1)), Some(13));
+        assert_eq!(locate_path(SOURCE, &["routes", "flow"], Some(0)), Some(7));
+        assert_eq!(locate_path(SOURCE, &["server", "port"], None), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let err = ConfigError {
+            line: Some(5),
+            key: Some("notifiers.ops.webhook_url".to_string()),
+            message: "environment variable `DISCORD_WEBHOOK` is not set".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "line 5, key `notifiers.ops.webhook_url`: environment variable `DISCORD_WEBHOOK` is not set"
+        );
+    }
+}
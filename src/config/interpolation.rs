@@ -0,0 +1,42 @@
+use std::env;
+
+// Replaces every `${NAME}` with the value of the `NAME` environment variable,
+// so that secrets don't have to be written in the configuration file.
+pub(crate) fn interpolate(text: &str) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("unterminated `${{` in `{}`", text)),
+        };
+        let name = &rest[start + 2..end];
+        let value = env::var(name).map_err(|_| format!("environment variable `{}` is not set", name))?;
+        interpolated.push_str(&rest[..start]);
+        interpolated.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interpolate() {
+        env::set_var("CLOUDWATCH_BOT_TEST_TOKEN", "s3cr3t");
+
+        assert_eq!(interpolate("no variable"), Ok("no variable".to_string()));
+        assert_eq!(
+            interpolate("https://hooks.example.org/${CLOUDWATCH_BOT_TEST_TOKEN}?a=${CLOUDWATCH_BOT_TEST_TOKEN}"),
+            Ok("https://hooks.example.org/s3cr3t?a=s3cr3t".to_string())
+        );
+        assert_eq!(
+            interpolate("${CLOUDWATCH_BOT_TEST_UNSET}"),
+            Err("environment variable `CLOUDWATCH_BOT_TEST_UNSET` is not set".to_string())
+        );
+        assert_eq!(interpolate("${OOPS"), Err("unterminated `${` in `${OOPS`".to_string()));
+    }
+}
@@ -0,0 +1,867 @@
+mod errors;
+mod interpolation;
+mod notifiers;
+mod reload;
+mod rotations;
+mod routes;
+mod templates;
+
+pub(crate) use self::errors::ConfigError;
+pub(crate) use self::reload::{SharedConfig, watch};
+
+use hyper::Uri;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use toml;
+
+use l10n::Localizer;
+use links::{LogsInsightsQueries, LogsInsightsQuery};
+use model::AlarmDetails;
+use notifiers::{PostStore, Posts};
+use routing::{Destinations, Router};
+use templates::Templates;
+use self::errors::{find_key, find_table};
+use self::interpolation::interpolate;
+use self::notifiers::NotifierConfig;
+use self::rotations::RotationConfig;
+use self::routes::RouteConfig;
+use self::templates::TemplateConfig;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    #[serde(default = "ServerConfig::default_address")]
+    pub address: String,
+    #[serde(default = "ServerConfig::default_port")]
+    pub port: u16,
+    #[serde(default = "ServerConfig::default_https_threads")]
+    pub https_threads: usize,
+    // Bearer token of the `/admin` endpoints, which are disabled without one.
+    pub admin_token: Option<String>,
+}
+
+impl ServerConfig {
+    fn default_address() -> String {
+        "localhost".to_string()
+    }
+
+    fn default_port() -> u16 {
+        8000
+    }
+
+    fn default_https_threads() -> usize {
+        4
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            address: ServerConfig::default_address(),
+            port: ServerConfig::default_port(),
+            https_threads: ServerConfig::default_https_threads(),
+            admin_token: None,
+        }
+    }
+}
+
+// Outbound deliveries, read at startup only.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct QueueConfig {
+    // Without a directory, pending deliveries and alarm groups are lost on
+    // restart.
+    pub directory: Option<PathBuf>,
+    #[serde(default = "QueueConfig::default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "QueueConfig::default_initial_backoff_secs")]
+    pub initial_backoff_secs: u64,
+    #[serde(default = "QueueConfig::default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+}
+
+impl QueueConfig {
+    fn default_max_attempts() -> u32 {
+        10
+    }
+
+    fn default_initial_backoff_secs() -> u64 {
+        5
+    }
+
+    fn default_max_backoff_secs() -> u64 {
+        3600
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> QueueConfig {
+        QueueConfig {
+            directory: None,
+            max_attempts: QueueConfig::default_max_attempts(),
+            initial_backoff_secs: QueueConfig::default_initial_backoff_secs(),
+            max_backoff_secs: QueueConfig::default_max_backoff_secs(),
+        }
+    }
+}
+
+// Redeliveries of a notification, recognized by SNS `MessageId`, are
+// acknowledged without notifying anyone again. Read at startup only.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct DedupConfig {
+    // Without a directory, the IDs seen before a restart are forgotten.
+    pub directory: Option<PathBuf>,
+    #[serde(default = "DedupConfig::default_ttl_secs")]
+    pub ttl_secs: u64,
+    // IDs kept in memory, the least recently seen go first.
+    #[serde(default = "DedupConfig::default_capacity")]
+    pub capacity: usize,
+}
+
+impl DedupConfig {
+    fn default_ttl_secs() -> u64 {
+        86_400
+    }
+
+    fn default_capacity() -> usize {
+        10_000
+    }
+}
+
+impl Default for DedupConfig {
+    fn default() -> DedupConfig {
+        DedupConfig {
+            directory: None,
+            ttl_secs: DedupConfig::default_ttl_secs(),
+            capacity: DedupConfig::default_capacity(),
+        }
+    }
+}
+
+// The latest state and the transitions of every alarm. Read at startup only.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct HistoryConfig {
+    // Without a directory, the history starts over on restart.
+    pub directory: Option<PathBuf>,
+    // Transitions kept per alarm, the oldest go first.
+    #[serde(default = "HistoryConfig::default_max_transitions")]
+    pub max_transitions: usize,
+}
+
+impl HistoryConfig {
+    fn default_max_transitions() -> usize {
+        1000
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig {
+            directory: None,
+            max_transitions: HistoryConfig::default_max_transitions(),
+        }
+    }
+}
+
+// The messages posted for the firing alarms, which their recovery edits. Read
+// at startup only.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct PostsConfig {
+    // Without a directory, a recovery after a restart is posted anew.
+    pub directory: Option<PathBuf>,
+}
+
+// Notifications from other topics or accounts are dropped, an empty list
+// allows everything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Allowlist {
+    #[serde(default)]
+    pub topic_arns: Vec<String>,
+    #[serde(default)]
+    pub accounts: Vec<String>,
+}
+
+impl Allowlist {
+    pub fn allows(&self, alarm: &AlarmDetails) -> bool {
+        (self.topic_arns.is_empty() || self.topic_arns.contains(&alarm.envelope.topic_arn)) &&
+            (self.accounts.is_empty() || self.accounts.contains(&alarm.account_id))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    default_destinations: Vec<String>,
+    #[serde(default)]
+    server: ServerConfig,
+    #[serde(default)]
+    queue: QueueConfig,
+    #[serde(default)]
+    dedup: DedupConfig,
+    #[serde(default)]
+    history: HistoryConfig,
+    #[serde(default)]
+    posts: PostsConfig,
+    #[serde(default)]
+    allowlist: Allowlist,
+    #[serde(default)]
+    notifiers: BTreeMap<String, NotifierConfig>,
+    #[serde(default)]
+    templates: BTreeMap<String, TemplateConfig>,
+    // Logs Insights queries linked from the alarms, by namespace.
+    #[serde(default)]
+    logs_insights: BTreeMap<String, LogsInsightsQuery>,
+    // On-call rotations which routes can mention.
+    #[serde(default)]
+    rotations: BTreeMap<String, RotationConfig>,
+    #[serde(default)]
+    routes: Vec<RouteConfig>,
+}
+
+#[derive(Default)]
+pub(crate) struct Config {
+    pub server: ServerConfig,
+    pub queue: QueueConfig,
+    pub dedup: DedupConfig,
+    pub history: HistoryConfig,
+    pub posts: PostsConfig,
+    // Outlives the configuration, which is parsed again on reload.
+    pub post_store: Arc<PostStore>,
+    pub allowlist: Allowlist,
+    pub destinations: Destinations,
+    // Language of the notices the bot writes itself, by destination.
+    pub localizers: HashMap<String, Localizer>,
+    pub templates: Templates,
+    pub logs_insights: LogsInsightsQueries,
+    pub router: Router,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let destinations: Vec<&String> = self.destinations.keys().collect();
+        fmt.debug_struct("Config")
+            .field("server", &self.server)
+            .field("queue", &self.queue)
+            .field("dedup", &self.dedup)
+            .field("history", &self.history)
+            .field("posts", &self.posts)
+            .field("allowlist", &self.allowlist)
+            .field("destinations", &destinations)
+            .field("templates", &self.templates)
+            .field("logs_insights", &self.logs_insights)
+            .field("router", &self.router)
+            .finish()
+    }
+}
+
+// A table of the configuration file, to report errors at the right key and
+// line while building its values.
+pub(crate) struct Section<'a> {
+    source: &'a str,
+    path: Vec<&'a str>,
+    // Position of the table for `[[arrays]]` of tables.
+    index: Option<usize>,
+}
+
+impl<'a> Section<'a> {
+    fn new(source: &'a str, path: Vec<&'a str>, index: Option<usize>) -> Section<'a> {
+        Section {
+            source: source,
+            path: path,
+            index: index,
+        }
+    }
+
+    // The table at `index` of an `[[array]]` of tables.
+    fn at(&self, index: usize) -> Section<'a> {
+        Section::new(self.source, self.path.clone(), Some(index))
+    }
+
+    pub fn error(&self, key: &str, message: String) -> ConfigError {
+        let mut full_key = self.path.join(".");
+        if let Some(index) = self.index {
+            full_key.push_str(&format!("[{}]", index));
+        }
+        if !full_key.is_empty() {
+            full_key.push('.');
+        }
+        full_key.push_str(key);
+
+        ConfigError {
+            line: find_key(self.source, &self.path, self.index, key)
+                .or_else(|| find_table(self.source, &self.path, self.index)),
+            key: Some(full_key),
+            message: message,
+        }
+    }
+
+    pub fn string(&self, key: &str, value: &str) -> Result<String, ConfigError> {
+        interpolate(value).map_err(|message| self.error(key, message))
+    }
+
+    pub fn optional(&self, key: &str, value: &Option<String>) -> Result<Option<String>, ConfigError> {
+        match *value {
+            Some(ref value) => self.string(key, value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn parse<T>(&self, key: &str, value: &str) -> Result<T, ConfigError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.string(key, value)?.parse().map_err(
+            |err: T::Err| self.error(key, err.to_string()),
+        )
+    }
+
+    // Hyper takes about anything for a URI, requests need a host to go to.
+    pub fn uri(&self, key: &str, value: &str) -> Result<Uri, ConfigError> {
+        let url = self.string(key, value)?;
+        let uri: Uri = url.parse().map_err(|err| self.error(key, format!("invalid URL `{}`: {}", url, err)))?;
+        match (uri.scheme(), uri.authority()) {
+            (Some("http"), Some(authority)) | (Some("https"), Some(authority)) if !authority.contains(' ') => Ok(uri),
+            _ => Err(self.error(key, format!("invalid URL `{}`: expected an absolute http(s) URL", url))),
+        }
+    }
+}
+
+// The TOML deserializer reports `routes.key` without saying which route is
+// wrong, find the first one which does not deserialize on its own.
+fn failing_route(source: &str) -> Option<usize> {
+    let document: Option<toml::Value> = source.parse().ok();
+    document.and_then(|document| {
+        document.get("routes").and_then(|routes| routes.as_array()).and_then(|routes| {
+            routes.iter().position(|route| route.clone().try_into::<RouteConfig>().is_err())
+        })
+    })
+}
+
+impl Config {
+    pub fn parse(source: &str, post_store: &Arc<PostStore>) -> Result<Config, ConfigError> {
+        let mut file: ConfigFile = toml::from_str(source).map_err(|err| {
+            let mut config_err = ConfigError::from_toml(source, &err, None);
+            if config_err.key.as_ref().map_or(false, |key| key.starts_with("routes.")) {
+                config_err = ConfigError::from_toml(source, &err, failing_route(source));
+            }
+            config_err
+        })?;
+
+        let server = Section::new(source, vec!["server"], None);
+        file.server.admin_token = server.optional("admin_token", &file.server.admin_token)?;
+        if file.server.https_threads == 0 {
+            return Err(server.error("https_threads", "at least one thread is required".to_string()));
+        }
+
+        let queue = Section::new(source, vec!["queue"], None);
+        if file.queue.max_attempts == 0 {
+            return Err(queue.error("max_attempts", "at least one attempt is required".to_string()));
+        }
+        if file.queue.initial_backoff_secs == 0 || file.queue.initial_backoff_secs > file.queue.max_backoff_secs {
+            return Err(queue.error(
+                "initial_backoff_secs",
+                "must be positive and at most `max_backoff_secs`".to_string(),
+            ));
+        }
+
+        let dedup = Section::new(source, vec!["dedup"], None);
+        if file.dedup.ttl_secs == 0 {
+            return Err(dedup.error("ttl_secs", "must be positive".to_string()));
+        }
+        if file.dedup.capacity == 0 {
+            return Err(dedup.error("capacity", "at least one ID must be kept".to_string()));
+        }
+
+        if file.history.max_transitions == 0 {
+            let history = Section::new(source, vec!["history"], None);
+            return Err(history.error("max_transitions", "at least one transition must be kept".to_string()));
+        }
+
+        let mut destinations = Destinations::new();
+        let mut localizers = HashMap::new();
+        for (name, notifier) in &file.notifiers {
+            let section = Section::new(source, vec!["notifiers", name], None);
+            destinations.insert(name.clone(), notifier.build(&section, Posts::new(name, post_store.clone()))?);
+            localizers.insert(name.clone(), notifier.localizer(&section)?);
+        }
+
+        let root = Section::new(source, vec![], None);
+        for destination in &file.default_destinations {
+            if !destinations.contains_key(destination) {
+                return Err(root.error(
+                    "default_destinations",
+                    format!("unknown notifier `{}`", destination),
+                ));
+            }
+        }
+
+        let mut templates = Templates::new();
+        let mut destination_templates = HashMap::new();
+        for (name, template) in &file.templates {
+            let section = Section::new(source, vec!["templates", name], None);
+            let transitions = Section::new(source, vec!["templates", name, "transitions"], None);
+            templates.insert(name.clone(), template.build(&section, &transitions)?);
+
+            for destination in &template.destinations {
+                if !destinations.contains_key(destination) {
+                    return Err(section.error("destinations", format!("unknown notifier `{}`", destination)));
+                }
+                if let Some(other) = destination_templates.insert(destination.clone(), name.clone()) {
+                    return Err(section.error(
+                        "destinations",
+                        format!("notifier `{}` already uses template `{}`", destination, other),
+                    ));
+                }
+            }
+        }
+
+        for (namespace, query) in &file.logs_insights {
+            if query.log_groups.is_empty() {
+                let section = Section::new(source, vec!["logs_insights", namespace], None);
+                return Err(section.error("log_groups", "at least one log group is required".to_string()));
+            }
+        }
+
+        let mut rotations = HashMap::new();
+        for (name, rotation) in &file.rotations {
+            let section = Section::new(source, vec!["rotations", name], None);
+            let overrides = Section::new(source, vec!["rotations", name, "overrides"], None);
+            rotations.insert(name.clone(), rotation.build(&section, &overrides)?);
+        }
+
+        let mut rules = Vec::new();
+        for (index, route) in file.routes.iter().enumerate() {
+            let section = Section::new(source, vec!["routes"], Some(index));
+            rules.push(route.build(
+                &section,
+                &destinations,
+                &templates,
+                &rotations,
+                file.history.max_transitions,
+            )?);
+        }
+
+        Ok(Config {
+            server: file.server,
+            queue: file.queue,
+            dedup: file.dedup,
+            history: file.history,
+            posts: file.posts,
+            post_store: post_store.clone(),
+            allowlist: file.allowlist,
+            destinations: destinations,
+            localizers: localizers,
+            templates: templates,
+            logs_insights: file.logs_insights.into_iter().collect(),
+            router: Router {
+                rules: rules,
+                default_destinations: file.default_destinations,
+                destination_templates: destination_templates,
+                rotations: rotations,
+            },
+        })
+    }
+
+    pub fn load(path: &Path, post_store: &Arc<PostStore>) -> Result<Config, ConfigError> {
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .map_err(|err| ConfigError::new(format!("cannot read {}: {}", path.display(), err)))?;
+        Config::parse(&source, post_store)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use oncall::Mention;
+    use std::env;
+
+    const CONFIG: &str = "\
+default_destinations = [\"ops\"]
+
+[server]
+address = \"0.0.0.0\"
+port = 8080
+
+[allowlist]
+accounts = [\"097958131044\"]
+
+[notifiers.ops]
+type = \"discord\"
+webhook_url = \"https://discord.com/api/webhooks/${TOKEN}\"
+
+[notifiers.rtb]
+type = \"json_lines\"
+
+[[routes]]
+name = \"dba\"
+namespaces = [\"AWS/RDS\"]
+destinations = [\"ops\"]
+flow = \"stop\"
+
+[[routes]]
+name = \"rtb\"
+alarm_name = \"[[]RTB-US]*\"
+new_states = [\"ALARM\", \"INSUFFICIENT_DATA\"]
+destinations = [\"rtb\", \"ops\"]
+template = \"short\"
+mentions = { ALARM = [\"user:U1\", \"rotation:rtb\"] }
+flapping = { transitions = 6, window_secs = 3600 }
+group = { by = \"dimension:LoadBalancerName\", window_secs = 30 }
+
+[templates.short]
+text = \"{{state_emoji new_state}} {{name}}\"
+destinations = [\"ops\"]
+
+[templates.short.transitions]
+\"ALARM->OK\" = \"{{name}} recovered after {{duration trigger.period}}\"
+
+[logs_insights.\"AWS/ELB\"]
+log_groups = [\"/aws/elb/rtb\"]
+query = \"fields @message | limit 20\"
+
+[rotations.rtb]
+members = [\"alice\", \"bob\"]
+start = \"2024-01-01T09:00:00Z\"
+
+[[rotations.rtb.overrides]]
+member = \"carol\"
+start = \"2024-01-10T00:00:00Z\"
+end = \"2024-01-11T00:00:00Z\"
+";
+
+    // Tests run in parallel, each one sets a variable of its own.
+    fn config(test: &str) -> String {
+        let name = format!("CLOUDWATCH_BOT_CONFIG_TEST_{}", test.to_uppercase());
+        env::set_var(&name, "123/abc");
+        CONFIG.replace("${TOKEN}", &format!("${{{}}}", name))
+    }
+
+    fn parse(source: &str) -> Result<Config, ConfigError> {
+        Config::parse(source, &Arc::new(PostStore::default()))
+    }
+
+    // The error without its line, which must be the first one of `source`
+    // starting with `at`, so that fixtures can change without renumbering.
+    fn error(source: &str, at: &str) -> String {
+        let mut err = parse(source).err().expect("Config should be invalid");
+        let line = source.lines().position(|line| line.starts_with(at)).map(|index| index + 1);
+        assert_eq!(err.line, line, "{}", err);
+        err.line = None;
+        err.to_string()
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = parse(&config("parse")).expect("Config should be valid");
+
+        assert_eq!(config.server.address, "0.0.0.0");
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.server.https_threads, 4);
+        assert_eq!(config.allowlist.allows(&alarm_details("ALARM", "OK")), true);
+        assert_eq!(config.destinations.len(), 2);
+        assert_eq!(config.router.rules.len(), 2);
+        assert_eq!(
+            config.router.route(&alarm_details("ALARM", "OK")).destinations,
+            vec!["rtb", "ops"]
+        );
+        assert_eq!(config.router.route(&alarm_details("OK", "ALARM")).destinations, vec!["ops"]);
+        assert_eq!(config.router.route(&alarm_details("OK", "ALARM")).templates["ops"], "short");
+        assert_eq!(
+            config.templates["short"].render(&alarm_details("OK", "ALARM"), &config.logs_insights),
+            "[RTB-US] UnHealthyHostCount recovered after 1m"
+        );
+        assert_eq!(config.logs_insights["AWS/ELB"].log_groups, vec!["/aws/elb/rtb"]);
+        assert_eq!(
+            config.router.route(&alarm_details("ALARM", "OK")).flapping.map(|threshold| threshold.transitions),
+            Some(6)
+        );
+        assert_eq!(
+            config.router.route(&alarm_details("ALARM", "OK")).grouping.map(|(_, grouping)| grouping.key.to_string()),
+            Some("dimension:LoadBalancerName".to_string())
+        );
+
+        let at = |time: &str| config.router.route_at(&alarm_details("ALARM", "OK"), time.parse().unwrap());
+        let mentions = vec![Mention::User("U1".to_string()), Mention::User("bob".to_string())];
+        assert_eq!(at("2024-01-09T12:00:00Z").mentions["ops"], mentions);
+        let mentions = vec![Mention::User("U1".to_string()), Mention::User("carol".to_string())];
+        assert_eq!(at("2024-01-10T12:00:00Z").mentions["ops"], mentions);
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = parse("").expect("Config should be valid");
+
+        assert_eq!(config.server.address, "localhost");
+        assert_eq!(config.server.port, 8000);
+        assert_eq!(config.server.admin_token, None);
+        assert_eq!(config.queue.directory, None);
+        assert_eq!(config.queue.max_attempts, 10);
+        assert_eq!(config.destinations.is_empty(), true);
+        assert_eq!(config.router.route(&alarm_details("ALARM", "OK")).destinations.is_empty(), true);
+    }
+
+    #[test]
+    fn test_syntax_error() {
+        assert_eq!(error("[server]\nport = \n", "port"), "expected a value, found a newline");
+    }
+
+    #[test]
+    fn test_type_errors() {
+        assert_eq!(
+            error("[server]\naddress = \"0.0.0.0\"\nport = \"8080\"\n", "port"),
+            "key `server.port`: invalid type: string \"8080\", expected u16"
+        );
+        assert_eq!(
+            error("[notifiers.ops]\ntype = \"mattermost\"\n", "type"),
+            "key `notifiers.ops.type`: unknown variant `mattermost`, expected one of `teams`, `discord`, \
+             `google_chat`, `slack`, `telegram`, `matrix`, `zulip`, `irc`, `email`, `pagerduty`, `opsgenie`, \
+             `webhook`, `json_lines`"
+        );
+        assert_eq!(
+            error("[[routes]]\nname = \"a\"\ndestinations = []\n\n[[routes]]\nname = \"b\"\ndestinations = []\nflow = 1\n", "flow"),
+            "key `routes.flow`: invalid type: integer `1`, expected a string"
+        );
+    }
+
+    #[test]
+    fn test_locale_errors() {
+        assert_eq!(
+            error("[notifiers.ops]\ntype = \"discord\"\nwebhook_url = \"https://discord.com/api/webhooks/1/x\"\nlocale = \"de\"\n", "locale")
+                .starts_with("key `notifiers.ops.locale`: 'de' does not match allowed enum values"),
+            true
+        );
+        assert_eq!(
+            error(
+                "[notifiers.ops]\ntype = \"email\"\nhost = \"smtp.example.org\"\nfrom = \"bot@example.org\"\n\
+                 recipients = []\ntime_zone = \"Europe/Pariss\"\n",
+                "time_zone",
+            ),
+            "key `notifiers.ops.time_zone`: 'Europe/Pariss' is not a valid timezone"
+        );
+        assert_eq!(
+            parse("[notifiers.ops]\ntype = \"zulip\"\nsite_url = \"https://x.zulipchat.com\"\nbot_email = \"b\"\n\
+                   api_key = \"k\"\nstream = \"ops\"\nlocale = \"ja\"\ntime_zone = \"Asia/Tokyo\"\n")
+                .is_ok(),
+            true
+        );
+        assert_eq!(
+            parse("[notifiers.ops]\ntype = \"slack\"\ntoken = \"xoxb-1\"\nchannel = \"C0123456789\"\nlocale = \"fr\"\n")
+                .is_ok(),
+            true
+        );
+    }
+
+    #[test]
+    fn test_url_errors() {
+        assert_eq!(
+            error("[notifiers.ops]\ntype = \"telegram\"\nbot_token = \"123:a b\"\nchats = []\n", "bot_token"),
+            "key `notifiers.ops.bot_token`: must be URL safe"
+        );
+        assert_eq!(
+            error(
+                "[notifiers.ops]\ntype = \"matrix\"\nhomeserver_url = \"matrix.example.org\"\naccess_token = \"t\"\n\
+                 room_id = \"!ops:example.org\"\n",
+                "homeserver_url",
+            ),
+            "key `notifiers.ops.homeserver_url`: invalid URL `matrix.example.org`: expected an absolute http(s) URL"
+        );
+        assert_eq!(
+            error("[notifiers.ops]\ntype = \"zulip\"\nsite_url = \"\"\nbot_email = \"b\"\napi_key = \"k\"\nstream = \"s\"\n", "site_url")
+                .starts_with("key `notifiers.ops.site_url`: invalid URL ``"),
+            true
+        );
+        assert_eq!(
+            error("[notifiers.ops]\ntype = \"opsgenie\"\napi_key = \"k\"\napi_url = \"api.opsgenie.com\"\n", "api_url"),
+            "key `notifiers.ops.api_url`: invalid URL `api.opsgenie.com`: expected an absolute http(s) URL"
+        );
+    }
+
+    #[test]
+    fn test_missing_environment_variable() {
+        assert_eq!(
+            error("[notifiers.ops]\ntype = \"discord\"\nwebhook_url = \"${CLOUDWATCH_BOT_CONFIG_TEST_UNSET}\"\n", "webhook_url"),
+            "key `notifiers.ops.webhook_url`: environment variable `CLOUDWATCH_BOT_CONFIG_TEST_UNSET` is not set"
+        );
+    }
+
+    #[test]
+    fn test_unknown_destinations() {
+        assert_eq!(
+            error("default_destinations = [\"nope\"]\n", "default_destinations"),
+            "key `default_destinations`: unknown notifier `nope`"
+        );
+        assert_eq!(
+            error(
+                &config("unknown_destinations").replace("destinations = [\"rtb\", \"ops\"]", "destinations = [\"rtb\", \"dba\"]"),
+                "destinations = [\"rtb\", \"dba\"]",
+            ),
+            "key `routes[1].destinations`: unknown notifier `dba`"
+        );
+    }
+
+    #[test]
+    fn test_template_errors() {
+        assert_eq!(
+            error("[templates.short]\ntext = \"{{#if arn}}\"\n", "text"),
+            "key `templates.short.text`: invalid template: unclosed `{{#if}}`"
+        );
+        assert_eq!(
+            error("[templates.short]\ntext = \"\"\n\n[templates.short.transitions]\n\"OK->ALARMS\" = \"\"\n", "\"OK->ALARMS\"")
+                .starts_with(
+                    "key `templates.short.transitions.OK->ALARMS`: invalid transition `OK->ALARMS`: \
+                     'ALARMS' does not match allowed enum values",
+                ),
+            true
+        );
+        assert_eq!(
+            error("[[routes]]\nname = \"a\"\ndestinations = []\ntemplate = \"nope\"\n", "template"),
+            "key `routes[0].template`: unknown template `nope`"
+        );
+    }
+
+    #[test]
+    fn test_mention_errors() {
+        let route = "[[routes]]\nname = \"a\"\ndestinations = []\n";
+        assert_eq!(
+            error(&format!("{}mentions = {{ OK = [\"here\"] }}\n", route), "mentions"),
+            "key `routes[0].mentions`: OK transitions never mention anyone"
+        );
+        assert_eq!(
+            error(&format!("{}mentions = {{ ALARM = [\"alice\"] }}\n", route), "mentions"),
+            "key `routes[0].mentions`: invalid mention `alice`, expected `user:<id>`, `group:<id>`, \
+             `here` or `rotation:<name>`"
+        );
+        assert_eq!(
+            error(&format!("{}mentions = {{ ALARM = [\"rotation:rtb\"] }}\n", route), "mentions"),
+            "key `routes[0].mentions`: unknown rotation `rtb`"
+        );
+    }
+
+    #[test]
+    fn test_flapping_errors() {
+        let route = "[[routes]]\nname = \"a\"\ndestinations = []\n";
+        assert_eq!(
+            error(&format!("{}flapping = {{ transitions = 1, window_secs = 60 }}\n", route), "flapping"),
+            "key `routes[0].flapping`: at least two transitions are needed to flap"
+        );
+        assert_eq!(
+            error(&format!("{}flapping = {{ transitions = 5, window_secs = 0 }}\n", route), "flapping"),
+            "key `routes[0].flapping`: the window must last at least one second"
+        );
+        assert_eq!(
+            error(
+                &format!("[history]\nmax_transitions = 5\n\n{}flapping = {{ transitions = 6, window_secs = 60 }}\n", route),
+                "flapping",
+            ),
+            "key `routes[0].flapping`: more transitions than the history keeps (5)"
+        );
+    }
+
+    #[test]
+    fn test_group_errors() {
+        let route = "[[routes]]\nname = \"a\"\ndestinations = []\n";
+        assert_eq!(
+            error(&format!("{}group = {{ by = \"LoadBalancerName\", window_secs = 30 }}\n", route), "group"),
+            "key `routes[0].group`: invalid group key `LoadBalancerName`, expected `namespace`, `account`, \
+             `name_prefix` or `dimension:<name>`"
+        );
+        assert_eq!(
+            error(&format!("{}group = {{ by = \"namespace\", window_secs = 0 }}\n", route), "group"),
+            "key `routes[0].group`: the window must last at least one second"
+        );
+    }
+
+    #[test]
+    fn test_rotation_errors() {
+        assert_eq!(
+            error("[rotations.rtb]\nmembers = []\nstart = \"2024-01-01T09:00:00Z\"\n", "members"),
+            "key `rotations.rtb.members`: at least one member is required"
+        );
+        assert_eq!(
+            error("[rotations.rtb]\nmembers = [\"alice\"]\nstart = \"2024-01-01\"\n", "start")
+                .starts_with("key `rotations.rtb.start`: invalid timestamp `2024-01-01`: "),
+            true
+        );
+        assert_eq!(
+            error("[rotations.rtb]\nmembers = [\"alice\"]\nstart = \"2024-01-01T09:00:00Z\"\nshift_days = 0\n", "shift_days"),
+            "key `rotations.rtb.shift_days`: shifts must last at least one day"
+        );
+        assert_eq!(
+            error(
+                "[rotations.rtb]\nmembers = [\"alice\"]\nstart = \"2024-01-01T09:00:00Z\"\n\n\
+                 [[rotations.rtb.overrides]]\nmember = \"bob\"\nstart = \"2024-01-02T00:00:00Z\"\n\
+                 end = \"2024-01-02T00:00:00Z\"\n",
+                "end",
+            ),
+            "key `rotations.rtb.overrides[0].end`: an override must end after it starts"
+        );
+    }
+
+    #[test]
+    fn test_server_errors() {
+        assert_eq!(
+            error("[server]\nhttps_threads = 0\n", "https_threads"),
+            "key `server.https_threads`: at least one thread is required"
+        );
+    }
+
+    #[test]
+    fn test_queue_errors() {
+        assert_eq!(
+            error("[queue]\nmax_attempts = 0\n", "max_attempts"),
+            "key `queue.max_attempts`: at least one attempt is required"
+        );
+        assert_eq!(
+            error("[queue]\ninitial_backoff_secs = 60\nmax_backoff_secs = 30\n", "initial_backoff_secs"),
+            "key `queue.initial_backoff_secs`: must be positive and at most `max_backoff_secs`"
+        );
+    }
+
+    #[test]
+    fn test_dedup_errors() {
+        assert_eq!(error("[dedup]\nttl_secs = 0\n", "ttl_secs"), "key `dedup.ttl_secs`: must be positive");
+        assert_eq!(error("[dedup]\ncapacity = 0\n", "capacity"), "key `dedup.capacity`: at least one ID must be kept");
+    }
+
+    #[test]
+    fn test_history_errors() {
+        assert_eq!(
+            error("[history]\nmax_transitions = 0\n", "max_transitions"),
+            "key `history.max_transitions`: at least one transition must be kept"
+        );
+    }
+
+    #[test]
+    fn test_logs_insights_errors() {
+        assert_eq!(
+            error("[logs_insights.\"AWS/ELB\"]\nlog_groups = []\nquery = \"fields @message\"\n", "log_groups"),
+            "key `logs_insights.AWS/ELB.log_groups`: at least one log group is required"
+        );
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let alarm = alarm_details("ALARM", "OK");
+        let allowlist = Allowlist {
+            topic_arns: vec!["arn:aws:sns:us-east-1:097958131044:jabber-all".to_string()],
+            accounts: vec![],
+        };
+
+        assert_eq!(Allowlist::default().allows(&alarm), true);
+        assert_eq!(allowlist.allows(&alarm), false);
+    }
+}
@@ -0,0 +1,365 @@
+use serde_json;
+use std::collections::BTreeMap;
+use std::path::Path;
+use toml;
+
+use l10n::Localizer;
+use notifiers::{DiscordNotifier, EmailNotifier, GoogleChatNotifier, IrcConfig, IrcNotifier, JsonLinesNotifier,
+                MatrixNotifier, Notifier, OPSGENIE_API_URL, OpsgenieNotifier, PAGERDUTY_EVENTS_URL, PagerDutyNotifier,
+                Posts, SLACK_API_URL, SlackNotifier, SmtpConfig, SmtpSecurity, TeamsNotifier, TelegramChat,
+                TelegramNotifier, WebhookNotifier, ZulipNotifier, telegram_send_message_url};
+use super::{ConfigError, Section};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct TelegramChatConfig {
+    chat_id: String,
+    thread_id: Option<i64>,
+}
+
+// Chat notifiers also take a `locale` ("en", "fr", "ja") and a `time_zone`
+// ("Europe/Paris"), English and UTC by default.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum NotifierConfig {
+    Teams {
+        webhook_url: String,
+        runbook_url: Option<String>,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    Discord {
+        webhook_url: String,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    GoogleChat {
+        webhook_url: String,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    // `channel` is an ID, "C0123456789".
+    Slack {
+        token: String,
+        channel: String,
+        api_url: Option<String>,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    Telegram {
+        bot_token: String,
+        chats: Vec<TelegramChatConfig>,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    Matrix {
+        homeserver_url: String,
+        access_token: String,
+        room_id: String,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    Zulip {
+        site_url: String,
+        bot_email: String,
+        api_key: String,
+        stream: String,
+        topic: Option<String>,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    Irc {
+        host: String,
+        port: Option<u16>,
+        tls: Option<bool>,
+        nickname: String,
+        realname: Option<String>,
+        nickserv_password: Option<String>,
+        channels: Vec<String>,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    Email {
+        host: String,
+        port: Option<u16>,
+        security: Option<String>,
+        username: Option<String>,
+        password: Option<String>,
+        from: String,
+        recipients: Vec<String>,
+        locale: Option<String>,
+        time_zone: Option<String>,
+    },
+    #[serde(rename = "pagerduty")]
+    PagerDuty {
+        routing_key: String,
+        events_url: Option<String>,
+        severity: Option<String>,
+        insufficient_data_severity: Option<String>,
+        insufficient_data: Option<String>,
+    },
+    Opsgenie {
+        api_key: String,
+        api_url: Option<String>,
+        insufficient_data: Option<String>,
+    },
+    Webhook {
+        url: String,
+        template: Option<toml::Value>,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        secret: Option<String>,
+    },
+    // Writes to stdout without a path.
+    JsonLines { path: Option<String> },
+}
+
+fn localizer(section: &Section, locale: &Option<String>, time_zone: &Option<String>) -> Result<Localizer, ConfigError> {
+    let default = Localizer::default();
+    Ok(Localizer {
+        locale: match *locale {
+            Some(ref locale) => section.parse("locale", locale)?,
+            None => default.locale,
+        },
+        time_zone: match *time_zone {
+            Some(ref time_zone) => section.parse("time_zone", time_zone)?,
+            None => default.time_zone,
+        },
+    })
+}
+
+impl NotifierConfig {
+    // That of the messages the notifier formats, English for the others.
+    pub fn localizer(&self, section: &Section) -> Result<Localizer, ConfigError> {
+        match *self {
+            NotifierConfig::Teams { ref locale, ref time_zone, .. } |
+            NotifierConfig::Discord { ref locale, ref time_zone, .. } |
+            NotifierConfig::GoogleChat { ref locale, ref time_zone, .. } |
+            NotifierConfig::Slack { ref locale, ref time_zone, .. } |
+            NotifierConfig::Telegram { ref locale, ref time_zone, .. } |
+            NotifierConfig::Matrix { ref locale, ref time_zone, .. } |
+            NotifierConfig::Zulip { ref locale, ref time_zone, .. } |
+            NotifierConfig::Irc { ref locale, ref time_zone, .. } |
+            NotifierConfig::Email { ref locale, ref time_zone, .. } => localizer(section, locale, time_zone),
+            _ => Ok(Localizer::default()),
+        }
+    }
+
+    // `posts` keeps the messages which recoveries edit, for the notifiers
+    // which can.
+    pub fn build(&self, section: &Section, posts: Posts) -> Result<Box<Notifier + Send + Sync>, ConfigError> {
+        let notifier: Box<Notifier + Send + Sync> = match *self {
+            NotifierConfig::Teams {
+                ref webhook_url,
+                ref runbook_url,
+                ref locale,
+                ref time_zone,
+            } => Box::new(TeamsNotifier {
+                webhook_url: section.uri("webhook_url", webhook_url)?,
+                runbook_url: section.optional("runbook_url", runbook_url)?,
+                l10n: localizer(section, locale, time_zone)?,
+            }),
+            NotifierConfig::Discord {
+                ref webhook_url,
+                ref locale,
+                ref time_zone,
+            } => Box::new(DiscordNotifier::new(
+                section.uri("webhook_url", webhook_url)?,
+                localizer(section, locale, time_zone)?,
+                posts,
+            )),
+            NotifierConfig::GoogleChat {
+                ref webhook_url,
+                ref locale,
+                ref time_zone,
+            } => Box::new(GoogleChatNotifier {
+                webhook_url: section.uri("webhook_url", webhook_url)?,
+                l10n: localizer(section, locale, time_zone)?,
+            }),
+            NotifierConfig::Slack {
+                ref token,
+                ref channel,
+                ref api_url,
+                ref locale,
+                ref time_zone,
+            } => Box::new(SlackNotifier {
+                api_url: section.uri("api_url", api_url.as_ref().map_or(SLACK_API_URL, |u| u))?,
+                token: section.string("token", token)?,
+                channel: section.string("channel", channel)?,
+                l10n: localizer(section, locale, time_zone)?,
+                posts: posts,
+            }),
+            NotifierConfig::Telegram {
+                ref bot_token,
+                ref chats,
+                ref locale,
+                ref time_zone,
+            } => {
+                let mut telegram_chats = Vec::new();
+                for chat in chats {
+                    telegram_chats.push(TelegramChat {
+                        chat_id: section.string("chats", &chat.chat_id)?,
+                        thread_id: chat.thread_id,
+                    });
+                }
+                let bot_token = section.string("bot_token", bot_token)?;
+                if telegram_send_message_url(&bot_token).is_err() {
+                    return Err(section.error("bot_token", "must be URL safe".to_string()));
+                }
+                Box::new(TelegramNotifier {
+                    bot_token: bot_token,
+                    chats: telegram_chats,
+                    l10n: localizer(section, locale, time_zone)?,
+                    posts: posts,
+                })
+            }
+            NotifierConfig::Matrix {
+                ref homeserver_url,
+                ref access_token,
+                ref room_id,
+                ref locale,
+                ref time_zone,
+            } => Box::new(MatrixNotifier {
+                homeserver_url: section.uri("homeserver_url", homeserver_url)?,
+                access_token: section.string("access_token", access_token)?,
+                room_id: section.string("room_id", room_id)?,
+                l10n: localizer(section, locale, time_zone)?,
+                posts: posts,
+            }),
+            NotifierConfig::Zulip {
+                ref site_url,
+                ref bot_email,
+                ref api_key,
+                ref stream,
+                ref topic,
+                ref locale,
+                ref time_zone,
+            } => Box::new(ZulipNotifier {
+                site_url: section.uri("site_url", site_url)?,
+                bot_email: section.string("bot_email", bot_email)?,
+                api_key: section.string("api_key", api_key)?,
+                stream: section.string("stream", stream)?,
+                topic: section.optional("topic", topic)?,
+                l10n: localizer(section, locale, time_zone)?,
+                posts: posts,
+            }),
+            NotifierConfig::Irc {
+                ref host,
+                port,
+                tls,
+                ref nickname,
+                ref realname,
+                ref nickserv_password,
+                ref channels,
+                ref locale,
+                ref time_zone,
+            } => {
+                let tls = tls.unwrap_or(true);
+                let nickname = section.string("nickname", nickname)?;
+                let irc = IrcConfig {
+                    host: section.string("host", host)?,
+                    port: port.unwrap_or(if tls { 6697 } else { 6667 }),
+                    tls: tls,
+                    realname: section.optional("realname", realname)?.unwrap_or_else(|| nickname.clone()),
+                    nickname: nickname,
+                    nickserv_password: section.optional("nickserv_password", nickserv_password)?,
+                    channels: channels.clone(),
+                };
+                Box::new(IrcNotifier::new(irc, localizer(section, locale, time_zone)?))
+            }
+            NotifierConfig::Email {
+                ref host,
+                port,
+                ref security,
+                ref username,
+                ref password,
+                ref from,
+                ref recipients,
+                ref locale,
+                ref time_zone,
+            } => {
+                let security = match *security {
+                    Some(ref security) => section.parse("security", security)?,
+                    None => SmtpSecurity::StartTls,
+                };
+                let default_port = match security {
+                    SmtpSecurity::None => 25,
+                    SmtpSecurity::StartTls => 587,
+                    SmtpSecurity::Tls => 465,
+                };
+                let smtp = SmtpConfig {
+                    host: section.string("host", host)?,
+                    port: port.unwrap_or(default_port),
+                    security: security,
+                    username: section.optional("username", username)?,
+                    password: section.optional("password", password)?,
+                    from: section.string("from", from)?,
+                };
+                Box::new(EmailNotifier::new(smtp, recipients.clone(), localizer(section, locale, time_zone)?, posts))
+            }
+            NotifierConfig::PagerDuty {
+                ref routing_key,
+                ref events_url,
+                ref severity,
+                ref insufficient_data_severity,
+                ref insufficient_data,
+            } => Box::new(PagerDutyNotifier {
+                events_url: section.uri("events_url", events_url.as_ref().map_or(PAGERDUTY_EVENTS_URL, |u| u))?,
+                routing_key: section.string("routing_key", routing_key)?,
+                alarm_severity: section.parse("severity", severity.as_ref().map_or("critical", |s| s))?,
+                insufficient_data_severity: section.parse(
+                    "insufficient_data_severity",
+                    insufficient_data_severity.as_ref().map_or("warning", |s| s),
+                )?,
+                insufficient_data: section.parse(
+                    "insufficient_data",
+                    insufficient_data.as_ref().map_or("ignore", |s| s),
+                )?,
+            }),
+            NotifierConfig::Opsgenie {
+                ref api_key,
+                ref api_url,
+                ref insufficient_data,
+            } => Box::new(OpsgenieNotifier {
+                api_url: section.uri("api_url", api_url.as_ref().map_or(OPSGENIE_API_URL, |u| u))?,
+                api_key: section.string("api_key", api_key)?,
+                insufficient_data: section.parse(
+                    "insufficient_data",
+                    insufficient_data.as_ref().map_or("ignore", |s| s),
+                )?,
+            }),
+            NotifierConfig::Webhook {
+                ref url,
+                ref template,
+                ref headers,
+                ref secret,
+            } => {
+                let template = match *template {
+                    Some(ref template) => Some(serde_json::to_value(template).map_err(
+                        |err| section.error("template", err.to_string()),
+                    )?),
+                    None => None,
+                };
+                let mut webhook_headers = Vec::new();
+                for (name, value) in headers {
+                    webhook_headers.push((name.clone(), section.string("headers", value)?));
+                }
+                Box::new(WebhookNotifier {
+                    url: section.uri("url", url)?,
+                    template: template,
+                    headers: webhook_headers,
+                    secret: section.optional("secret", secret)?,
+                })
+            }
+            NotifierConfig::JsonLines { ref path } => match *path {
+                Some(ref path) => {
+                    let path = section.string("path", path)?;
+                    Box::new(JsonLinesNotifier::file(Path::new(&path)).map_err(|err| {
+                        section.error("path", format!("cannot open {}: {}", path, err))
+                    })?)
+                }
+                None => Box::new(JsonLinesNotifier::stdout()),
+            },
+        };
+        Ok(notifier)
+    }
+}
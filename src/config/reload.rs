@@ -0,0 +1,133 @@
+use libc;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{ATOMIC_BOOL_INIT, AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::{Config, ConfigError};
+
+const POLL_INTERVAL_SECS: u64 = 1;
+
+static SIGHUP_RECEIVED: AtomicBool = ATOMIC_BOOL_INIT;
+
+// The configuration in use, replaced as a whole on reload. Deliveries work on
+// the snapshot returned by `current`, so the ones in flight during a reload
+// finish with the notifiers and routes they started with.
+#[derive(Debug)]
+pub(crate) struct SharedConfig(RwLock<Arc<Config>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig(RwLock::new(Arc::new(config)))
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        self.0.read().expect("Config lock poisoned").clone()
+    }
+
+    pub fn replace(&self, config: Config) {
+        *self.0.write().expect("Config lock poisoned") = Arc::new(config);
+    }
+
+    // The new file is fully validated before the swap, an invalid one leaves
+    // the current config untouched. Its notifiers keep the messages posted by
+    // the previous ones.
+    pub fn reload(&self, path: &Path) -> Result<(), ConfigError> {
+        let config = Config::load(path, &self.current().post_store)?;
+        self.replace(config);
+        Ok(())
+    }
+}
+
+extern "C" fn on_sighup(_: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Reloads the configuration on SIGHUP or when the file changes. The server
+// address, port and threads are only read at startup.
+pub(crate) fn watch(path: PathBuf, config: Arc<SharedConfig>) -> thread::JoinHandle<()> {
+    unsafe {
+        libc::signal(libc::SIGHUP, on_sighup as libc::sighandler_t);
+    }
+
+    thread::spawn(move || {
+        let mut last_modified = modified(&path);
+        loop {
+            thread::sleep(Duration::from_secs(POLL_INTERVAL_SECS));
+
+            let current_modified = modified(&path);
+            let sighup = SIGHUP_RECEIVED.swap(false, Ordering::SeqCst);
+            if !sighup && current_modified == last_modified {
+                continue;
+            }
+            last_modified = current_modified;
+
+            let _ = match config.reload(&path) {
+                Ok(()) => writeln!(io::stderr(), "{}: configuration reloaded", path.display()),
+                Err(err) => writeln!(
+                    io::stderr(),
+                    "{}: {}, keeping the previous configuration",
+                    path.display(),
+                    err
+                ),
+            };
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::{PostStore, Posts};
+    use std::env;
+    use std::fs::File;
+
+    fn write_config(path: &Path, source: &str) {
+        File::create(path).and_then(|mut file| file.write_all(source.as_bytes())).unwrap();
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = env::temp_dir().join("cloudwatch_bot_test_reload.toml");
+        write_config(&path, "[notifiers.ops]\ntype = \"json_lines\"\n");
+        let shared = SharedConfig::new(Config::load(&path, &Arc::new(PostStore::default())).unwrap());
+        let in_flight = shared.current();
+
+        write_config(&path, "default_destinations = [\"ops\"]\n\n[notifiers.ops]\ntype = \"json_lines\"\n");
+        shared.reload(&path).unwrap();
+        assert_eq!(shared.current().router.default_destinations, vec!["ops"]);
+        assert_eq!(in_flight.router.default_destinations.is_empty(), true);
+
+        write_config(&path, "default_destinations = [\"nope\"]\n");
+        assert_eq!(
+            shared.reload(&path).err().map(|err| err.to_string()),
+            Some("line 1, key `default_destinations`: unknown notifier `nope`".to_string())
+        );
+        assert_eq!(shared.current().router.default_destinations, vec!["ops"]);
+        assert_eq!(shared.current().destinations.contains_key("ops"), true);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reload_keeps_posted_messages() {
+        let path = env::temp_dir().join("cloudwatch_bot_test_reload_posts.toml");
+        write_config(&path, "[notifiers.ops]\ntype = \"json_lines\"\n");
+        let shared = SharedConfig::new(Config::load(&path, &Arc::new(PostStore::default())).unwrap());
+        Posts::new("ops", shared.current().post_store.clone()).remember(&alarm_details("ALARM", "OK"), "1".to_string());
+
+        shared.reload(&path).unwrap();
+        let posts = Posts::new("ops", shared.current().post_store.clone());
+        assert_eq!(posts.get(&alarm_details("OK", "ALARM")).map(|post| post.id), Some("1".to_string()));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Duration, Utc};
+
+use oncall::{Override, Rotation};
+use super::{ConfigError, Section};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct OverrideConfig {
+    member: String,
+    start: String,
+    end: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RotationConfig {
+    members: Vec<String>,
+    // RFC 3339, when the first member's shift starts.
+    start: String,
+    #[serde(default = "RotationConfig::default_shift_days")]
+    shift_days: u32,
+    #[serde(default)]
+    overrides: Vec<OverrideConfig>,
+}
+
+fn timestamp(section: &Section, key: &str, value: &str) -> Result<DateTime<Utc>, ConfigError> {
+    let value = section.string(key, value)?;
+    value
+        .parse()
+        .map_err(|err| section.error(key, format!("invalid timestamp `{}`: {}", value, err)))
+}
+
+impl RotationConfig {
+    fn default_shift_days() -> u32 {
+        7
+    }
+
+    // `overrides` is the section of the `[[rotations.name.overrides]]` tables,
+    // without their index.
+    pub fn build(&self, section: &Section, overrides: &Section) -> Result<Rotation, ConfigError> {
+        if self.members.is_empty() {
+            return Err(section.error("members", "at least one member is required".to_string()));
+        }
+        if self.shift_days == 0 {
+            return Err(section.error("shift_days", "shifts must last at least one day".to_string()));
+        }
+
+        let mut built = Vec::new();
+        for (index, over) in self.overrides.iter().enumerate() {
+            let section = overrides.at(index);
+            let start = timestamp(&section, "start", &over.start)?;
+            let end = timestamp(&section, "end", &over.end)?;
+            if end <= start {
+                return Err(section.error("end", "an override must end after it starts".to_string()));
+            }
+            built.push(Override {
+                member: section.string("member", &over.member)?,
+                start: start,
+                end: end,
+            });
+        }
+
+        Ok(Rotation {
+            members: self.members.clone(),
+            start: timestamp(section, "start", &self.start)?,
+            shift: Duration::days(i64::from(self.shift_days)),
+            overrides: built,
+        })
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+
+use model::AlarmState;
+use oncall::{MentionTarget, Rotation};
+use routing::{Destinations, FlappingThreshold, Flow, Grouping, NameMatcher, Rule, RuleMatch};
+use templates::Templates;
+use super::{ConfigError, Section};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FlappingConfig {
+    transitions: usize,
+    window_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupConfig {
+    // "namespace", "account", "name_prefix" or "dimension:<name>".
+    by: String,
+    window_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RouteConfig {
+    name: String,
+    destinations: Vec<String>,
+    template: Option<String>,
+    // Who to mention by new state, "ALARM" or "INSUFFICIENT_DATA".
+    #[serde(default)]
+    mentions: BTreeMap<String, Vec<String>>,
+    // Mailed by the `email` destinations of the route, instead of their own
+    // recipients.
+    #[serde(default)]
+    recipients: Vec<String>,
+    // Transitions of flapping alarms are replaced by a notice, then by a
+    // summary once they settle.
+    flapping: Option<FlappingConfig>,
+    // Alarms sharing a key are sent as one message by the chat destinations.
+    // Those which mention someone are sent on their own.
+    group: Option<GroupConfig>,
+    flow: Option<String>,
+    #[serde(default)]
+    topic_arns: Vec<String>,
+    #[serde(default)]
+    accounts: Vec<String>,
+    #[serde(default)]
+    regions: Vec<String>,
+    // Glob pattern, `alarm_name_regex` for a regular expression.
+    alarm_name: Option<String>,
+    alarm_name_regex: Option<String>,
+    #[serde(default)]
+    namespaces: Vec<String>,
+    #[serde(default)]
+    metric_names: Vec<String>,
+    #[serde(default)]
+    dimensions: BTreeMap<String, String>,
+    // Dimensions which must be present, whatever their value.
+    #[serde(default)]
+    dimension_keys: Vec<String>,
+    #[serde(default)]
+    new_states: Vec<String>,
+    #[serde(default)]
+    previous_states: Vec<String>,
+    #[serde(default)]
+    message_attributes: BTreeMap<String, String>,
+    #[serde(default)]
+    message_attribute_keys: Vec<String>,
+}
+
+fn pairs(values: &BTreeMap<String, String>, keys: &[String]) -> Vec<(String, Option<String>)> {
+    values
+        .iter()
+        .map(|(key, value)| (key.clone(), Some(value.clone())))
+        .chain(keys.iter().map(|key| (key.clone(), None)))
+        .collect()
+}
+
+impl RouteConfig {
+    pub fn build(
+        &self,
+        section: &Section,
+        destinations: &Destinations,
+        templates: &Templates,
+        rotations: &HashMap<String, Rotation>,
+        max_transitions: usize,
+    ) -> Result<Rule, ConfigError> {
+        for destination in &self.destinations {
+            if !destinations.contains_key(destination) {
+                return Err(section.error("destinations", format!("unknown notifier `{}`", destination)));
+            }
+        }
+        if let Some(ref template) = self.template {
+            if !templates.contains_key(template) {
+                return Err(section.error("template", format!("unknown template `{}`", template)));
+            }
+        }
+
+        let alarm_name = match (&self.alarm_name, &self.alarm_name_regex) {
+            (&Some(_), &Some(_)) => {
+                return Err(section.error(
+                    "alarm_name_regex",
+                    "`alarm_name` and `alarm_name_regex` cannot be used together".to_string(),
+                ))
+            }
+            (&Some(ref pattern), &None) => Some(NameMatcher::glob(pattern).map_err(|err| {
+                section.error("alarm_name", format!("invalid pattern `{}`: {}", pattern, err))
+            })?),
+            (&None, &Some(ref pattern)) => Some(NameMatcher::regex(pattern).map_err(|err| {
+                section.error("alarm_name_regex", format!("invalid regex `{}`: {}", pattern, err))
+            })?),
+            (&None, &None) => None,
+        };
+
+        let mut new_states = Vec::new();
+        for state in &self.new_states {
+            new_states.push(section.parse("new_states", state)?);
+        }
+        let mut previous_states = Vec::new();
+        for state in &self.previous_states {
+            previous_states.push(section.parse("previous_states", state)?);
+        }
+
+        let mut mentions = Vec::new();
+        for (state, targets) in &self.mentions {
+            let state: AlarmState = section.parse("mentions", state)?;
+            if state == AlarmState::Ok {
+                return Err(section.error("mentions", "OK transitions never mention anyone".to_string()));
+            }
+            let mut parsed = Vec::new();
+            for target in targets {
+                let target: MentionTarget = section.parse("mentions", target)?;
+                if let MentionTarget::Rotation(ref name) = target {
+                    if !rotations.contains_key(name) {
+                        return Err(section.error("mentions", format!("unknown rotation `{}`", name)));
+                    }
+                }
+                parsed.push(target);
+            }
+            mentions.push((state, parsed));
+        }
+
+        let flapping = match self.flapping {
+            Some(ref flapping) if flapping.transitions < 2 => {
+                return Err(section.error("flapping", "at least two transitions are needed to flap".to_string()))
+            }
+            Some(ref flapping) if flapping.window_secs == 0 => {
+                return Err(section.error("flapping", "the window must last at least one second".to_string()))
+            }
+            // The history wouldn't keep enough transitions to ever count them.
+            Some(ref flapping) if flapping.transitions > max_transitions => {
+                return Err(section.error(
+                    "flapping",
+                    format!("more transitions than the history keeps ({})", max_transitions),
+                ))
+            }
+            Some(ref flapping) => Some(FlappingThreshold {
+                transitions: flapping.transitions,
+                window_secs: flapping.window_secs,
+            }),
+            None => None,
+        };
+
+        let grouping = match self.group {
+            Some(ref group) if group.window_secs == 0 => {
+                return Err(section.error("group", "the window must last at least one second".to_string()))
+            }
+            Some(ref group) => Some(Grouping {
+                key: section.parse("group", &group.by)?,
+                window_secs: group.window_secs,
+            }),
+            None => None,
+        };
+
+        Ok(Rule {
+            name: self.name.clone(),
+            matcher: RuleMatch {
+                topic_arns: self.topic_arns.clone(),
+                accounts: self.accounts.clone(),
+                regions: self.regions.clone(),
+                alarm_name: alarm_name,
+                namespaces: self.namespaces.clone(),
+                metric_names: self.metric_names.clone(),
+                dimensions: pairs(&self.dimensions, &self.dimension_keys),
+                new_states: new_states,
+                previous_states: previous_states,
+                message_attributes: pairs(&self.message_attributes, &self.message_attribute_keys),
+            },
+            destinations: self.destinations.clone(),
+            template: self.template.clone(),
+            mentions: mentions,
+            recipients: self.recipients.clone(),
+            flapping: flapping,
+            grouping: grouping,
+            flow: match self.flow {
+                Some(ref flow) => section.parse("flow", flow)?,
+                None => Flow::Continue,
+            },
+        })
+    }
+}
@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+
+use templates::{Template, TemplateSet, parse_transition};
+use super::{ConfigError, Section};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TemplateConfig {
+    text: String,
+    // Notifiers using this template unless their route sets another one.
+    #[serde(default)]
+    pub destinations: Vec<String>,
+    // Replacements of `text` for some transitions, "ALARM" or "OK->ALARM".
+    #[serde(default)]
+    transitions: BTreeMap<String, String>,
+}
+
+impl TemplateConfig {
+    pub fn build(&self, section: &Section, transitions: &Section) -> Result<TemplateSet, ConfigError> {
+        let default = Template::compile(&self.text)
+            .map_err(|err| section.error("text", format!("invalid template: {}", err)))?;
+
+        let mut templates = Vec::new();
+        for (transition, text) in &self.transitions {
+            let (previous, new) = parse_transition(transition).map_err(|err| {
+                transitions.error(transition, format!("invalid transition `{}`: {}", transition, err))
+            })?;
+            let template = Template::compile(text)
+                .map_err(|err| transitions.error(transition, format!("invalid template: {}", err)))?;
+            templates.push((previous, new, template));
+        }
+
+        Ok(TemplateSet {
+            default: default,
+            transitions: templates,
+        })
+    }
+}
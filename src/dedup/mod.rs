@@ -0,0 +1,267 @@
+mod store;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::DedupConfig;
+use self::store::Store;
+
+// How often, at most, expired IDs are removed from disk.
+const PURGE_INTERVAL_SECS: u64 = 3600;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Claim {
+    // To be processed, then `finish`ed or `release`d.
+    First,
+    // Processed within the TTL.
+    Processed,
+    // Another delivery is being processed, which may still fail.
+    InFlight,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // When each ID was first seen, and when it was last seen as a tick of
+    // `recency`.
+    seen: HashMap<String, (u64, u64)>,
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    in_flight: HashSet<String>,
+}
+
+impl State {
+    fn touch(&mut self, id: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.seen.get_mut(id) {
+            self.recency.remove(&entry.1);
+            entry.1 = tick;
+        }
+        self.recency.insert(tick, id.to_string());
+    }
+
+    fn insert(&mut self, id: &str, seen_at: u64, capacity: usize) {
+        self.seen.insert(id.to_string(), (seen_at, 0));
+        self.touch(id);
+        while self.seen.len() > capacity {
+            let oldest = match self.recency.keys().next() {
+                Some(&tick) => tick,
+                None => break,
+            };
+            if let Some(id) = self.recency.remove(&oldest) {
+                self.seen.remove(&id);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &str) {
+        if let Some((_, tick)) = self.seen.remove(id) {
+            self.recency.remove(&tick);
+        }
+    }
+}
+
+// The SNS message IDs processed within the last `ttl_secs`. The most recently
+// seen are kept in memory, the store on disk remembers them all.
+#[derive(Debug)]
+pub(crate) struct Dedup {
+    ttl_secs: u64,
+    capacity: usize,
+    store: Option<Store>,
+    state: Mutex<State>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0)
+}
+
+impl Dedup {
+    pub fn open(config: &DedupConfig) -> io::Result<Dedup> {
+        let store = match config.directory {
+            Some(ref directory) => Some(Store::open(directory)?),
+            None => None,
+        };
+
+        Ok(Dedup {
+            ttl_secs: config.ttl_secs,
+            capacity: config.capacity,
+            store: store,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().expect("Dedup lock poisoned")
+    }
+
+    // `First` the first time a message ID is seen, `Processed` for the
+    // redeliveries within the TTL.
+    pub fn claim(&self, id: &str) -> io::Result<Claim> {
+        self.claim_at(id, now())
+    }
+
+    pub fn claim_at(&self, id: &str, now: u64) -> io::Result<Claim> {
+        let expired = now.saturating_sub(self.ttl_secs);
+        let mut state = self.lock();
+        if state.in_flight.contains(id) {
+            return Ok(Claim::InFlight);
+        }
+
+        let seen_at = state.seen.get(id).map(|&(seen_at, _)| seen_at);
+        match seen_at {
+            Some(seen_at) if seen_at > expired => {
+                state.touch(id);
+                return Ok(Claim::Processed);
+            }
+            Some(_) => state.remove(id),
+            None => {}
+        }
+
+        if let Some(ref store) = self.store {
+            if let Some(seen_at) = store.seen_at(id)? {
+                if seen_at > expired {
+                    state.insert(id, seen_at, self.capacity);
+                    return Ok(Claim::Processed);
+                }
+            }
+            store.save(id, now)?;
+        }
+        state.insert(id, now, self.capacity);
+        state.in_flight.insert(id.to_string());
+        Ok(Claim::First)
+    }
+
+    // The message ID was processed, its redeliveries are acknowledged.
+    pub fn finish(&self, id: &str) {
+        self.lock().in_flight.remove(id);
+    }
+
+    // Forgets a message ID whose processing failed, so that its redelivery
+    // goes through.
+    pub fn release(&self, id: &str) -> io::Result<()> {
+        {
+            let mut state = self.lock();
+            state.in_flight.remove(id);
+            state.remove(id);
+        }
+        match self.store {
+            Some(ref store) => store.remove(id),
+            None => Ok(()),
+        }
+    }
+
+    // Removes the IDs expired at `now` from disk. Claims go on meanwhile.
+    pub fn purge_at(&self, now: u64) -> io::Result<usize> {
+        match self.store {
+            Some(ref store) => store.purge(now.saturating_sub(self.ttl_secs) + 1),
+            None => Ok(0),
+        }
+    }
+}
+
+pub(crate) fn start(dedup: Arc<Dedup>) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(PURGE_INTERVAL_SECS.min(dedup.ttl_secs)));
+        if let Err(err) = dedup.purge_at(now()) {
+            let _ = writeln!(io::stderr(), "cannot purge expired notification IDs: {}", err);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("cloudwatch_bot_test_dedup_{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn dedup(directory: Option<PathBuf>, capacity: usize) -> Dedup {
+        Dedup::open(&DedupConfig {
+            directory: directory,
+            ttl_secs: 100,
+            capacity: capacity,
+        }).unwrap()
+    }
+
+    // Claims `id` at `now`, its processing finishing straight away.
+    fn claim(dedup: &Dedup, id: &str, now: u64) -> Claim {
+        let claim = dedup.claim_at(id, now).unwrap();
+        dedup.finish(id);
+        claim
+    }
+
+    #[test]
+    fn test_redeliveries_within_ttl() {
+        let dedup = dedup(None, 10);
+
+        assert_eq!(claim(&dedup, "m1", 1000), Claim::First);
+        assert_eq!(claim(&dedup, "m1", 1050), Claim::Processed);
+        assert_eq!(claim(&dedup, "m2", 1050), Claim::First);
+        assert_eq!(claim(&dedup, "m1", 1099), Claim::Processed);
+        assert_eq!(claim(&dedup, "m1", 1100), Claim::First);
+    }
+
+    #[test]
+    fn test_least_recently_seen_are_evicted() {
+        let dedup = dedup(None, 2);
+
+        claim(&dedup, "m1", 1000);
+        claim(&dedup, "m2", 1000);
+        assert_eq!(claim(&dedup, "m1", 1001), Claim::Processed);
+        claim(&dedup, "m3", 1002);
+
+        assert_eq!(claim(&dedup, "m1", 1003), Claim::Processed);
+        assert_eq!(claim(&dedup, "m2", 1003), Claim::First);
+    }
+
+    #[test]
+    fn test_release() {
+        let dedup = dedup(None, 10);
+
+        claim(&dedup, "m1", 1000);
+        dedup.release("m1").unwrap();
+        assert_eq!(claim(&dedup, "m1", 1001), Claim::First);
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let dedup = dedup(None, 10);
+
+        dedup.claim_at("m1", 1000).unwrap();
+        assert_eq!(dedup.claim_at("m1", 1001).unwrap(), Claim::InFlight);
+        dedup.release("m1").unwrap();
+        assert_eq!(dedup.claim_at("m1", 1002).unwrap(), Claim::First);
+        dedup.finish("m1");
+        assert_eq!(dedup.claim_at("m1", 1003).unwrap(), Claim::Processed);
+    }
+
+    #[test]
+    fn test_store() {
+        let directory = directory("store");
+        let dedup = dedup(Some(directory.clone()), 1);
+
+        claim(&dedup, "d921a633-3dbb-528e-a15c-e978b55d6156", 1000);
+        claim(&dedup, "../m2", 1000);
+        assert_eq!(directory.join("d921a633-3dbb-528e-a15c-e978b55d6156").exists(), true);
+        assert_eq!(directory.join("_2e2e2f6d32").exists(), true);
+
+        // Evicted from memory, and forgotten on restart, but still on disk.
+        assert_eq!(claim(&dedup, "d921a633-3dbb-528e-a15c-e978b55d6156", 1010), Claim::Processed);
+        let dedup = self::dedup(Some(directory.clone()), 1);
+        assert_eq!(claim(&dedup, "../m2", 1010), Claim::Processed);
+
+        // Expired IDs are purged once in a while.
+        assert_eq!(claim(&dedup, "m3", 1200), Claim::First);
+        assert_eq!(dedup.purge_at(1200).unwrap(), 2);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+// One file per message ID, holding the unix time it was first seen. A file
+// left empty by a crash reads as never seen, which at worst notifies twice.
+#[derive(Debug)]
+pub(crate) struct Store {
+    directory: PathBuf,
+}
+
+// SNS message IDs are UUIDs, anything else is hex encoded so that it can't
+// escape the directory.
+fn file_name(id: &str) -> String {
+    let plain = id.chars().all(|c| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' => true,
+        _ => false,
+    });
+    if plain && !id.is_empty() {
+        id.to_string()
+    } else {
+        let hex: Vec<String> = id.bytes().map(|byte| format!("{:02x}", byte)).collect();
+        format!("_{}", hex.concat())
+    }
+}
+
+impl Store {
+    pub fn open(directory: &Path) -> io::Result<Store> {
+        fs::create_dir_all(directory)?;
+        Ok(Store { directory: directory.to_path_buf() })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.directory.join(file_name(id))
+    }
+
+    fn read(path: &Path) -> io::Result<Option<u64>> {
+        let mut content = String::new();
+        match File::open(path).and_then(|mut file| file.read_to_string(&mut content)) {
+            Ok(_) => Ok(content.trim().parse().ok()),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn seen_at(&self, id: &str) -> io::Result<Option<u64>> {
+        Store::read(&self.path(id))
+    }
+
+    pub fn save(&self, id: &str, seen_at: u64) -> io::Result<()> {
+        let mut file = File::create(self.path(id))?;
+        file.write_all(seen_at.to_string().as_bytes())
+    }
+
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    // Removes the IDs seen before `before`, and the unreadable ones.
+    pub fn purge(&self, before: u64) -> io::Result<usize> {
+        let mut purged = 0;
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if Store::read(&path)?.map_or(true, |seen_at| seen_at < before) {
+                fs::remove_file(&path)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+}
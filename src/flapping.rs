@@ -0,0 +1,127 @@
+use chrono::{DateTime, Utc};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use config::{Config, SharedConfig};
+use history::{AlarmRecord, Flapping, History, parse_time};
+use l10n::Localizer;
+use model::AlarmDetails;
+use queue::{Job, Queue};
+use templates::format_duration;
+
+const SETTLE_INTERVAL_SECS: u64 = 30;
+
+// Posted instead of the transition which made the alarm flap.
+pub(crate) fn started_notice(l10n: &Localizer, alarm: &AlarmDetails, flapping: &Flapping) -> String {
+    l10n.message(
+        "flapping-started",
+        &[
+            ("name", &alarm.name),
+            ("count", &flapping.transitions.to_string()),
+            ("window", &format_duration(flapping.window_secs)),
+        ],
+    )
+}
+
+pub(crate) fn settled_summary(l10n: &Localizer, record: &AlarmRecord, flapping: &Flapping) -> String {
+    let seconds = match (parse_time(&flapping.since), parse_time(&record.since)) {
+        (Some(start), Some(end)) => end.signed_duration_since(start).num_seconds().max(0) as u64,
+        _ => 0,
+    };
+    l10n.message(
+        "flapping-settled",
+        &[
+            ("name", &record.name),
+            ("state", &l10n.state(&record.state)),
+            ("duration", &format_duration(seconds)),
+            ("count", &flapping.suppressed.to_string()),
+        ],
+    )
+}
+
+// Queues the summary of the alarms which settled at `now`, to the
+// destinations the route of their latest transition has now. They keep
+// flapping if it can't be queued, to be summarized on the next try.
+pub(crate) fn settle(history: &History, queue: &Queue, config: &Config, now: DateTime<Utc>) -> io::Result<()> {
+    history
+        .settle(now, |settled| {
+            let mut jobs = Vec::new();
+            for &(ref record, ref flapping) in settled {
+                let alarm: AlarmDetails = match flapping.notification.parse() {
+                    Ok(alarm) => alarm,
+                    Err(err) => {
+                        let _ = writeln!(io::stderr(), "cannot summarize flapping alarm {}: {}", record.key, err);
+                        continue;
+                    }
+                };
+                let decision = config.router.route(&alarm);
+                jobs.extend(Job::notices(config, &decision, &flapping.notification, |l10n| {
+                    settled_summary(l10n, record, flapping)
+                }));
+            }
+            queue.enqueue(jobs)
+        })
+        .map(|_| ())
+}
+
+pub(crate) fn start(history: Arc<History>, queue: Arc<Queue>, config: Arc<SharedConfig>) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(SETTLE_INTERVAL_SECS));
+        if let Err(err) = settle(&history, &queue, &config.current(), Utc::now()) {
+            let _ = writeln!(io::stderr(), "cannot settle flapping alarms: {}", err);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{HistoryConfig, QueueConfig};
+    use history::Recorded;
+    use model::fixtures::sns_notification;
+    use routing::FlappingThreshold;
+
+    #[test]
+    fn test_notices() {
+        let l10n = Localizer::default();
+        let history = History::open(&HistoryConfig::default()).unwrap();
+        let threshold = FlappingThreshold {
+            transitions: 2,
+            window_secs: 3600,
+        };
+        let transitions = [("ALARM", "OK", "11:21:10"), ("OK", "ALARM", "11:31:10"), ("ALARM", "OK", "11:41:10")];
+        let mut recorded = Vec::new();
+        for &(new_state, previous_state, time) in &transitions {
+            let notification = sns_notification("m1", new_state, previous_state).replace("11:21:10", time);
+            let alarm: AlarmDetails = notification.parse().unwrap();
+            recorded.push(history.record(&alarm, &notification, Some(&threshold), |_| Ok(())).unwrap());
+        }
+
+        let flapping = match recorded[1] {
+            Recorded::StartedFlapping(ref flapping) => flapping.clone(),
+            ref other => panic!("{:?}", other),
+        };
+        let alarm: AlarmDetails = flapping.notification.parse().unwrap();
+        assert_eq!(
+            started_notice(&l10n, &alarm, &flapping),
+            "[RTB-US] UnHealthyHostCount is flapping: 2 state changes within 1h, its transitions are muted until \
+             it settles."
+        );
+
+        let queue = Queue::open(&QueueConfig::default()).unwrap();
+        let mut config = Config::default();
+        config.router.default_destinations = vec!["ops".to_string()];
+        settle(&history, &queue, &config, "2016-02-27T12:41:11Z".parse().unwrap()).unwrap();
+        let pending = queue.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(
+            pending[0].text,
+            Some(
+                "[RTB-US] UnHealthyHostCount settled in ALARM after flapping for 10m (muted transitions: 1)."
+                    .to_string()
+            )
+        );
+    }
+}
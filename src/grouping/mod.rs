@@ -0,0 +1,410 @@
+mod store;
+
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use config::{Config, QueueConfig, SharedConfig};
+use history;
+use l10n::Localizer;
+use model::AlarmDetails;
+use queue::{GroupDelivery, Job, Queue};
+use routing::RoutingDecision;
+use self::store::Store;
+use templates::state_emoji;
+
+const FLUSH_INTERVAL_SECS: u64 = 1;
+const GROUPS: &str = "groups";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Member {
+    // History key of the alarm, its later transitions replace this one.
+    key: String,
+    notification: String,
+    routing: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Group {
+    id: String,
+    key: String,
+    window_secs: u64,
+    destinations: Vec<String>,
+    members: Vec<Member>,
+    // Members which joined or changed since the group was last sent.
+    changed: Vec<usize>,
+    opened_at: u64,
+    joined_at: u64,
+    sent: bool,
+    // Whether the consolidated message was sent, it is then edited.
+    posted: bool,
+}
+
+impl Group {
+    // A group left with a single alarm once its window is over sends the
+    // alarm's own message.
+    fn jobs(&self, config: &Config) -> Vec<Job> {
+        if self.members.len() == 1 && !self.posted {
+            let member = &self.members[0];
+            let alarm: AlarmDetails = match member.notification.parse() {
+                Ok(alarm) => alarm,
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "cannot send group {}: {}", self.id, err);
+                    return Vec::new();
+                }
+            };
+            let mut decision = RoutingDecision::from_json(&member.routing);
+            decision.destinations = self.destinations.clone();
+            return Job::all(config, &decision, &alarm, &member.notification);
+        }
+
+        let mut alarms = Vec::new();
+        for member in &self.members {
+            match member.notification.parse() {
+                Ok(alarm) => alarms.push(alarm),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "cannot send group {}: {}", self.id, err);
+                    return Vec::new();
+                }
+            }
+        }
+        let delivery = GroupDelivery {
+            id: self.id.clone(),
+            key: self.key.clone(),
+            notifications: self.members.iter().map(|member| member.notification.clone()).collect(),
+            changed: self.changed.clone(),
+            update: self.posted,
+        };
+        let routing = self.members.last().map_or(Value::Null, |member| member.routing.clone());
+        self.destinations
+            .iter()
+            .map(|destination| {
+                let text = match config.localizers.get(destination) {
+                    Some(l10n) => summary(l10n, &self.key, &alarms),
+                    None => summary(&Localizer::default(), &self.key, &alarms),
+                };
+                Job::group(destination, routing.clone(), text, delivery.clone())
+            })
+            .collect()
+    }
+}
+
+// The alarms collected in the grouping window of their route. The groups are
+// kept next to the queued deliveries, an alarm waiting in a group is
+// acknowledged like a queued one.
+#[derive(Debug)]
+pub(crate) struct Groups {
+    store: Option<Store>,
+    groups: Mutex<HashMap<String, Group>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).unwrap_or(0)
+}
+
+impl Groups {
+    pub fn open(config: &QueueConfig) -> io::Result<Groups> {
+        let mut groups = HashMap::new();
+        let store = match config.directory {
+            Some(ref directory) => {
+                let store = Store::open(&directory.join(GROUPS))?;
+                for group in store.load()? {
+                    groups.insert(group.id.clone(), group);
+                }
+                Some(store)
+            }
+            None => None,
+        };
+
+        Ok(Groups {
+            store: store,
+            groups: Mutex::new(groups),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<String, Group>> {
+        self.groups.lock().expect("Groups lock poisoned")
+    }
+
+    fn save(&self, group: &Group) -> io::Result<()> {
+        match self.store {
+            Some(ref store) => store.save(group),
+            None => Ok(()),
+        }
+    }
+
+    // Adds the alarm to the group of its route and returns the destinations
+    // the group sends to, the others are notified as usual. Alarms which
+    // mention someone aren't grouped.
+    pub fn join(
+        &self,
+        config: &Config,
+        decision: &RoutingDecision,
+        alarm: &AlarmDetails,
+        notification: &str,
+    ) -> io::Result<Vec<String>> {
+        self.join_at(config, decision, alarm, notification, now())
+    }
+
+    pub fn join_at(
+        &self,
+        config: &Config,
+        decision: &RoutingDecision,
+        alarm: &AlarmDetails,
+        notification: &str,
+        now: u64,
+    ) -> io::Result<Vec<String>> {
+        let (rule, grouping) = match decision.grouping {
+            Some((ref rule, ref grouping)) if decision.mentions.is_empty() => (rule, grouping),
+            _ => return Ok(Vec::new()),
+        };
+        let key = match grouping.key.value(alarm) {
+            Some(key) => key,
+            None => return Ok(Vec::new()),
+        };
+        let destinations: Vec<String> = decision
+            .destinations
+            .iter()
+            .filter(|destination| config.destinations.get(*destination).map_or(false, |n| n.can_group()))
+            .cloned()
+            .collect();
+        if destinations.is_empty() {
+            return Ok(destinations);
+        }
+
+        let id = format!("{}|{}|{}", rule, key, destinations.join(","));
+        let member = Member {
+            key: history::key(alarm),
+            notification: notification.to_string(),
+            routing: decision.to_json(),
+        };
+        let mut groups = self.lock();
+        let mut group = groups.get(&id).cloned().unwrap_or_else(|| Group {
+            id: id.clone(),
+            key: key,
+            window_secs: grouping.window_secs,
+            destinations: destinations.clone(),
+            members: Vec::new(),
+            changed: Vec::new(),
+            opened_at: now,
+            joined_at: now,
+            sent: false,
+            posted: false,
+        });
+        group.joined_at = now;
+        let index = match group.members.iter().position(|m| m.key == member.key) {
+            Some(index) => {
+                group.members[index] = member;
+                index
+            }
+            None => {
+                group.members.push(member);
+                group.members.len() - 1
+            }
+        };
+        if !group.changed.contains(&index) {
+            group.changed.push(index);
+        }
+        self.save(&group)?;
+        groups.insert(id, group);
+        Ok(destinations)
+    }
+
+    // Queues the messages of the groups whose window is over, then of their
+    // later changes. Groups quiet for a whole window are closed. The groups
+    // are only marked as sent once their messages are queued.
+    pub fn flush(&self, queue: &Queue, config: &Config, now: u64) -> io::Result<()> {
+        let mut groups = self.lock();
+        let mut jobs = Vec::new();
+        let mut sent = Vec::new();
+        let mut closed = Vec::new();
+        for (id, group) in groups.iter() {
+            if !group.changed.is_empty() && (group.sent || now >= group.opened_at + group.window_secs) {
+                jobs.extend(group.jobs(config));
+                sent.push(id.clone());
+            } else if group.sent && group.changed.is_empty() && now >= group.joined_at + group.window_secs {
+                closed.push(id.clone());
+            }
+        }
+        if !jobs.is_empty() {
+            queue.enqueue(jobs)?;
+        }
+
+        for id in &sent {
+            if let Some(group) = groups.get_mut(id) {
+                group.posted = group.posted || group.members.len() > 1;
+                group.sent = true;
+                group.changed.clear();
+                self.save(group)?;
+            }
+        }
+        for id in &closed {
+            groups.remove(id);
+            if let Some(ref store) = self.store {
+                store.remove(id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// "3 alarms for [RTB-US]" then a line per alarm, in the order they joined.
+pub(crate) fn summary(l10n: &Localizer, key: &str, alarms: &[AlarmDetails]) -> String {
+    let mut lines = vec![l10n.message("group-summary", &[("count", &alarms.len().to_string()), ("key", key)])];
+    for alarm in alarms {
+        lines.push(format!("{} {}: {}", state_emoji(&alarm.new_state), alarm.name, l10n.state(&alarm.new_state)));
+    }
+    lines.join("\n")
+}
+
+pub(crate) fn start(groups: Arc<Groups>, queue: Arc<Queue>, config: Arc<SharedConfig>) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(FLUSH_INTERVAL_SECS));
+        if let Err(err) = groups.flush(&queue, &config.current(), now()) {
+            let _ = writeln!(io::stderr(), "cannot send alarm groups: {}", err);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Client;
+    use hyper::client::HttpConnector;
+    use hyper_tls::HttpsConnector;
+    use model::fixtures::sns_notification;
+    use notifiers::{Notifier, NotifierError};
+    use routing::{GroupKey, Grouping};
+    use std::env;
+    use std::fs;
+    use tokio_core::reactor::Core;
+
+    struct Chat;
+
+    impl Notifier for Chat {
+        fn notify(
+            &self,
+            _client: &Client<HttpsConnector<HttpConnector>>,
+            _core: &mut Core,
+            _alarm: &AlarmDetails,
+        ) -> Result<(), NotifierError> {
+            Ok(())
+        }
+
+        fn can_group(&self) -> bool {
+            true
+        }
+    }
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.destinations.insert("chat".to_string(), Box::new(Chat));
+        config.router.default_destinations = vec!["chat".to_string(), "audit".to_string()];
+        config
+    }
+
+    fn join(groups: &Groups, config: &Config, name: &str, new_state: &str, now: u64) -> Vec<String> {
+        let previous_state = if new_state == "OK" { "ALARM" } else { "OK" };
+        let notification = sns_notification("m1", new_state, previous_state).replace("[RTB-US] UnHealthyHostCount", name);
+        let alarm: AlarmDetails = notification.parse().unwrap();
+        let mut decision = config.router.route(&alarm);
+        decision.grouping = Some((
+            "default".to_string(),
+            Grouping {
+                key: GroupKey::NamePrefix,
+                window_secs: 30,
+            },
+        ));
+        groups.join_at(config, &decision, &alarm, &notification, now).unwrap()
+    }
+
+    fn groups() -> Groups {
+        Groups::open(&QueueConfig::default()).unwrap()
+    }
+
+    // The jobs queued by a flush at `now`.
+    fn due(groups: &Groups, config: &Config, now: u64) -> Vec<Job> {
+        let queue = Queue::open(&QueueConfig::default()).unwrap();
+        groups.flush(&queue, config, now).unwrap();
+        queue.pending()
+    }
+
+    #[test]
+    fn test_groups() {
+        let config = config();
+        let groups = groups();
+
+        assert_eq!(join(&groups, &config, "[RTB-US] UnHealthyHostCount", "ALARM", 1000), vec!["chat"]);
+        assert_eq!(join(&groups, &config, "[RTB-US] Latency", "ALARM", 1010), vec!["chat"]);
+        assert_eq!(due(&groups, &config, 1029).is_empty(), true);
+
+        let jobs = due(&groups, &config, 1030);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].destination, "chat");
+        assert_eq!(
+            jobs[0].text,
+            Some("2 alarms for [RTB-US]\n🔴 [RTB-US] UnHealthyHostCount: ALARM\n🔴 [RTB-US] Latency: ALARM".to_string())
+        );
+        let group = jobs[0].group.clone().unwrap();
+        assert_eq!(group.id, "default|[RTB-US]|chat");
+        assert_eq!(group.changed, vec![0, 1]);
+        assert_eq!(group.update, false);
+        assert_eq!(due(&groups, &config, 1031).is_empty(), true);
+
+        // Later changes edit the message right away.
+        join(&groups, &config, "[RTB-US] UnHealthyHostCount", "OK", 1035);
+        let jobs = due(&groups, &config, 1036);
+        let line = jobs[0].text.as_ref().and_then(|text| text.lines().nth(1));
+        assert_eq!(line, Some("✅ [RTB-US] UnHealthyHostCount: OK"));
+        let group = jobs[0].group.clone().unwrap();
+        assert_eq!(group.changed, vec![0]);
+        assert_eq!(group.update, true);
+
+        // Quiet for a whole window, the group is closed.
+        assert_eq!(due(&groups, &config, 1065).is_empty(), true);
+        join(&groups, &config, "[RTB-US] Latency", "OK", 1066);
+        assert_eq!(due(&groups, &config, 1067).is_empty(), true);
+    }
+
+    #[test]
+    fn test_single_alarm_is_sent_as_usual() {
+        let config = config();
+        let groups = groups();
+
+        join(&groups, &config, "[RTB-US] UnHealthyHostCount", "ALARM", 1000);
+        let jobs = due(&groups, &config, 1030);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].group.is_none(), true);
+        assert_eq!(jobs[0].destination, "chat");
+
+        // Unless there is no key to group on.
+        assert_eq!(join(&groups, &config, "Latency", "ALARM", 1031).is_empty(), true);
+    }
+
+    #[test]
+    fn test_groups_survive_restarts() {
+        let config = config();
+        let directory = env::temp_dir().join("cloudwatch_bot_test_grouping_restart");
+        let _ = fs::remove_dir_all(&directory);
+        let queue_config = QueueConfig {
+            directory: Some(directory.clone()),
+            ..QueueConfig::default()
+        };
+
+        let groups = Groups::open(&queue_config).unwrap();
+        join(&groups, &config, "[RTB-US] UnHealthyHostCount", "ALARM", 1000);
+        join(&groups, &config, "[RTB-US] Latency", "ALARM", 1001);
+
+        let groups = Groups::open(&queue_config).unwrap();
+        let jobs = due(&groups, &config, 1030);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].group.clone().unwrap().notifications.len(), 2);
+
+        // Closed groups are forgotten.
+        due(&groups, &config, 1061);
+        assert_eq!(Groups::open(&queue_config).unwrap().lock().is_empty(), true);
+    }
+}
@@ -0,0 +1,66 @@
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::Group;
+
+// One JSON file per open group, named after a hash of its ID. Written next to
+// their final name then renamed, like the queued deliveries.
+#[derive(Debug)]
+pub(crate) struct Store {
+    directory: PathBuf,
+}
+
+impl Store {
+    pub fn open(directory: &Path) -> io::Result<Store> {
+        fs::create_dir_all(directory)?;
+        Ok(Store { directory: directory.to_path_buf() })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        let hex: Vec<String> = Sha256::digest(id.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        self.directory.join(format!("{}.json", hex.concat()))
+    }
+
+    pub fn save(&self, group: &Group) -> io::Result<()> {
+        let path = self.path(&group.id);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec(group).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&json)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)
+    }
+
+    pub fn remove(&self, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(id)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    // Unreadable files are reported and left alone.
+    pub fn load(&self) -> io::Result<Vec<Group>> {
+        let mut groups = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |extension| extension != "json") {
+                continue;
+            }
+            let group = File::open(&path).and_then(|file| {
+                serde_json::from_reader(file).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+            });
+            match group {
+                Ok(group) => groups.push(group),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "{}: skipping alarm group: {}", path.display(), err);
+                }
+            }
+        }
+        Ok(groups)
+    }
+}
@@ -0,0 +1,544 @@
+mod store;
+
+use chrono::{DateTime, FixedOffset, Utc};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Mutex, MutexGuard};
+
+use config::HistoryConfig;
+use model::{AlarmDetails, AlarmState};
+use routing::FlappingThreshold;
+use self::store::Store;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Transition {
+    pub from: AlarmState,
+    pub to: AlarmState,
+    // `StateChangeTime` of the notification.
+    pub timestamp: String,
+    pub reason: String,
+}
+
+impl<'a> From<&'a AlarmDetails> for Transition {
+    fn from(alarm: &AlarmDetails) -> Transition {
+        Transition {
+            from: alarm.previous_state.clone(),
+            to: alarm.new_state.clone(),
+            timestamp: alarm.timestamp.clone(),
+            reason: alarm.reason.clone(),
+        }
+    }
+}
+
+// An alarm whose transitions are not notified until it settles.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Flapping {
+    // Time of the transition which made it flap.
+    pub since: String,
+    // Transitions within the window at that time.
+    pub transitions: usize,
+    pub window_secs: u64,
+    // Transitions not notified since.
+    pub suppressed: u32,
+    // The SNS notification of the latest transition, for the summary.
+    pub notification: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct AlarmRecord {
+    pub key: String,
+    pub name: String,
+    pub account_id: String,
+    pub region: String,
+    pub state: AlarmState,
+    // Time and reason of the latest transition.
+    pub since: String,
+    pub reason: String,
+    // Oldest first.
+    pub transitions: Vec<Transition>,
+    pub flapping: Option<Flapping>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Recorded {
+    // The transition to the current state of the alarm.
+    Latest,
+    // The latest transition, which made the alarm flap.
+    StartedFlapping(Flapping),
+    // The latest transition of an alarm which is flapping.
+    Flapping,
+    // Older than the latest transition, SNS delivered it late.
+    Stale,
+    // Already in the history.
+    Duplicate,
+}
+
+// The ARN when CloudWatch sends it, which older payloads don't.
+pub(crate) fn key(alarm: &AlarmDetails) -> String {
+    match alarm.arn {
+        Some(ref arn) => arn.clone(),
+        None => format!("{}:{}:{}", alarm.account_id, alarm.region_code().unwrap_or(&alarm.region), alarm.name),
+    }
+}
+
+pub(crate) fn parse_time(timestamp: &str) -> Option<DateTime<FixedOffset>> {
+    DateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f%z").ok()
+}
+
+// Timestamps which can't be parsed are compared as strings, which works for
+// the ISO 8601 ones at least.
+fn compare(a: &str, b: &str) -> Ordering {
+    match (parse_time(a), parse_time(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+impl AlarmRecord {
+    fn new(alarm: &AlarmDetails) -> AlarmRecord {
+        AlarmRecord {
+            key: key(alarm),
+            name: alarm.name.clone(),
+            account_id: alarm.account_id.clone(),
+            region: alarm.region.clone(),
+            state: alarm.new_state.clone(),
+            since: alarm.timestamp.clone(),
+            reason: alarm.reason.clone(),
+            transitions: Vec::new(),
+            flapping: None,
+        }
+    }
+
+    // Those within `window_secs` of the latest one.
+    fn transitions_within(&self, window_secs: u64) -> usize {
+        let latest = match parse_time(&self.since) {
+            Some(latest) => latest,
+            None => return 0,
+        };
+        self.transitions
+            .iter()
+            .filter_map(|transition| parse_time(&transition.timestamp))
+            .filter(|&time| latest.signed_duration_since(time).num_seconds() <= window_secs as i64)
+            .count()
+    }
+
+    // Transitions are kept in the order they happened, whatever the order
+    // they were delivered in.
+    fn add(
+        &mut self,
+        alarm: &AlarmDetails,
+        notification: &str,
+        threshold: Option<&FlappingThreshold>,
+        max_transitions: usize,
+    ) -> Recorded {
+        let transition = Transition::from(alarm);
+        if self.transitions.contains(&transition) {
+            return if self.flapping.is_some() { Recorded::Flapping } else { Recorded::Duplicate };
+        }
+
+        let position = self.transitions
+            .iter()
+            .rposition(|t| compare(&t.timestamp, &transition.timestamp) != Ordering::Greater)
+            .map_or(0, |position| position + 1);
+        let latest = position == self.transitions.len();
+        self.transitions.insert(position, transition);
+        let excess = self.transitions.len().saturating_sub(max_transitions);
+        self.transitions.drain(..excess);
+
+        if !latest {
+            return Recorded::Stale;
+        }
+        self.state = alarm.new_state.clone();
+        self.since = alarm.timestamp.clone();
+        self.reason = alarm.reason.clone();
+
+        if let Some(ref mut flapping) = self.flapping {
+            flapping.suppressed += 1;
+            flapping.notification = notification.to_string();
+            return Recorded::Flapping;
+        }
+        if let Some(threshold) = threshold {
+            let transitions = self.transitions_within(threshold.window_secs);
+            if transitions >= threshold.transitions {
+                let flapping = Flapping {
+                    since: alarm.timestamp.clone(),
+                    transitions: transitions,
+                    window_secs: threshold.window_secs,
+                    suppressed: 0,
+                    notification: notification.to_string(),
+                };
+                self.flapping = Some(flapping.clone());
+                return Recorded::StartedFlapping(flapping);
+            }
+        }
+        Recorded::Latest
+    }
+}
+
+// The latest state of every alarm notified so far, with its transitions.
+#[derive(Debug)]
+pub(crate) struct History {
+    max_transitions: usize,
+    store: Option<Store>,
+    records: Mutex<HashMap<String, AlarmRecord>>,
+}
+
+impl History {
+    pub fn open(config: &HistoryConfig) -> io::Result<History> {
+        let mut records = HashMap::new();
+        let store = match config.directory {
+            Some(ref directory) => {
+                let store = Store::open(directory)?;
+                for record in store.load()? {
+                    records.insert(record.key.clone(), record);
+                }
+                Some(store)
+            }
+            None => None,
+        };
+
+        Ok(History {
+            max_transitions: config.max_transitions,
+            store: store,
+            records: Mutex::new(records),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<HashMap<String, AlarmRecord>> {
+        self.records.lock().expect("History lock poisoned")
+    }
+
+    fn save(&self, record: &AlarmRecord) -> io::Result<()> {
+        match self.store {
+            Some(ref store) => store.save(record),
+            None => Ok(()),
+        }
+    }
+
+    // Puts a record back on disk as it was before a change which failed,
+    // `previous` being `None` for a new alarm.
+    fn restore(&self, key: &str, previous: Option<&AlarmRecord>) {
+        let store = match self.store {
+            Some(ref store) => store,
+            None => return,
+        };
+        let restored = match previous {
+            Some(record) => store.save(record),
+            None => store.remove(key),
+        };
+        if let Err(err) = restored {
+            let _ = writeln!(io::stderr(), "cannot restore the history of {}: {}", key, err);
+        }
+    }
+
+    // The history only changes once it is on disk and `then` queued what the
+    // transition calls for, it is left as it was otherwise so that SNS's
+    // retry finds it so. Flapping is detected with the threshold of the
+    // route, if it has one.
+    pub fn record<F>(
+        &self,
+        alarm: &AlarmDetails,
+        notification: &str,
+        threshold: Option<&FlappingThreshold>,
+        then: F,
+    ) -> io::Result<Recorded>
+    where
+        F: FnOnce(&Recorded) -> io::Result<()>,
+    {
+        let key = key(alarm);
+        let mut records = self.lock();
+        let previous = records.get(&key).cloned();
+        let mut record = previous.clone().unwrap_or_else(|| AlarmRecord::new(alarm));
+
+        let recorded = record.add(alarm, notification, threshold, self.max_transitions);
+        if recorded == Recorded::Duplicate {
+            then(&recorded)?;
+            return Ok(recorded);
+        }
+        self.save(&record)?;
+        if let Err(err) = then(&recorded) {
+            self.restore(&key, previous.as_ref());
+            return Err(err);
+        }
+        records.insert(key, record);
+        Ok(recorded)
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, key: &str) -> Option<AlarmRecord> {
+        self.lock().get(key).cloned()
+    }
+
+    // By key, that is by account and region for the ARNs.
+    pub fn all(&self) -> Vec<AlarmRecord> {
+        let mut records: Vec<AlarmRecord> = self.lock().values().cloned().collect();
+        records.sort_by(|a, b| a.key.cmp(&b.key));
+        records
+    }
+
+    // The alarms currently in ALARM or INSUFFICIENT_DATA.
+    pub fn firing(&self) -> Vec<AlarmRecord> {
+        self.all().into_iter().filter(|record| record.state != AlarmState::Ok).collect()
+    }
+
+    // Flapping alarms which didn't change state for their window stop
+    // flapping once `then` queued their summary, and are returned with how
+    // they flapped.
+    pub fn settle<F>(&self, now: DateTime<Utc>, then: F) -> io::Result<Vec<(AlarmRecord, Flapping)>>
+    where
+        F: FnOnce(&[(AlarmRecord, Flapping)]) -> io::Result<()>,
+    {
+        let mut records = self.lock();
+        let mut settled = Vec::new();
+        for record in records.values() {
+            let quiet = match record.flapping {
+                Some(ref flapping) => parse_time(&record.since).map_or(true, |since| {
+                    now.signed_duration_since(since.with_timezone(&Utc)).num_seconds() >= flapping.window_secs as i64
+                }),
+                None => false,
+            };
+            if !quiet {
+                continue;
+            }
+
+            let mut stable = record.clone();
+            if let Some(flapping) = stable.flapping.take() {
+                settled.push((stable, flapping));
+            }
+        }
+        settled.sort_by(|a, b| a.0.key.cmp(&b.0.key));
+
+        let mut result = Ok(());
+        let mut saved = 0;
+        for &(ref record, _) in &settled {
+            result = self.save(record);
+            if result.is_err() {
+                break;
+            }
+            saved += 1;
+        }
+        if result.is_ok() {
+            result = then(&settled);
+        }
+        if let Err(err) = result {
+            for &(ref record, _) in &settled[..saved] {
+                self.restore(&record.key, records.get(&record.key));
+            }
+            return Err(err);
+        }
+
+        for &(ref record, _) in &settled {
+            records.insert(record.key.clone(), record.clone());
+        }
+        Ok(settled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("cloudwatch_bot_test_history_{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn history(directory: Option<PathBuf>) -> History {
+        History::open(&HistoryConfig {
+            directory: directory,
+            max_transitions: 3,
+        }).unwrap()
+    }
+
+    fn alarm_at(new_state: &str, previous_state: &str, timestamp: &str) -> AlarmDetails {
+        let mut alarm = alarm_details(new_state, previous_state);
+        alarm.timestamp = timestamp.to_string();
+        alarm
+    }
+
+    #[test]
+    fn test_key() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        assert_eq!(key(&alarm), "arn:aws:cloudwatch:us-east-1:097958131044:alarm:[RTB-US] UnHealthyHostCount");
+
+        alarm.arn = None;
+        assert_eq!(key(&alarm), "097958131044:us-east-1:[RTB-US] UnHealthyHostCount");
+    }
+
+    #[test]
+    fn test_latest_state() {
+        let history = history(None);
+
+        let recorded = history
+            .record(&alarm_at("ALARM", "OK", "2016-02-27T11:21:10.602+0000"), "", None, |_| Ok(()))
+            .unwrap();
+        assert_eq!(recorded, Recorded::Latest);
+        let recorded = history
+            .record(&alarm_at("OK", "ALARM", "2016-02-27T11:33:10.602+0000"), "", None, |_| Ok(()))
+            .unwrap();
+        assert_eq!(recorded, Recorded::Latest);
+
+        let key = key(&alarm_details("OK", "ALARM"));
+        let record = history.get(&key).unwrap();
+        assert_eq!(record.state, AlarmState::Ok);
+        assert_eq!(record.since, "2016-02-27T11:33:10.602+0000");
+        assert_eq!(record.transitions.len(), 2);
+        assert_eq!(history.firing().is_empty(), true);
+    }
+
+    #[test]
+    fn test_out_of_order_and_duplicates() {
+        let history = history(None);
+
+        history.record(&alarm_at("OK", "ALARM", "2016-02-27T12:33:10.602+0100"), "", None, |_| Ok(())).unwrap();
+        let recorded = history
+            .record(&alarm_at("ALARM", "OK", "2016-02-27T11:21:10.602+0000"), "", None, |_| Ok(()))
+            .unwrap();
+        assert_eq!(recorded, Recorded::Stale);
+        let recorded = history
+            .record(&alarm_at("ALARM", "OK", "2016-02-27T11:21:10.602+0000"), "", None, |_| Ok(()))
+            .unwrap();
+        assert_eq!(recorded, Recorded::Duplicate);
+
+        let record = history.get(&key(&alarm_details("OK", "ALARM"))).unwrap();
+        assert_eq!(record.state, AlarmState::Ok);
+        let states: Vec<&AlarmState> = record.transitions.iter().map(|t| &t.to).collect();
+        assert_eq!(states, vec![&AlarmState::Alarm, &AlarmState::Ok]);
+    }
+
+    #[test]
+    fn test_oldest_transitions_go_first() {
+        let history = history(None);
+        for minute in 0..5 {
+            let state = if minute % 2 == 0 { ("ALARM", "OK") } else { ("OK", "ALARM") };
+            let alarm = alarm_at(state.0, state.1, &format!("2016-02-27T11:0{}:00.000+0000", minute));
+            history.record(&alarm, "", None, |_| Ok(())).unwrap();
+        }
+
+        let record = history.get(&key(&alarm_details("OK", "ALARM"))).unwrap();
+        assert_eq!(record.state, AlarmState::Alarm);
+        assert_eq!(record.transitions.len(), 3);
+        assert_eq!(record.transitions[0].timestamp, "2016-02-27T11:02:00.000+0000");
+        assert_eq!(history.firing().len(), 1);
+    }
+
+    #[test]
+    fn test_flapping() {
+        let history = history(None);
+        let threshold = FlappingThreshold {
+            transitions: 3,
+            window_secs: 600,
+        };
+        let record = |new_state, previous_state, time: &str| {
+            let alarm = alarm_at(new_state, previous_state, &format!("2016-02-27T{}:00.000+0000", time));
+            history.record(&alarm, time, Some(&threshold), |_| Ok(())).unwrap()
+        };
+
+        assert_eq!(record("ALARM", "OK", "11:00"), Recorded::Latest);
+        assert_eq!(record("OK", "ALARM", "11:05"), Recorded::Latest);
+        assert_eq!(record("ALARM", "OK", "11:20"), Recorded::Latest);
+        assert_eq!(record("OK", "ALARM", "11:25"), Recorded::Latest);
+        let flapping = match record("ALARM", "OK", "11:30") {
+            Recorded::StartedFlapping(flapping) => flapping,
+            recorded => panic!("{:?}", recorded),
+        };
+        assert_eq!(flapping.transitions, 3);
+        assert_eq!(flapping.since, "2016-02-27T11:30:00.000+0000");
+        assert_eq!(record("OK", "ALARM", "11:31"), Recorded::Flapping);
+        assert_eq!(record("OK", "ALARM", "11:31"), Recorded::Flapping);
+        assert_eq!(record("ALARM", "OK", "11:32"), Recorded::Flapping);
+    }
+
+    #[test]
+    fn test_settle() {
+        let history = history(None);
+        let threshold = FlappingThreshold {
+            transitions: 2,
+            window_secs: 600,
+        };
+        history
+            .record(&alarm_at("ALARM", "OK", "2016-02-27T11:00:00.000+0000"), "1", Some(&threshold), |_| Ok(()))
+            .unwrap();
+        history
+            .record(&alarm_at("OK", "ALARM", "2016-02-27T11:01:00.000+0000"), "2", Some(&threshold), |_| Ok(()))
+            .unwrap();
+        history
+            .record(&alarm_at("ALARM", "OK", "2016-02-27T11:02:00.000+0000"), "3", Some(&threshold), |_| Ok(()))
+            .unwrap();
+
+        let at = |time: &str| {
+            history
+                .settle(format!("2016-02-27T{}Z", time).parse().unwrap(), |_| Ok(()))
+                .unwrap()
+        };
+        assert_eq!(at("11:11:59").is_empty(), true);
+        let settled = at("11:12:00");
+        assert_eq!(settled.len(), 1);
+        let (ref record, ref flapping) = settled[0];
+        assert_eq!(record.state, AlarmState::Alarm);
+        assert_eq!(record.flapping, None);
+        assert_eq!(flapping.since, "2016-02-27T11:01:00.000+0000");
+        assert_eq!(flapping.suppressed, 1);
+        assert_eq!(flapping.notification, "3");
+        assert_eq!(at("12:00:00").is_empty(), true);
+    }
+
+    fn fail<T: ?Sized>(_: &T) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "disk full"))
+    }
+
+    #[test]
+    fn test_failed_queueing_leaves_history_unchanged() {
+        let directory = directory("rollback");
+        let reopen = || history(Some(directory.clone()));
+        let history = reopen();
+        let key = key(&alarm_details("ALARM", "OK"));
+
+        assert_eq!(history.record(&alarm_details("ALARM", "OK"), "", None, fail).is_err(), true);
+        assert_eq!(history.get(&key), None);
+        assert_eq!(reopen().all().is_empty(), true);
+
+        let threshold = FlappingThreshold {
+            transitions: 2,
+            window_secs: 600,
+        };
+        history
+            .record(&alarm_at("ALARM", "OK", "2016-02-27T11:00:00.000+0000"), "1", Some(&threshold), |_| Ok(()))
+            .unwrap();
+        history
+            .record(&alarm_at("OK", "ALARM", "2016-02-27T11:01:00.000+0000"), "2", Some(&threshold), |_| Ok(()))
+            .unwrap();
+        let recorded = history.record(&alarm_at("ALARM", "OK", "2016-02-27T11:02:00.000+0000"), "3", None, fail);
+        assert_eq!(recorded.is_err(), true);
+        assert_eq!(history.get(&key).map(|record| record.transitions.len()), Some(2));
+
+        let now = "2016-02-27T12:00:00Z".parse().unwrap();
+        assert_eq!(history.settle(now, fail).is_err(), true);
+        assert_eq!(history.get(&key).map(|record| record.flapping.is_some()), Some(true));
+        assert_eq!(reopen().get(&key).map(|record| record.flapping.is_some()), Some(true));
+        assert_eq!(history.settle(now, |_| Ok(())).unwrap().len(), 1);
+        assert_eq!(history.get(&key).map(|record| record.flapping.is_some()), Some(false));
+    }
+
+    #[test]
+    fn test_history_survives_restarts() {
+        let directory = directory("restart");
+        history(Some(directory.clone())).record(&alarm_details("ALARM", "OK"), "", None, |_| Ok(())).unwrap();
+
+        let history = history(Some(directory.clone()));
+        let records = history.all();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].state, AlarmState::Alarm);
+        assert_eq!(records[0].transitions, vec![Transition::from(&alarm_details("ALARM", "OK"))]);
+        assert_eq!(
+            history.record(&alarm_details("ALARM", "OK"), "", None, |_| Ok(())).unwrap(),
+            Recorded::Duplicate
+        );
+    }
+}
@@ -1,25 +1,74 @@
 use hyper::Client;
 use hyper::client::HttpConnector;
 use hyper_tls::HttpsConnector;
-use rocket::{self, Request, Rocket};
+use rocket::{self, Request, Rocket, State};
+use rocket::config::{Config as RocketConfig, Environment};
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest};
+use rocket::response::Failure;
+use rocket::response::content::JSON;
+use serde_json;
+use std::io::{self, Write};
+use std::sync::Arc;
 use std::sync::atomic::AtomicPtr;
 use tokio_core::reactor::Core;
 
+use config::{Config, SharedConfig};
+use dedup::{Claim, Dedup};
+use flapping::started_notice;
+use grouping::Groups;
+use history::{History, Recorded};
+use model::{AlarmDetails, MessageType, SubscriptionConfirmation};
+use queue::{Job, Queue};
+use services::confirm_subscription;
+
 struct TokioCore(AtomicPtr<Core>);
 struct HttpsClient(AtomicPtr<Client<HttpsConnector<HttpConnector>>>);
 
-pub(crate) fn setup_server() -> Rocket {
+pub(crate) fn setup_server(
+    config: Arc<SharedConfig>,
+    queue: Arc<Queue>,
+    dedup: Arc<Dedup>,
+    history: Arc<History>,
+    groups: Arc<Groups>,
+) -> Rocket {
+    let server = &config.current().server;
     let mut core = &mut Core::new().expect("Failed to init Tokio event loop");
     let handle = core.handle();
     let http_client = &mut Client::configure()
-        .connector(HttpsConnector::new(4, &handle).expect("Failed to create HTTPS connector"))
+        .connector(
+            HttpsConnector::new(server.https_threads, &handle).expect("Failed to create HTTPS connector"),
+        )
         .build(&handle);
 
-    rocket::ignite()
-        .mount("/", routes![health_check_route])
+    let environment = Environment::active().expect("Invalid ROCKET_ENV");
+    let rocket_config = RocketConfig::build(environment)
+        .address(server.address.clone())
+        .port(server.port)
+        .finalize()
+        .expect("Invalid server configuration");
+
+    rocket::custom(rocket_config, true)
+        .mount(
+            "/",
+            routes![
+                health_check_route,
+                sns_route,
+                dead_letters_route,
+                replay_route,
+                alarms_route,
+                firing_alarms_route
+            ],
+        )
         .catch(errors![not_found])
         .manage(TokioCore(AtomicPtr::new(core)))
         .manage(HttpsClient(AtomicPtr::new(http_client)))
+        .manage(config)
+        .manage(queue)
+        .manage(dedup)
+        .manage(history)
+        .manage(groups)
 }
 
 #[error(404)]
@@ -32,17 +81,313 @@ pub(crate) fn health_check_route() -> &'static str {
     "OK"
 }
 
+fn confirm(config: &Config, body: &str) -> Result<(), Failure> {
+    let confirmation: SubscriptionConfirmation = body.parse().map_err(|_| Failure(Status::BadRequest))?;
+    let topic_arns = &config.allowlist.topic_arns;
+    if !topic_arns.is_empty() && !topic_arns.contains(&confirmation.topic_arn) {
+        return Err(Failure(Status::Forbidden));
+    }
+
+    let mut core = Core::new().expect("Failed to init Tokio event loop");
+    let handle = core.handle();
+    let client = Client::configure()
+        .connector(HttpsConnector::new(1, &handle).expect("Failed to create HTTPS connector"))
+        .build(&handle);
+    confirm_subscription(&client, &mut core, &confirmation).map_err(|_| Failure(Status::BadGateway))
+}
+
+// Notifications are only acknowledged once their deliveries are queued, SNS
+// retries the ones which fail and the history doesn't keep them. Redeliveries
+// of the ones which didn't are acknowledged straight away, those arriving while
+// the first delivery is still processed are retried later in case it fails.
+// So are the transitions delivered after a later one of the same alarm. The
+// transition which makes an alarm flap is replaced by a notice, and the next
+// ones are muted until it settles. The chat destinations of a grouped route
+// get the alarm with the rest of its group.
+#[post("/", data = "<body>")]
+pub(crate) fn sns_route(
+    message_type: MessageType,
+    body: String,
+    config: State<Arc<SharedConfig>>,
+    queue: State<Arc<Queue>>,
+    dedup: State<Arc<Dedup>>,
+    history: State<Arc<History>>,
+    groups: State<Arc<Groups>>,
+) -> Result<&'static str, Failure> {
+    let config = config.current();
+    match message_type {
+        MessageType::Notification => {
+            let alarm: AlarmDetails = body.parse().map_err(|_| Failure(Status::BadRequest))?;
+            let message_id = &alarm.envelope.message_id;
+            let claim = dedup.claim(message_id).map_err(|err| {
+                let _ = writeln!(io::stderr(), "cannot check notification {}: {}", message_id, err);
+                Failure(Status::ServiceUnavailable)
+            })?;
+            match claim {
+                Claim::First => {}
+                Claim::Processed => return Ok("OK"),
+                Claim::InFlight => return Err(Failure(Status::ServiceUnavailable)),
+            }
+            if !config.allowlist.allows(&alarm) {
+                dedup.finish(message_id);
+                return Ok("OK");
+            }
+
+            let release = |action: &str, err: io::Error| {
+                let _ = writeln!(io::stderr(), "cannot {} notification {}: {}", action, message_id, err);
+                if let Err(err) = dedup.release(message_id) {
+                    let _ = writeln!(io::stderr(), "cannot release notification {}: {}", message_id, err);
+                }
+                Failure(Status::ServiceUnavailable)
+            };
+            let decision = config.router.route(&alarm);
+            history
+                .record(&alarm, &body, decision.flapping.as_ref(), |recorded| {
+                    let jobs = match *recorded {
+                        Recorded::Latest | Recorded::Duplicate => {
+                            let grouped = groups.join(&config, &decision, &alarm, &body)?;
+                            Job::all(&config, &decision, &alarm, &body)
+                                .into_iter()
+                                .filter(|job| !grouped.contains(&job.destination))
+                                .collect()
+                        }
+                        Recorded::StartedFlapping(ref flapping) => Job::notices(&config, &decision, &body, |l10n| {
+                            started_notice(l10n, &alarm, flapping)
+                        }),
+                        Recorded::Flapping | Recorded::Stale => Vec::new(),
+                    };
+                    queue.enqueue(jobs)
+                })
+                .map_err(|err| release("queue", err))?;
+            dedup.finish(message_id);
+            Ok("OK")
+        }
+        MessageType::SubscriptionConfirmation => confirm(&config, &body).map(|_| "OK"),
+        MessageType::UnsubscribeConfirmation => Ok("OK"),
+    }
+}
+
+// The bearer token of the request, if any.
+pub(crate) struct AdminToken(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<AdminToken, ()> {
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| if value.starts_with("Bearer ") { Some(value[7..].to_string()) } else { None });
+        Outcome::Success(AdminToken(token))
+    }
+}
+
+// The admin endpoints don't exist unless `server.admin_token` is set. The
+// comparison takes the same time whatever the given token is.
+fn authorize(token: &AdminToken, config: &Config) -> Result<(), Failure> {
+    match (config.server.admin_token.as_ref(), token.0.as_ref()) {
+        (None, _) => Err(Failure(Status::NotFound)),
+        (Some(expected), Some(given)) if expected.len() == given.len() &&
+            expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0 => Ok(()),
+        _ => Err(Failure(Status::Unauthorized)),
+    }
+}
+
+#[get("/admin/dead-letters")]
+pub(crate) fn dead_letters_route(
+    token: AdminToken,
+    config: State<Arc<SharedConfig>>,
+    queue: State<Arc<Queue>>,
+) -> Result<JSON<String>, Failure> {
+    authorize(&token, &config.current())?;
+    serde_json::to_string(&queue.dead_letters())
+        .map(JSON)
+        .map_err(|_| Failure(Status::InternalServerError))
+}
+
+#[post("/admin/dead-letters/<id>/replay")]
+pub(crate) fn replay_route(
+    id: String,
+    token: AdminToken,
+    config: State<Arc<SharedConfig>>,
+    queue: State<Arc<Queue>>,
+) -> Result<&'static str, Failure> {
+    authorize(&token, &config.current())?;
+    match queue.replay(&id) {
+        Ok(true) => Ok("OK"),
+        Ok(false) => Err(Failure(Status::NotFound)),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "cannot replay dead letter {}: {}", id, err);
+            Err(Failure(Status::InternalServerError))
+        }
+    }
+}
+
+#[get("/admin/alarms")]
+pub(crate) fn alarms_route(
+    token: AdminToken,
+    config: State<Arc<SharedConfig>>,
+    history: State<Arc<History>>,
+) -> Result<JSON<String>, Failure> {
+    authorize(&token, &config.current())?;
+    serde_json::to_string(&history.all())
+        .map(JSON)
+        .map_err(|_| Failure(Status::InternalServerError))
+}
+
+#[get("/admin/alarms/firing")]
+pub(crate) fn firing_alarms_route(
+    token: AdminToken,
+    config: State<Arc<SharedConfig>>,
+    history: State<Arc<History>>,
+) -> Result<JSON<String>, Failure> {
+    authorize(&token, &config.current())?;
+    serde_json::to_string(&history.firing())
+        .map(JSON)
+        .map_err(|_| Failure(Status::InternalServerError))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use config::{DedupConfig, HistoryConfig, QueueConfig};
+    use model::fixtures::sns_notification;
+    use notifiers::PostStore;
     use rocket::http::*;
     use rocket::testing::MockRequest;
 
+    const CONFIG: &str = "\
+default_destinations = [\"audit\"]
+
+[server]
+admin_token = \"secret\"
+
+[notifiers.audit]
+type = \"json_lines\"
+";
+
+    fn server(source: &str) -> (Rocket, Arc<Queue>) {
+        let config = Config::parse(source, &Arc::new(PostStore::default())).expect("Config should be valid");
+        let queue = Arc::new(Queue::open(&QueueConfig::default()).unwrap());
+        let dedup = Arc::new(Dedup::open(&DedupConfig::default()).unwrap());
+        let history = Arc::new(History::open(&HistoryConfig::default()).unwrap());
+        let groups = Arc::new(Groups::open(&QueueConfig::default()).unwrap());
+        (setup_server(Arc::new(SharedConfig::new(config)), queue.clone(), dedup, history, groups), queue)
+    }
+
     #[test]
     fn test_health_check() {
-        let rocket = setup_server();
+        let rocket = setup_server(
+            Arc::new(SharedConfig::new(Config::default())),
+            Arc::new(Queue::open(&QueueConfig::default()).unwrap()),
+            Arc::new(Dedup::open(&DedupConfig::default()).unwrap()),
+            Arc::new(History::open(&HistoryConfig::default()).unwrap()),
+            Arc::new(Groups::open(&QueueConfig::default()).unwrap()),
+        );
         let mut req = MockRequest::new(Method::Get, "/ping");
         let response = req.dispatch_with(&rocket);
         assert_eq!(response.status(), Status::Ok);
     }
+
+    #[test]
+    fn test_notification_is_queued() {
+        let (rocket, queue) = server(CONFIG);
+        let mut req = MockRequest::new(Method::Post, "/")
+            .header(Header::new("x-amz-sns-message-type", "Notification"))
+            .body(sns_notification("m1", "ALARM", "OK"));
+        let response = req.dispatch_with(&rocket);
+
+        assert_eq!(response.status(), Status::Ok);
+        let pending = queue.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].destination, "audit");
+    }
+
+    #[test]
+    fn test_redelivery_is_acknowledged() {
+        let (rocket, queue) = server(CONFIG);
+        for message_id in &["m1", "m1", "m2"] {
+            let mut req = MockRequest::new(Method::Post, "/")
+                .header(Header::new("x-amz-sns-message-type", "Notification"))
+                .body(sns_notification(message_id, "ALARM", "OK"));
+            assert_eq!(req.dispatch_with(&rocket).status(), Status::Ok);
+        }
+
+        assert_eq!(queue.pending().len(), 2);
+    }
+
+    #[test]
+    fn test_stale_transition_is_not_queued() {
+        let (rocket, queue) = server(CONFIG);
+        let recovery = sns_notification("m1", "OK", "ALARM");
+        let late = sns_notification("m2", "ALARM", "OK").replace("T11:21:10", "T11:01:10");
+        for body in &[recovery, late] {
+            let mut req = MockRequest::new(Method::Post, "/")
+                .header(Header::new("x-amz-sns-message-type", "Notification"))
+                .body(body);
+            assert_eq!(req.dispatch_with(&rocket).status(), Status::Ok);
+        }
+
+        assert_eq!(queue.pending().len(), 1);
+    }
+
+    #[test]
+    fn test_grouped_alarm_waits_for_its_group() {
+        let source = format!(
+            "{}
+[notifiers.ops]
+type = \"discord\"
+webhook_url = \"https://discord.com/api/webhooks/1/xxx\"
+
+[[routes]]
+name = \"rtb\"
+destinations = [\"ops\", \"audit\"]
+group = {{ by = \"name_prefix\", window_secs = 30 }}
+",
+            CONFIG
+        );
+        let (rocket, queue) = server(&source);
+        let mut req = MockRequest::new(Method::Post, "/")
+            .header(Header::new("x-amz-sns-message-type", "Notification"))
+            .body(sns_notification("m1", "ALARM", "OK"));
+        assert_eq!(req.dispatch_with(&rocket).status(), Status::Ok);
+
+        let pending = queue.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].destination, "audit");
+    }
+
+    #[test]
+    fn test_invalid_notification() {
+        let (rocket, queue) = server(CONFIG);
+        let mut req = MockRequest::new(Method::Post, "/")
+            .header(Header::new("x-amz-sns-message-type", "Notification"))
+            .body("{}");
+        let response = req.dispatch_with(&rocket);
+
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(queue.pending().is_empty(), true);
+    }
+
+    #[test]
+    fn test_admin_token() {
+        let (rocket, _) = server(CONFIG);
+        let mut req = MockRequest::new(Method::Get, "/admin/dead-letters");
+        assert_eq!(req.dispatch_with(&rocket).status(), Status::Unauthorized);
+
+        let mut req = MockRequest::new(Method::Get, "/admin/dead-letters")
+            .header(Header::new("Authorization", "Bearer secret"));
+        let mut response = req.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body().and_then(|body| body.into_string()), Some("[]".to_string()));
+
+        let mut req = MockRequest::new(Method::Post, "/admin/dead-letters/nope/replay")
+            .header(Header::new("Authorization", "Bearer secret"));
+        assert_eq!(req.dispatch_with(&rocket).status(), Status::NotFound);
+
+        let (rocket, _) = server("");
+        let mut req = MockRequest::new(Method::Get, "/admin/dead-letters")
+            .header(Header::new("Authorization", "Bearer secret"));
+        assert_eq!(req.dispatch_with(&rocket).status(), Status::NotFound);
+    }
 }
@@ -0,0 +1,123 @@
+// A small subset of Fluent (https://projectfluent.org): one `id = value`
+// message per line, `#` comments and `{ $variable }` placeables.
+use std::collections::HashMap;
+
+#[derive(Debug, PartialEq)]
+enum Piece {
+    Text(String),
+    Variable(String),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Catalog {
+    messages: HashMap<String, Vec<Piece>>,
+}
+
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty() &&
+        name.chars().all(|c| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => true,
+            _ => false,
+        })
+}
+
+fn parse_value(value: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            pieces.push(Piece::Text(rest[..start].to_string()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| "unterminated placeable".to_string())?;
+        let placeable = rest[start + 1..start + end].trim();
+        if !placeable.starts_with('$') || !is_identifier(&placeable[1..]) {
+            return Err(format!("unsupported placeable `{{{}}}`", &rest[start + 1..start + end]));
+        }
+        pieces.push(Piece::Variable(placeable[1..].to_string()));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Text(rest.to_string()));
+    }
+    Ok(pieces)
+}
+
+impl Catalog {
+    pub fn parse(source: &str) -> Result<Catalog, String> {
+        let mut messages = HashMap::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim_right();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+
+            let mut parts = line.splitn(2, '=');
+            let id = parts.next().unwrap_or("").trim();
+            let value = parts
+                .next()
+                .ok_or_else(|| error("expected `id = value`".to_string()))?
+                .trim_left();
+            if !is_identifier(id) {
+                return Err(error(format!("invalid message id `{}`", id)));
+            }
+            let pieces = parse_value(value).map_err(&error)?;
+            if messages.insert(id.to_string(), pieces).is_some() {
+                return Err(error(format!("duplicate message `{}`", id)));
+            }
+        }
+        Ok(Catalog { messages: messages })
+    }
+
+    #[cfg(test)]
+    pub fn ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.messages.keys().map(String::as_str).collect();
+        ids.sort();
+        ids
+    }
+
+    // Missing variables are rendered as `{$name}`, like Fluent does.
+    pub fn format(&self, id: &str, args: &[(&str, &str)]) -> Option<String> {
+        self.messages.get(id).map(|pieces| {
+            pieces
+                .iter()
+                .map(|piece| match *piece {
+                    Piece::Text(ref text) => text.clone(),
+                    Piece::Variable(ref name) => args.iter()
+                        .find(|&&(arg, _)| arg == name)
+                        .map_or_else(|| format!("{{${}}}", name), |&(_, value)| value.to_string()),
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let catalog = Catalog::parse("# Comment\n\ngreeting = Hello { $name }!\nbye=Bye\n").unwrap();
+
+        assert_eq!(catalog.format("greeting", &[("name", "ops")]), Some("Hello ops!".to_string()));
+        assert_eq!(catalog.format("greeting", &[]), Some("Hello {$name}!".to_string()));
+        assert_eq!(catalog.format("bye", &[]), Some("Bye".to_string()));
+        assert_eq!(catalog.format("nope", &[]), None);
+        assert_eq!(catalog.ids(), vec!["bye", "greeting"]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Catalog::parse("a = 1\nb\n").err(), Some("line 2: expected `id = value`".to_string()));
+        assert_eq!(Catalog::parse("a b = 1\n").err(), Some("line 1: invalid message id `a b`".to_string()));
+        assert_eq!(Catalog::parse("a = { $b\n").err(), Some("line 1: unterminated placeable".to_string()));
+        assert_eq!(
+            Catalog::parse("a = { b }\n").err(),
+            Some("line 1: unsupported placeable `{ b }`".to_string())
+        );
+        assert_eq!(Catalog::parse("a = 1\na = 2\n").err(), Some("line 2: duplicate message `a`".to_string()));
+    }
+}
@@ -0,0 +1,220 @@
+mod catalog;
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use model::{AlarmDetails, AlarmState, ComparisonOperator, EnumResult, ParseEnumError};
+use self::catalog::Catalog;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub(crate) enum Locale {
+    EnUs,
+    FrFr,
+    JaJp,
+}
+
+lazy_static! {
+    static ref LOCALES: HashMap<&'static str, Locale> = {
+        let mut map = HashMap::new();
+        map.insert("en", Locale::EnUs);
+        map.insert("en-US", Locale::EnUs);
+        map.insert("fr", Locale::FrFr);
+        map.insert("fr-FR", Locale::FrFr);
+        map.insert("ja", Locale::JaJp);
+        map.insert("ja-JP", Locale::JaJp);
+        map
+    };
+
+    static ref CATALOGS: HashMap<Locale, Catalog> = {
+        let mut map = HashMap::new();
+        map.insert(Locale::EnUs, include_str!("../../locales/en-US.ftl"));
+        map.insert(Locale::FrFr, include_str!("../../locales/fr-FR.ftl"));
+        map.insert(Locale::JaJp, include_str!("../../locales/ja-JP.ftl"));
+        map.into_iter()
+            .map(|(locale, source)| {
+                (locale, Catalog::parse(source).expect("Bundled message catalogs should be valid"))
+            })
+            .collect()
+    };
+}
+
+impl FromStr for Locale {
+    type Err = ParseEnumError<Self>;
+
+    fn from_str(locale: &str) -> EnumResult<Locale> {
+        LOCALES.get(locale).cloned().ok_or(ParseEnumError {
+            value: locale.to_string(),
+            mapping: LOCALES.clone(),
+        })
+    }
+}
+
+// Language and time zone of the messages sent to a destination.
+#[derive(Debug, Clone)]
+pub(crate) struct Localizer {
+    pub locale: Locale,
+    pub time_zone: Tz,
+}
+
+impl Default for Localizer {
+    fn default() -> Localizer {
+        Localizer {
+            locale: Locale::EnUs,
+            time_zone: Tz::UTC,
+        }
+    }
+}
+
+impl Localizer {
+    // Messages missing from a translation fall back to English, then to
+    // their ID.
+    pub fn message(&self, id: &str, args: &[(&str, &str)]) -> String {
+        CATALOGS[&self.locale]
+            .format(id, args)
+            .or_else(|| CATALOGS[&Locale::EnUs].format(id, args))
+            .unwrap_or_else(|| id.to_string())
+    }
+
+    pub fn text(&self, id: &str) -> String {
+        self.message(id, &[])
+    }
+
+    pub fn state(&self, state: &AlarmState) -> String {
+        self.text(match *state {
+            AlarmState::Ok => "state-ok",
+            AlarmState::Alarm => "state-alarm",
+            AlarmState::InsufficientData => "state-insufficient-data",
+        })
+    }
+
+    pub fn was_state(&self, state: &AlarmState) -> String {
+        self.message("was-state", &[("state", &self.state(state))])
+    }
+
+    pub fn comparison(&self, op: &ComparisonOperator) -> String {
+        self.text(match *op {
+            ComparisonOperator::GreaterThanOrEqualToThreshold => "comparison-greater-than-or-equal",
+            ComparisonOperator::GreaterThanThreshold => "comparison-greater-than",
+            ComparisonOperator::LessThanThreshold => "comparison-less-than",
+            ComparisonOperator::LessThanOrEqualToThreshold => "comparison-less-than-or-equal",
+            ComparisonOperator::Other(ref op) => return op.clone(),
+        })
+    }
+
+    // The metric compared to the threshold, "UnHealthyHostCount >= 1".
+    pub fn condition(&self, alarm: &AlarmDetails) -> String {
+        let trigger = &alarm.trigger;
+        self.message(
+            "condition",
+            &[
+                ("metric", &trigger.metric_name),
+                ("comparison", &self.comparison(&trigger.op)),
+                ("threshold", &trigger.threshold.to_string()),
+            ],
+        )
+    }
+
+    pub fn threshold(&self, alarm: &AlarmDetails) -> String {
+        let trigger = &alarm.trigger;
+        self.message(
+            "threshold",
+            &[("comparison", &self.comparison(&trigger.op)), ("threshold", &trigger.threshold.to_string())],
+        )
+    }
+
+    pub fn period(&self, alarm: &AlarmDetails) -> String {
+        let trigger = &alarm.trigger;
+        self.message(
+            "period",
+            &[("count", &trigger.nb_periods.to_string()), ("seconds", &trigger.period.to_string())],
+        )
+    }
+
+    // The state change time in the destination's time zone, as sent by
+    // CloudWatch when it cannot be parsed.
+    pub fn timestamp(&self, alarm: &AlarmDetails) -> String {
+        match DateTime::parse_from_str(&alarm.timestamp, "%Y-%m-%dT%H:%M:%S%.f%z") {
+            Ok(time) => time.with_timezone(&self.time_zone)
+                .format(&self.text("timestamp-format"))
+                .to_string(),
+            Err(_) => alarm.timestamp.clone(),
+        }
+    }
+
+    pub fn transition(&self, alarm: &AlarmDetails) -> String {
+        self.message(
+            "transition",
+            &[
+                ("previous", &self.state(&alarm.previous_state)),
+                ("new", &self.state(&alarm.new_state)),
+                ("time", &self.timestamp(alarm)),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    fn localizer(locale: &str, time_zone: &str) -> Localizer {
+        Localizer {
+            locale: locale.parse().unwrap(),
+            time_zone: time_zone.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_parse_locale() {
+        assert_eq!("fr".parse::<Locale>(), Ok(Locale::FrFr));
+        assert_eq!("ja-JP".parse::<Locale>(), Ok(Locale::JaJp));
+        assert_eq!(
+            "de".parse::<Locale>().unwrap_err().to_string().starts_with("'de' does not match allowed enum values"),
+            true
+        );
+    }
+
+    #[test]
+    fn test_catalogs_define_the_same_messages() {
+        let english = CATALOGS[&Locale::EnUs].ids();
+        for catalog in CATALOGS.values() {
+            assert_eq!(catalog.ids(), english);
+        }
+    }
+
+    #[test]
+    fn test_english_is_unchanged() {
+        let alarm = alarm_details("ALARM", "OK");
+        let l10n = Localizer::default();
+
+        assert_eq!(l10n.state(&alarm.new_state), alarm.new_state.to_string());
+        assert_eq!(l10n.condition(&alarm), "UnHealthyHostCount >= 1");
+        assert_eq!(l10n.period(&alarm), "5 x 60s");
+        assert_eq!(l10n.transition(&alarm), "OK -> ALARM at 2016-02-27 11:21:10 UTC");
+    }
+
+    #[test]
+    fn test_translations() {
+        let alarm = alarm_details("ALARM", "OK");
+        let french = localizer("fr", "Europe/Paris");
+        let japanese = localizer("ja", "Asia/Tokyo");
+
+        assert_eq!(french.condition(&alarm), "UnHealthyHostCount supérieur ou égal à 1");
+        assert_eq!(french.was_state(&alarm.previous_state), "(précédemment OK)");
+        assert_eq!(french.timestamp(&alarm), "27/02/2016 12:21:10 CET");
+        assert_eq!(japanese.condition(&alarm), "UnHealthyHostCount が 1 以上");
+        assert_eq!(japanese.state(&alarm.new_state), "アラーム");
+        assert_eq!(japanese.timestamp(&alarm), "2016年02月27日 20:21:10 JST");
+    }
+
+    #[test]
+    fn test_unparseable_timestamp() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.timestamp = "yesterday".to_string();
+
+        assert_eq!(Localizer::default().timestamp(&alarm), "yesterday");
+    }
+}
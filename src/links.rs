@@ -0,0 +1,241 @@
+use chrono::{DateTime, Duration, Utc};
+use percent_encoding::{USERINFO_ENCODE_SET, utf8_percent_encode};
+use serde_json::{Number, Value};
+use std::cmp;
+use std::collections::HashMap;
+
+use model::AlarmDetails;
+
+// The graphs start at least this long before the state change, and go on
+// for half as long after it.
+const MIN_GRAPH_WINDOW_SECS: i64 = 3600;
+
+define_encode_set! {
+    // Same behaviour as JavaScript's `encodeURIComponent`, which is what the
+    // AWS console expects in its URL fragments.
+    pub COMPONENT_ENCODE_SET = [USERINFO_ENCODE_SET] | {'&', '+', ',', '$'}
+}
+
+pub(crate) fn encode_component(value: &str) -> String {
+    utf8_percent_encode(value, COMPONENT_ENCODE_SET).to_string()
+}
+
+pub(crate) fn alarm_console_url(alarm: &AlarmDetails) -> Option<String> {
+    alarm.region_code().map(|region| {
+        format!(
+            "https://console.aws.amazon.com/cloudwatch/home?region={}#alarmsV2:alarm/{}",
+            region,
+            encode_component(&alarm.name)
+        )
+    })
+}
+
+// Logs Insights query to link to for the alarms of a namespace.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct LogsInsightsQuery {
+    pub log_groups: Vec<String>,
+    pub query: String,
+}
+
+// Logs Insights queries by namespace.
+pub(crate) type LogsInsightsQueries = HashMap<String, LogsInsightsQuery>;
+
+fn jsurl_number(number: &Number) -> String {
+    match number.as_f64() {
+        Some(float) if float.fract() == 0.0 && float.abs() < 1e15 => format!("{}", float as i64),
+        _ => number.to_string(),
+    }
+}
+
+// Same escaping as JSURL's, over UTF-16 code units like JavaScript.
+fn jsurl_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for unit in text.encode_utf16() {
+        match unit {
+            0x30...0x39 | 0x41...0x5A | 0x61...0x7A | 0x2D | 0x2E | 0x5F => escaped.push(unit as u8 as char),
+            0x24 => escaped.push('!'),
+            0...0xFF => escaped.push_str(&format!("*{:02x}", unit)),
+            _ => escaped.push_str(&format!("**{:04x}", unit)),
+        }
+    }
+    escaped
+}
+
+// JSURL, the compact JSON encoding of the CloudWatch console URL fragments.
+// https://github.com/Sage/jsurl
+pub(crate) fn jsurl(value: &Value) -> String {
+    match *value {
+        Value::Null => "~null".to_string(),
+        Value::Bool(boolean) => format!("~{}", boolean),
+        Value::Number(ref number) => format!("~{}", jsurl_number(number)),
+        Value::String(ref text) => format!("~'{}", jsurl_escape(text)),
+        Value::Array(ref values) if values.is_empty() => "~(~)".to_string(),
+        Value::Array(ref values) => format!("~({})", values.iter().map(jsurl).collect::<Vec<_>>().concat()),
+        Value::Object(ref map) => {
+            let members: Vec<String> = map.iter().map(|(key, value)| format!("{}{}", jsurl_escape(key), jsurl(value))).collect();
+            format!("~({})", members.join("~"))
+        }
+    }
+}
+
+fn state_change_time(alarm: &AlarmDetails) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_str(&alarm.timestamp, "%Y-%m-%dT%H:%M:%S%.f%z")
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// Start and end of the graphs: a few evaluation windows before the state
+// change, and half as long after it.
+fn graph_window(alarm: &AlarmDetails) -> Option<(String, String)> {
+    let trigger = &alarm.trigger;
+    let evaluation_secs = i64::from(trigger.period) * i64::from(trigger.nb_periods);
+    let before = cmp::max(evaluation_secs * 3, MIN_GRAPH_WINDOW_SECS);
+    let format = "%Y-%m-%dT%H:%M:%SZ";
+
+    state_change_time(alarm).map(|time| {
+        (
+            (time - Duration::seconds(before)).format(format).to_string(),
+            (time + Duration::seconds(before / 2)).format(format).to_string(),
+        )
+    })
+}
+
+// Alarm statistics are upper case in notifications ("SAMPLE_COUNT"), the
+// console wants them as in the API ("SampleCount").
+fn console_statistic(statistic: &str) -> &str {
+    match statistic {
+        "AVERAGE" => "Average",
+        "SUM" => "Sum",
+        "MINIMUM" => "Minimum",
+        "MAXIMUM" => "Maximum",
+        "SAMPLE_COUNT" => "SampleCount",
+        other => other,
+    }
+}
+
+// Graph of the alarm metric around the state change, with the threshold.
+pub(crate) fn metric_graph_url(alarm: &AlarmDetails) -> Option<String> {
+    let trigger = &alarm.trigger;
+    let mut metric = vec![json!(trigger.namespace), json!(trigger.metric_name)];
+    for dimension in &trigger.dimensions {
+        metric.push(json!(dimension.name));
+        metric.push(json!(dimension.value));
+    }
+
+    alarm.region_code().and_then(|region| {
+        graph_window(alarm).map(|(start, end)| {
+            let graph = json!({
+                "metrics": [metric],
+                "view": "timeSeries",
+                "stat": console_statistic(&trigger.statistic),
+                "period": trigger.period,
+                "region": region,
+                "start": start,
+                "end": end,
+                "annotations": {"horizontal": [{"label": "Threshold", "value": trigger.threshold}]},
+            });
+            format!(
+                "https://console.aws.amazon.com/cloudwatch/home?region={}#metricsV2:graph={}",
+                region,
+                jsurl(&graph)
+            )
+        })
+    })
+}
+
+// Logs Insights query over the same window as the metric graph.
+pub(crate) fn logs_insights_url(alarm: &AlarmDetails, query: &LogsInsightsQuery) -> Option<String> {
+    alarm.region_code().and_then(|region| {
+        graph_window(alarm).map(|(start, end)| {
+            let detail = json!({
+                "start": start,
+                "end": end,
+                "timeType": "ABSOLUTE",
+                "tz": "UTC",
+                "editorString": query.query,
+                "source": query.log_groups,
+            });
+            // The console expects the query string of the fragment to be
+            // escaped with `$` instead of `%`.
+            format!(
+                "https://console.aws.amazon.com/cloudwatch/home?region={}#logsV2:logs-insights$3FqueryDetail$3D{}",
+                region,
+                jsurl(&detail)
+            )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    #[test]
+    fn test_encode_component() {
+        assert_eq!(encode_component("[RTB-US] a&b+c"), "%5BRTB-US%5D%20a%26b%2Bc");
+    }
+
+    #[test]
+    fn test_alarm_console_url() {
+        let alarm = alarm_details("ALARM", "OK");
+        assert_eq!(
+            alarm_console_url(&alarm),
+            Some(
+                "https://console.aws.amazon.com/cloudwatch/home?region=us-east-1#alarmsV2:alarm/%5BRTB-US%5D%20UnHealthyHostCount"
+                    .to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_jsurl() {
+        assert_eq!(
+            jsurl(&json!({"a": [1, 2.5, "x/y z", true, null, []], "b": {"c": "$é€"}})),
+            "~(a~(~1~2.5~'x*2fy*20z~true~null~(~))~b~(c~'!*e9**20ac))"
+        );
+    }
+
+    #[test]
+    fn test_metric_graph_url() {
+        let alarm = alarm_details("ALARM", "OK");
+        assert_eq!(
+            metric_graph_url(&alarm),
+            Some(
+                "https://console.aws.amazon.com/cloudwatch/home?region=us-east-1#metricsV2:graph=\
+                 ~(annotations~(horizontal~(~(label~'Threshold~value~1)))~end~'2016-02-27T11*3a51*3a10Z\
+                 ~metrics~(~(~'AWS*2fELB~'UnHealthyHostCount~'LoadBalancerName~'rtb))~period~60~region~'us-east-1\
+                 ~start~'2016-02-27T10*3a21*3a10Z~stat~'Average~view~'timeSeries)"
+                    .to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_logs_insights_url() {
+        let alarm = alarm_details("ALARM", "OK");
+        let query = LogsInsightsQuery {
+            log_groups: vec!["/aws/elb/rtb".to_string()],
+            query: "fields @message | limit 20".to_string(),
+        };
+        assert_eq!(
+            logs_insights_url(&alarm, &query),
+            Some(
+                "https://console.aws.amazon.com/cloudwatch/home?region=us-east-1#logsV2:logs-insights$3FqueryDetail$3D\
+                 ~(editorString~'fields*20*40message*20*7c*20limit*2020~end~'2016-02-27T11*3a51*3a10Z\
+                 ~source~(~'*2faws*2felb*2frtb)~start~'2016-02-27T10*3a21*3a10Z~timeType~'ABSOLUTE~tz~'UTC)"
+                    .to_string(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_alarm_console_url_unknown_region() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.arn = None;
+        alarm.region = "Moon - Dark Side".to_string();
+        assert_eq!(alarm_console_url(&alarm), None);
+        assert_eq!(metric_graph_url(&alarm), None);
+    }
+}
@@ -23,30 +23,135 @@
 // Extern crates //
 ///////////////////
 
+extern crate base64;
+extern crate chrono;
+extern crate chrono_tz;
 extern crate futures;
+extern crate glob;
+extern crate hmac;
 extern crate hyper;
 extern crate hyper_tls;
 #[macro_use]
 extern crate lazy_static;
+extern crate libc;
+extern crate native_tls;
+#[macro_use]
+extern crate percent_encoding;
+extern crate rand;
+extern crate regex;
 extern crate rocket;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
+extern crate sha2;
 extern crate tokio_core;
+extern crate toml;
 
 /////////////
 // Modules //
 /////////////
 
+mod config;
+mod dedup;
+mod flapping;
+mod grouping;
+mod history;
 mod http;
+mod l10n;
+mod links;
 mod model;
+mod notifiers;
+mod oncall;
+mod queue;
+mod routing;
 mod services;
+mod sparkline;
+mod templates;
 
 //////////
 // Main //
 //////////
 
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+
+use config::{Config, SharedConfig};
+use dedup::Dedup;
+use grouping::Groups;
+use history::History;
+use notifiers::PostStore;
+use queue::Queue;
+
+// The configuration file is the first argument, or `CLOUDWATCH_BOT_CONFIG`.
+// Without any, the bot starts with the defaults and no notifier.
+fn config_path() -> Option<PathBuf> {
+    env::args_os()
+        .nth(1)
+        .or_else(|| env::var_os("CLOUDWATCH_BOT_CONFIG"))
+        .map(PathBuf::from)
+}
+
 fn main() {
-    http::setup_server().launch();
+    let post_store = Arc::new(PostStore::default());
+    let config = match config_path() {
+        Some(path) => {
+            let config = match Config::load(&path, &post_store) {
+                Ok(config) => Arc::new(SharedConfig::new(config)),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "{}: {}", path.display(), err);
+                    process::exit(1);
+                }
+            };
+            config::watch(path, config.clone());
+            config
+        }
+        None => Arc::new(SharedConfig::new(Config::default())),
+    };
+    // Before the queue starts delivering, so that its recoveries find the
+    // messages posted before the restart.
+    if let Err(err) = post_store.open(&config.current().posts) {
+        let _ = writeln!(io::stderr(), "cannot open the posted messages: {}", err);
+        process::exit(1);
+    }
+
+    let queue = match Queue::open(&config.current().queue) {
+        Ok(queue) => Arc::new(queue),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "cannot open the delivery queue: {}", err);
+            process::exit(1);
+        }
+    };
+    queue::start(queue.clone(), config.clone());
+    let dedup = match Dedup::open(&config.current().dedup) {
+        Ok(dedup) => Arc::new(dedup),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "cannot open the deduplication store: {}", err);
+            process::exit(1);
+        }
+    };
+    dedup::start(dedup.clone());
+
+    let history = match History::open(&config.current().history) {
+        Ok(history) => Arc::new(history),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "cannot open the alarm history: {}", err);
+            process::exit(1);
+        }
+    };
+    flapping::start(history.clone(), queue.clone(), config.clone());
+    let groups = match Groups::open(&config.current().queue) {
+        Ok(groups) => Arc::new(groups),
+        Err(err) => {
+            let _ = writeln!(io::stderr(), "cannot open the alarm groups: {}", err);
+            process::exit(1);
+        }
+    };
+    grouping::start(groups.clone(), queue.clone(), config.clone());
+
+    http::setup_server(config, queue, dedup, history, groups).launch();
 }
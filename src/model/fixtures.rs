@@ -0,0 +1,48 @@
+use serde_json;
+
+use super::notification::AlarmDetails;
+
+pub(crate) fn alarm_message(new_state: &str, previous_state: &str) -> String {
+    format!(
+        "{{\
+            \"AlarmName\":\"[RTB-US] UnHealthyHostCount\",\
+            \"AlarmDescription\":\"UnHealthyHostCount\",\
+            \"AlarmArn\":\"arn:aws:cloudwatch:us-east-1:097958131044:alarm:[RTB-US] UnHealthyHostCount\",\
+            \"AWSAccountId\":\"097958131044\",\
+            \"NewStateValue\":\"{}\",\
+            \"NewStateReason\":\"Threshold Crossed: 1 datapoint (0.4482758620689655) was not greater than or equal to the threshold (1.0).\",\
+            \"StateChangeTime\":\"2016-02-27T11:21:10.602+0000\",\
+            \"Region\":\"US - N. Virginia\",\
+            \"OldStateValue\":\"{}\",\
+            \"Trigger\":{{\
+                \"MetricName\":\"UnHealthyHostCount\",\
+                \"Namespace\":\"AWS/ELB\",\
+                \"Statistic\":\"AVERAGE\",\
+                \"Unit\":null,\
+                \"Dimensions\":[{{\"name\":\"LoadBalancerName\",\"value\":\"rtb\"}}],\
+                \"Period\":60,\
+                \"EvaluationPeriods\":5,\
+                \"ComparisonOperator\":\"GreaterThanOrEqualToThreshold\",\
+                \"Threshold\":1.0\
+            }}\
+        }}",
+        new_state,
+        previous_state
+    )
+}
+
+pub(crate) fn alarm_details(new_state: &str, previous_state: &str) -> AlarmDetails {
+    serde_json::from_str(&alarm_message(new_state, previous_state)).expect("Fixture should be valid")
+}
+
+// The SNS notification carrying `alarm_message`, as POSTed by SNS.
+pub(crate) fn sns_notification(message_id: &str, new_state: &str, previous_state: &str) -> String {
+    json!({
+        "Type": "Notification",
+        "MessageId": message_id,
+        "TopicArn": "arn:aws:sns:us-east-1:097958131044:jabber-all",
+        "Subject": format!("{}: \"[RTB-US] UnHealthyHostCount\" in US - N. Virginia", new_state),
+        "Message": alarm_message(new_state, previous_state),
+        "Timestamp": "2016-02-27T11:21:10.645Z",
+    }).to_string()
+}
@@ -1,6 +1,11 @@
 mod errors;
+#[cfg(test)]
+pub(crate) mod fixtures;
 mod msg_type;
 mod notification;
 mod subscription_confirmation;
 
+pub(crate) use self::errors::{EnumResult, ParseEnumError};
+pub(crate) use self::msg_type::MessageType;
+pub(crate) use self::notification::{AlarmDetails, AlarmState, ComparisonOperator};
 pub use self::subscription_confirmation::SubscriptionConfirmation;
@@ -1,6 +1,7 @@
 use serde;
 use serde_json;
 use std::collections::HashMap;
+use std::fmt::{Display, Error, Formatter};
 use std::str::FromStr;
 
 use super::errors::*;
@@ -9,53 +10,106 @@ use super::errors::*;
 struct Notification {
     #[serde(rename = "Message")]
     message: String,
+    #[serde(rename = "MessageId")]
+    message_id: String,
+    #[serde(rename = "TopicArn")]
+    topic_arn: String,
+    #[serde(rename = "Subject", default)]
+    subject: Option<String>,
+    #[serde(rename = "Timestamp")]
+    timestamp: String,
+    #[serde(rename = "MessageAttributes", default)]
+    message_attributes: HashMap<String, MessageAttribute>,
+}
+
+// SNS metadata of the notification which carried the alarm.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Envelope {
+    pub message_id: String,
+    pub topic_arn: String,
+    pub subject: Option<String>,
+    pub timestamp: String,
+    pub message_attributes: HashMap<String, MessageAttribute>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct MessageAttribute {
+    #[serde(rename = "Type")]
+    pub kind: String,
+    #[serde(rename = "Value")]
+    pub value: String,
 }
 
 impl FromStr for AlarmDetails {
     type Err = serde_json::Error;
 
     fn from_str(str: &str) -> JsonResult<AlarmDetails> {
-        let notification: JsonResult<Notification> = serde_json::from_str(str);
-        notification.and_then(|n| serde_json::from_str(&n.message))
+        let notification: Notification = serde_json::from_str(str)?;
+        let mut details: AlarmDetails = serde_json::from_str(&notification.message)?;
+        details.envelope = Envelope {
+            message_id: notification.message_id,
+            topic_arn: notification.topic_arn,
+            subject: notification.subject,
+            timestamp: notification.timestamp,
+            message_attributes: notification.message_attributes,
+        };
+        Ok(details)
     }
 }
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct AlarmDetails {
     #[serde(rename = "AlarmName")]
-    name: String,
+    pub name: String,
     #[serde(rename = "AlarmDescription")]
-    description: String,
+    pub description: String,
+    #[serde(rename = "AlarmArn", default)]
+    pub arn: Option<String>,
+    #[serde(rename = "AWSAccountId")]
+    pub account_id: String,
+    #[serde(rename = "Region")]
+    pub region: String,
     #[serde(rename = "NewStateValue", deserialize_with = "AlarmState::deserialize_alarm_state")]
-    new_state: AlarmState,
+    pub new_state: AlarmState,
     #[serde(rename = "NewStateReason")]
-    reason: String,
+    pub reason: String,
     #[serde(rename = "StateChangeTime")]
-    timestamp: String,
+    pub timestamp: String,
     #[serde(rename = "OldStateValue", deserialize_with = "AlarmState::deserialize_alarm_state")]
-    previous_state: AlarmState,
+    pub previous_state: AlarmState,
     #[serde(rename = "Trigger")]
-    trigger: AlarmTrigger,
+    pub trigger: AlarmTrigger,
+    #[serde(skip_deserializing)]
+    pub envelope: Envelope,
+}
+
+impl AlarmDetails {
+    // SNS only gives the human readable region name ("US - N. Virginia"), but
+    // newer payloads also carry the alarm ARN, which holds the region code.
+    pub fn region_code(&self) -> Option<&str> {
+        let from_arn = self.arn.as_ref().and_then(|arn| arn.split(':').nth(3));
+        from_arn.or_else(|| REGION_CODES.get(self.region.as_str()).cloned())
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub(crate) struct AlarmTrigger {
     #[serde(rename = "MetricName")]
-    metric_name: String,
+    pub metric_name: String,
     #[serde(rename = "Namespace")]
-    namespace: String,
+    pub namespace: String,
     #[serde(rename = "Statistic")]
-    statistic: String,
+    pub statistic: String,
     #[serde(rename = "Dimensions")]
-    dimensions: Vec<Dimension>,
-    #[serde(rename = "ComparisonOperator")]
-    op: String,
+    pub dimensions: Vec<Dimension>,
+    #[serde(rename = "ComparisonOperator", deserialize_with = "ComparisonOperator::deserialize_comparison_operator")]
+    pub op: ComparisonOperator,
     #[serde(rename = "Period")]
-    period: u8,
+    pub period: u32,
     #[serde(rename = "EvaluationPeriods")]
-    nb_periods: u8,
+    pub nb_periods: u32,
     #[serde(rename = "Threshold")]
-    threshold: f64,
+    pub threshold: f64,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -100,15 +154,143 @@ impl AlarmState {
     }
 }
 
+// As in the notifications, for the alarm history.
+impl serde::Serialize for AlarmState {
+    fn serialize<S>(&self, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        ser.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for AlarmState {
+    fn deserialize<D>(de: D) -> Result<AlarmState, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        AlarmState::deserialize_alarm_state(de)
+    }
+}
+
+impl Display for AlarmState {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), Error> {
+        let state = match *self {
+            AlarmState::Ok => "OK",
+            AlarmState::Alarm => "ALARM",
+            AlarmState::InsufficientData => "INSUFFICIENT_DATA",
+        };
+        write!(fmt, "{}", state)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum ComparisonOperator {
+    GreaterThanOrEqualToThreshold,
+    GreaterThanThreshold,
+    LessThanThreshold,
+    LessThanOrEqualToThreshold,
+    // Anomaly detection bands (`LessThanLowerOrGreaterThanUpperThreshold`...)
+    // and any operator added later, shown as CloudWatch names it.
+    Other(String),
+}
+
+lazy_static! {
+    static ref COMPARISON_OPERATORS: HashMap<&'static str, ComparisonOperator> = {
+        let mut map = HashMap::new();
+        map.insert("GreaterThanOrEqualToThreshold", ComparisonOperator::GreaterThanOrEqualToThreshold);
+        map.insert("GreaterThanThreshold", ComparisonOperator::GreaterThanThreshold);
+        map.insert("LessThanThreshold", ComparisonOperator::LessThanThreshold);
+        map.insert("LessThanOrEqualToThreshold", ComparisonOperator::LessThanOrEqualToThreshold);
+        map
+    };
+}
+
+impl FromStr for ComparisonOperator {
+    type Err = ParseEnumError<Self>;
+
+    fn from_str(op: &str) -> EnumResult<ComparisonOperator> {
+        COMPARISON_OPERATORS.get(op).cloned().ok_or(ParseEnumError {
+            value: op.to_string(),
+            mapping: COMPARISON_OPERATORS.clone(),
+        })
+    }
+}
+
+impl ComparisonOperator {
+    fn deserialize_comparison_operator<'de, D>(de: D) -> Result<ComparisonOperator, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let deser_result = serde::Deserialize::deserialize(de)?;
+        match deser_result {
+            serde_json::Value::String(s) => Ok(s.parse().unwrap_or(ComparisonOperator::Other(s))),
+            _ => Err(serde::de::Error::custom("ComparisonOperator expects a string")),
+        }
+    }
+
+    pub fn symbol(&self) -> &str {
+        match *self {
+            ComparisonOperator::GreaterThanOrEqualToThreshold => ">=",
+            ComparisonOperator::GreaterThanThreshold => ">",
+            ComparisonOperator::LessThanThreshold => "<",
+            ComparisonOperator::LessThanOrEqualToThreshold => "<=",
+            ComparisonOperator::Other(ref op) => op,
+        }
+    }
+
+    pub fn breaches(&self, value: f64, threshold: f64) -> bool {
+        match *self {
+            ComparisonOperator::GreaterThanOrEqualToThreshold => value >= threshold,
+            ComparisonOperator::GreaterThanThreshold => value > threshold,
+            ComparisonOperator::LessThanThreshold => value < threshold,
+            ComparisonOperator::LessThanOrEqualToThreshold => value <= threshold,
+            // The band isn't in the payload.
+            ComparisonOperator::Other(_) => false,
+        }
+    }
+}
+
+lazy_static! {
+    static ref REGION_CODES: HashMap<&'static str, &'static str> = {
+        let mut map = HashMap::new();
+        map.insert("US East (N. Virginia)", "us-east-1");
+        map.insert("US - N. Virginia", "us-east-1");
+        map.insert("US East (Ohio)", "us-east-2");
+        map.insert("US West (N. California)", "us-west-1");
+        map.insert("US - N. California", "us-west-1");
+        map.insert("US West (Oregon)", "us-west-2");
+        map.insert("US - Oregon", "us-west-2");
+        map.insert("Canada (Central)", "ca-central-1");
+        map.insert("EU (Ireland)", "eu-west-1");
+        map.insert("EU - Ireland", "eu-west-1");
+        map.insert("EU (London)", "eu-west-2");
+        map.insert("EU (Frankfurt)", "eu-central-1");
+        map.insert("EU - Frankfurt", "eu-central-1");
+        map.insert("Asia Pacific (Tokyo)", "ap-northeast-1");
+        map.insert("Asia Pacific - Tokyo", "ap-northeast-1");
+        map.insert("Asia Pacific (Seoul)", "ap-northeast-2");
+        map.insert("Asia Pacific (Singapore)", "ap-southeast-1");
+        map.insert("Asia Pacific - Singapore", "ap-southeast-1");
+        map.insert("Asia Pacific (Sydney)", "ap-southeast-2");
+        map.insert("Asia Pacific - Sydney", "ap-southeast-2");
+        map.insert("Asia Pacific (Mumbai)", "ap-south-1");
+        map.insert("South America (Sao Paulo)", "sa-east-1");
+        map.insert("South America - Sao Paulo", "sa-east-1");
+        map
+    };
+}
+
 #[derive(Debug, Deserialize)]
 pub(crate) struct Dimension {
-    name: String,
-    value: String,
+    pub name: String,
+    pub value: String,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use model::fixtures::alarm_message;
 
     #[test]
     fn test_alarm_state_from_string_matches() {
@@ -127,6 +309,30 @@ mod tests {
         assert_eq!(no_match.is_ok(), false);
     }
 
+    #[test]
+    fn test_alarm_state_display() {
+        assert_eq!(AlarmState::Ok.to_string(), "OK");
+        assert_eq!(AlarmState::Alarm.to_string(), "ALARM");
+        assert_eq!(AlarmState::InsufficientData.to_string(), "INSUFFICIENT_DATA");
+    }
+
+    #[test]
+    fn test_comparison_operator_from_string_matches() {
+        let gte: ComparisonOperator = "GreaterThanOrEqualToThreshold".parse().expect("Should not happen");
+        let lt: ComparisonOperator = "LessThanThreshold".parse().expect("Should not happen");
+
+        assert_eq!(gte, ComparisonOperator::GreaterThanOrEqualToThreshold);
+        assert_eq!(gte.symbol(), ">=");
+        assert_eq!(lt, ComparisonOperator::LessThanThreshold);
+        assert_eq!(lt.symbol(), "<");
+    }
+
+    #[test]
+    fn test_comparison_operator_from_string_no_match() {
+        let no_match: EnumResult<ComparisonOperator> = "foo".parse();
+        assert_eq!(no_match.is_ok(), false);
+    }
+
     #[test]
     fn test_parse_notification() {
         let json = "\
@@ -147,7 +353,7 @@ mod tests {
         assert_eq!(result.is_ok(), true);
 
         let details = result.unwrap();
-        let trigger = details.trigger;
+        let trigger = &details.trigger;
 
         assert_eq!(details.name, "[RTB-US] UnHealthyHostCount");
         assert_eq!(details.description, "UnHealthyHostCount");
@@ -157,18 +363,57 @@ mod tests {
             "Threshold Crossed: 1 datapoint (0.4482758620689655) was not greater than or equal to the threshold (1.0)."
         );
         assert_eq!(details.previous_state, AlarmState::Alarm);
+        assert_eq!(details.account_id, "097958131044");
+        assert_eq!(details.region_code(), Some("us-east-1"));
 
         assert_eq!(trigger.metric_name, "UnHealthyHostCount");
         assert_eq!(trigger.namespace, "AWS/ELB");
         assert_eq!(trigger.statistic, "AVERAGE");
         assert_eq!(trigger.period, 60);
         assert_eq!(trigger.nb_periods, 5);
-        assert_eq!(trigger.op, "GreaterThanOrEqualToThreshold");
+        assert_eq!(trigger.op, ComparisonOperator::GreaterThanOrEqualToThreshold);
         assert_eq!(trigger.threshold, 1.0);
 
         assert_eq!(trigger.dimensions[0].name, "LoadBalancerName");
         assert_eq!(trigger.dimensions[0].value, "rtb");
 
+        assert_eq!(details.envelope.message_id, "d921a633-3dbb-528e-a15c-e978b55d6156");
+        assert_eq!(details.envelope.topic_arn, "arn:aws:sns:us-east-1:097958131044:jabber-all");
+        assert_eq!(details.envelope.timestamp, "2016-02-27T11:21:10.645Z");
+        assert_eq!(details.envelope.message_attributes.is_empty(), true);
+    }
+
+    #[test]
+    fn test_parse_other_comparison_operator() {
+        let message = alarm_message("ALARM", "OK")
+            .replace("GreaterThanOrEqualToThreshold", "LessThanLowerOrGreaterThanUpperThreshold");
+        let details: AlarmDetails = serde_json::from_str(&message).expect("Should not happen");
+
+        assert_eq!(
+            details.trigger.op,
+            ComparisonOperator::Other("LessThanLowerOrGreaterThanUpperThreshold".to_string())
+        );
+        assert_eq!(details.trigger.op.symbol(), "LessThanLowerOrGreaterThanUpperThreshold");
+    }
+
+    #[test]
+    fn test_parse_notification_message_attributes() {
+        let json = format!(
+            "{{\
+                \"Type\" : \"Notification\",\
+                \"MessageId\" : \"d921a633-3dbb-528e-a15c-e978b55d6156\",\
+                \"TopicArn\" : \"arn:aws:sns:us-east-1:097958131044:jabber-all\",\
+                \"Message\" : {},\
+                \"Timestamp\" : \"2016-02-27T11:21:10.645Z\",\
+                \"MessageAttributes\" : {{\"team\": {{\"Type\": \"String\", \"Value\": \"rtb\"}}}}\
+            }}",
+            serde_json::Value::String(alarm_message("ALARM", "OK"))
+        );
+
+        let details: AlarmDetails = json.parse().expect("Should not happen");
+        assert_eq!(details.envelope.subject, None);
+        assert_eq!(details.envelope.message_attributes["team"].kind, "String");
+        assert_eq!(details.envelope.message_attributes["team"].value, "rtb");
     }
 
     #[test]
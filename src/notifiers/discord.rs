@@ -0,0 +1,585 @@
+use hyper::{Client, Method, Request, Response, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::{ContentType, Headers};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use std::str;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use model::{AlarmDetails, AlarmState};
+use oncall::Mention;
+use sparkline::{self, FILENAME as SPARKLINE_FILENAME};
+use super::{GroupMessage, Notifier, NotifierError, json_request, read_json, send, truncate};
+use super::posts::{Post, Posts, resolved_after};
+
+// https://discord.com/developers/docs/resources/message#embed-object-embed-limits
+const TITLE_LIMIT: usize = 256;
+const DESCRIPTION_LIMIT: usize = 4096;
+const FIELD_NAME_LIMIT: usize = 256;
+const FIELD_VALUE_LIMIT: usize = 1024;
+const FIELDS_LIMIT: usize = 25;
+const EMBED_LIMIT: usize = 6000;
+// https://discord.com/developers/docs/resources/webhook#execute-webhook
+const CONTENT_LIMIT: usize = 2000;
+
+const MAX_ATTEMPTS: u32 = 3;
+
+const BOUNDARY: &str = "cloudwatch-bot-attachment";
+
+#[derive(Debug)]
+pub(crate) struct DiscordNotifier {
+    pub webhook_url: Uri,
+    pub l10n: Localizer,
+    // Set when Discord told us the webhook bucket is exhausted, requests are
+    // held back until then instead of being answered with a 429.
+    blocked_until: Mutex<Option<Instant>>,
+    posts: Posts,
+}
+
+impl DiscordNotifier {
+    pub fn new(webhook_url: Uri, l10n: Localizer, posts: Posts) -> DiscordNotifier {
+        DiscordNotifier {
+            webhook_url: webhook_url,
+            l10n: l10n,
+            blocked_until: Mutex::new(None),
+            posts: posts,
+        }
+    }
+
+    // Waits for the message to be created, to get its ID back.
+    fn execute_url(&self) -> Uri {
+        let url = self.webhook_url.to_string();
+        let separator = if url.contains('?') { '&' } else { '?' };
+        format!("{}{}wait=true", url, separator)
+            .parse()
+            .expect("Discord webhook URL should be well formed")
+    }
+
+    fn message_url(&self, id: &str) -> Uri {
+        let url = self.webhook_url.to_string();
+        let (base, query) = match url.find('?') {
+            Some(pos) => url.split_at(pos),
+            None => (url.as_str(), ""),
+        };
+        format!("{}/messages/{}{}", base, id, query)
+            .parse()
+            .expect("Discord webhook URL should be well formed")
+    }
+
+    // `post` is the message of the alarm being edited on recovery.
+    fn embed(&self, alarm: &AlarmDetails, post: Option<&Post>) -> Value {
+        let l10n = &self.l10n;
+        let trigger = &alarm.trigger;
+        let mut fields = vec![
+            (l10n.text("label-metric"), format!("{} ({})", trigger.metric_name, trigger.statistic)),
+            (l10n.text("label-namespace"), trigger.namespace.clone()),
+            (l10n.text("label-threshold"), l10n.threshold(alarm)),
+            (l10n.text("label-period"), l10n.period(alarm)),
+        ];
+        for dimension in &trigger.dimensions {
+            fields.push((dimension.name.clone(), dimension.value.clone()));
+        }
+        fields.truncate(FIELDS_LIMIT);
+
+        let title = truncate(&format!("{}: {}", l10n.state(&alarm.new_state), alarm.name), TITLE_LIMIT);
+        let footer = match post.and_then(|post| resolved_after(l10n, post, alarm)) {
+            Some(resolved) => format!("{} · {}", resolved, l10n.transition(alarm)),
+            None => l10n.transition(alarm),
+        };
+        let fields: Vec<Value> = fields
+            .iter()
+            .map(|&(ref name, ref value)| {
+                json!({
+                    "name": truncate(name, FIELD_NAME_LIMIT),
+                    "value": truncate(value, FIELD_VALUE_LIMIT),
+                    "inline": true,
+                })
+            })
+            .collect();
+
+        // The description gets whatever is left of the total embed budget.
+        let used = title.chars().count() + footer.chars().count() +
+            fields
+                .iter()
+                .map(|f| {
+                    f["name"].as_str().map_or(0, |s| s.chars().count()) + f["value"].as_str().map_or(0, |s| s.chars().count())
+                })
+                .sum::<usize>();
+        let description_limit = DESCRIPTION_LIMIT.min(EMBED_LIMIT.saturating_sub(used));
+
+        json!({
+            "embeds": [{
+                "title": title,
+                "description": truncate(&alarm.reason, description_limit),
+                "color": color(&alarm.new_state),
+                "fields": fields,
+                "footer": {"text": footer},
+            }],
+        })
+    }
+
+    fn wait_for_rate_limit(&self) {
+        let blocked_until = *self.blocked_until.lock().expect("Discord rate limit lock poisoned");
+        if let Some(until) = blocked_until {
+            let now = Instant::now();
+            if until > now {
+                thread::sleep(until - now);
+            }
+        }
+    }
+
+    fn block_for(&self, delay: Option<Duration>) {
+        let mut blocked_until = self.blocked_until.lock().expect("Discord rate limit lock poisoned");
+        *blocked_until = delay.map(|d| Instant::now() + d);
+    }
+
+    fn request(&self, method: Method, uri: Uri, payload: &Value, image: Option<&[u8]>) -> Request {
+        let image = match image {
+            Some(image) => image,
+            None => return json_request(method, uri, payload),
+        };
+
+        let mut body = format!(
+            "--{}\r\n\
+             Content-Disposition: form-data; name=\"payload_json\"\r\n\
+             Content-Type: application/json\r\n\
+             \r\n\
+             {}\r\n\
+             --{}\r\n\
+             Content-Disposition: form-data; name=\"files[0]\"; filename=\"{}\"\r\n\
+             Content-Type: image/png\r\n\
+             \r\n",
+            BOUNDARY,
+            payload,
+            BOUNDARY,
+            SPARKLINE_FILENAME
+        ).into_bytes();
+        body.extend_from_slice(image);
+        body.extend_from_slice(format!("\r\n--{}--\r\n", BOUNDARY).as_bytes());
+
+        let mut req = Request::new(method, uri);
+        req.headers_mut().set(ContentType(
+            format!("multipart/form-data; boundary={}", BOUNDARY)
+                .parse()
+                .expect("Multipart content type should be well formed"),
+        ));
+        req.set_body(body);
+        req
+    }
+
+    fn send(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        method: Method,
+        uri: Uri,
+        payload: &Value,
+        image: Option<&[u8]>,
+    ) -> Result<Response, NotifierError> {
+        let mut status = StatusCode::TooManyRequests;
+
+        for _ in 0..MAX_ATTEMPTS {
+            self.wait_for_rate_limit();
+            let resp = send(client, core, self.request(method.clone(), uri.clone(), payload, image))?;
+            status = resp.status();
+            self.block_for(rate_limit_delay(&status, resp.headers()));
+
+            match status {
+                StatusCode::TooManyRequests => continue,
+                _ if status.is_success() => return Ok(resp),
+                _ => break,
+            }
+        }
+        Err(NotifierError::BadStatus(status))
+    }
+}
+
+fn color(state: &AlarmState) -> u32 {
+    match *state {
+        AlarmState::Ok => 0x1D_8102,
+        AlarmState::Alarm => 0xD1_3212,
+        AlarmState::InsufficientData => 0xFF_9900,
+    }
+}
+
+fn header_seconds(headers: &Headers, name: &str) -> Option<Duration> {
+    headers
+        .get_raw(name)
+        .and_then(|raw| raw.one())
+        .and_then(|bytes| str::from_utf8(bytes).ok())
+        .and_then(|value| value.trim().parse::<f64>().ok())
+        .map(|seconds| Duration::from_millis((seconds.max(0.0) * 1000.0).ceil() as u64))
+}
+
+// How long to hold the next request back, according to Discord's rate limit
+// headers. `None` means there is still room in the current bucket.
+fn rate_limit_delay(status: &StatusCode, headers: &Headers) -> Option<Duration> {
+    if *status == StatusCode::TooManyRequests {
+        return header_seconds(headers, "Retry-After")
+            .or_else(|| header_seconds(headers, "X-RateLimit-Reset-After"))
+            .or_else(|| Some(Duration::from_secs(1)));
+    }
+    let remaining = headers
+        .get_raw("X-RateLimit-Remaining")
+        .and_then(|raw| raw.one())
+        .and_then(|bytes| str::from_utf8(bytes).ok())
+        .and_then(|value| value.trim().parse::<u32>().ok());
+    match remaining {
+        Some(0) => header_seconds(headers, "X-RateLimit-Reset-After"),
+        _ => None,
+    }
+}
+
+// Mentions go in the content, which is sent along the embed. Only the users
+// and roles mentioned are allowed to be pinged, whatever the text says.
+fn mention(payload: &mut Value, mentions: &[Mention]) {
+    let mut users = Vec::new();
+    let mut roles = Vec::new();
+    let mut parse = Vec::new();
+    let mut prefix = Vec::new();
+    for mention in mentions {
+        match *mention {
+            Mention::User(ref id) => {
+                prefix.push(format!("<@{}>", id));
+                users.push(id.clone());
+            }
+            Mention::Group(ref id) => {
+                prefix.push(format!("<@&{}>", id));
+                roles.push(id.clone());
+            }
+            Mention::Here => {
+                prefix.push("@here".to_string());
+                parse.push("everyone");
+            }
+        }
+    }
+
+    let content = match payload["content"].as_str() {
+        Some(content) => format!("{} {}", prefix.join(" "), content),
+        None => prefix.join(" "),
+    };
+    payload["content"] = json!(truncate(&content, CONTENT_LIMIT));
+    payload["allowed_mentions"] = json!({"parse": parse, "users": users, "roles": roles});
+}
+
+impl Notifier for DiscordNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, None, &[])
+    }
+
+    fn notify_text(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: &str,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, Some(text), &[])
+    }
+
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let post = self.posts.get(alarm);
+        let (mut payload, image) = match text {
+            // An edit only replaces the fields it sends, the embed of the
+            // alarm's message would stay below the text.
+            Some(text) => (json!({"content": truncate(text, CONTENT_LIMIT), "embeds": []}), None),
+            None => {
+                let mut payload = self.embed(alarm, post.as_ref());
+                let image = sparkline::render_png(alarm);
+                if image.is_some() {
+                    payload["embeds"][0]["image"] = json!({"url": format!("attachment://{}", SPARKLINE_FILENAME)});
+                    payload["attachments"] = json!([{"id": 0, "filename": SPARKLINE_FILENAME}]);
+                }
+                (payload, image)
+            }
+        };
+        if !mentions.is_empty() {
+            mention(&mut payload, mentions);
+        }
+        let image = image.as_ref().map(Vec::as_slice);
+
+        // The recovery replaces the message of the alarm, unless it has been
+        // deleted in the meantime.
+        if let Some(post) = post {
+            match self.send(client, core, Method::Patch, self.message_url(&post.id), &payload, image) {
+                Err(NotifierError::BadStatus(StatusCode::NotFound)) => {}
+                Err(err) => return Err(err),
+                Ok(_) => {
+                    self.posts.forget(alarm);
+                    return Ok(());
+                }
+            }
+        }
+
+        let resp = self.send(client, core, Method::Post, self.execute_url(), &payload, image)?;
+        if let Some(id) = read_json(core, resp).ok().and_then(|message| message["id"].as_str().map(str::to_string)) {
+            self.posts.remember(alarm, id);
+        }
+        Ok(())
+    }
+
+    fn can_group(&self) -> bool {
+        true
+    }
+
+    // Like the recoveries, the group's message is posted again if it has
+    // been deleted.
+    fn notify_group(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        group: &GroupMessage,
+    ) -> Result<(), NotifierError> {
+        let payload = json!({"content": truncate(group.text, CONTENT_LIMIT)});
+        let id = if group.update { self.posts.group(group.id) } else { None };
+        if let Some(id) = id {
+            match self.send(client, core, Method::Patch, self.message_url(&id), &payload, None) {
+                Err(NotifierError::BadStatus(StatusCode::NotFound)) => {}
+                result => return result.map(|_| ()),
+            }
+        }
+
+        let resp = self.send(client, core, Method::Post, self.execute_url(), &payload, None)?;
+        if let Some(id) = read_json(core, resp).ok().and_then(|message| message["id"].as_str().map(str::to_string)) {
+            self.posts.remember_group(group.id, id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+    use serde_json;
+
+    fn notifier() -> DiscordNotifier {
+        DiscordNotifier::new("https://discord.com/api/webhooks/1/xxx".parse().unwrap(), Localizer::default(), Posts::default())
+    }
+
+    #[test]
+    fn test_embed() {
+        let payload = notifier().embed(&alarm_details("ALARM", "OK"), None);
+        let embed = &payload["embeds"][0];
+
+        assert_eq!(embed["title"], "ALARM: [RTB-US] UnHealthyHostCount");
+        assert_eq!(embed["color"], 0xD1_3212);
+        assert_eq!(embed["fields"][0]["value"], "UnHealthyHostCount (AVERAGE)");
+        assert_eq!(embed["fields"][4]["name"], "LoadBalancerName");
+        assert_eq!(embed["fields"][4]["value"], "rtb");
+        assert_eq!(embed["footer"]["text"], "OK -> ALARM at 2016-02-27 11:21:10 UTC");
+    }
+
+    #[test]
+    fn test_embed_is_localized() {
+        let notifier = DiscordNotifier::new(
+            "https://discord.com/api/webhooks/1/xxx".parse().unwrap(),
+            Localizer {
+                locale: "ja".parse().unwrap(),
+                time_zone: "Asia/Tokyo".parse().unwrap(),
+            },
+            Posts::default(),
+        );
+        let payload = notifier.embed(&alarm_details("ALARM", "OK"), None);
+        let embed = &payload["embeds"][0];
+
+        assert_eq!(embed["title"], "アラーム: [RTB-US] UnHealthyHostCount");
+        assert_eq!(embed["fields"][2]["name"], "しきい値");
+        assert_eq!(embed["fields"][2]["value"], "1 以上");
+        assert_eq!(embed["footer"]["text"], "2016年02月27日 20:21:10 JST に 正常 -> アラーム");
+    }
+
+    #[test]
+    fn test_embed_respects_size_limits() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.name = "x".repeat(300);
+        alarm.reason = "y".repeat(7000);
+
+        let payload = notifier().embed(&alarm, None);
+        let embed = &payload["embeds"][0];
+        let title = embed["title"].as_str().unwrap();
+        let description = embed["description"].as_str().unwrap();
+        let fields_len: usize = embed["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap().len() + f["value"].as_str().unwrap().len())
+            .sum();
+        let footer = embed["footer"]["text"].as_str().unwrap();
+
+        assert_eq!(title.chars().count(), TITLE_LIMIT);
+        assert_eq!(
+            title.chars().count() + description.chars().count() + fields_len + footer.len() <= EMBED_LIMIT,
+            true
+        );
+    }
+
+    #[test]
+    fn test_mention() {
+        let mut payload = notifier().embed(&alarm_details("ALARM", "OK"), None);
+        mention(
+            &mut payload,
+            &[Mention::User("123".to_string()), Mention::Group("456".to_string()), Mention::Here],
+        );
+        assert_eq!(payload["content"], "<@123> <@&456> @here");
+        assert_eq!(payload["allowed_mentions"], json!({"parse": ["everyone"], "users": ["123"], "roles": ["456"]}));
+
+        let mut payload = json!({"content": "Disk full"});
+        mention(&mut payload, &[Mention::User("123".to_string())]);
+        assert_eq!(payload["content"], "<@123> Disk full");
+        assert_eq!(payload["allowed_mentions"], json!({"parse": [], "users": ["123"], "roles": []}));
+    }
+
+    #[test]
+    fn test_notify_attaches_sparkline() {
+        let server = MockServer::start(vec![(StatusCode::NoContent, "")]);
+        let mut core = core();
+        let client = https_client(&core);
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.reason = "Threshold Crossed: 2 datapoints [3.0 (27/02/16 11:20:00), 2.0 (27/02/16 11:19:00)] \
+                        were greater than or equal to the threshold (1.0)."
+            .to_string();
+
+        let notifier = DiscordNotifier::new(
+            format!("{}/api/webhooks/1/xxx", server.url).parse().unwrap(),
+            Localizer::default(),
+            Posts::default(),
+        );
+        notifier.notify(&client, &mut core, &alarm).unwrap();
+
+        let requests = server.requests();
+        let content_type = requests[0].headers.get::<ContentType>().map(|c| c.to_string());
+        assert_eq!(content_type, Some("multipart/form-data; boundary=cloudwatch-bot-attachment".to_string()));
+        assert_eq!(requests[0].body.contains("\"url\":\"attachment://sparkline.png\""), true);
+        assert_eq!(requests[0].body.contains("name=\"files[0]\"; filename=\"sparkline.png\""), true);
+    }
+
+    #[test]
+    fn test_recovery_edits_the_alarm_message() {
+        let server = MockServer::start(vec![
+            (StatusCode::Ok, "{\"id\": \"111\"}"),
+            (StatusCode::InternalServerError, "{}"),
+            (StatusCode::Ok, "{\"id\": \"111\"}"),
+            (StatusCode::Ok, "{\"id\": \"222\"}"),
+        ]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = DiscordNotifier::new(
+            format!("{}/api/webhooks/1/xxx", server.url).parse().unwrap(),
+            Localizer::default(),
+            Posts::default(),
+        );
+        let mut recovery = alarm_details("OK", "ALARM");
+        recovery.timestamp = "2016-02-27T11:33:10.602+0000".to_string();
+
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        // The failed edit is retried.
+        assert_eq!(notifier.notify(&client, &mut core, &recovery).is_err(), true);
+        notifier.notify(&client, &mut core, &recovery).unwrap();
+        notifier.notify(&client, &mut core, &recovery).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, Method::Post);
+        assert_eq!(requests[0].path, "/api/webhooks/1/xxx?wait=true");
+        assert_eq!(requests[1].method, Method::Patch);
+        assert_eq!(requests[2].method, Method::Patch);
+        assert_eq!(requests[2].path, "/api/webhooks/1/xxx/messages/111");
+        assert_eq!(requests[2].body.contains("Resolved after 12m · ALARM -> OK at"), true);
+        assert_eq!(requests[3].method, Method::Post);
+    }
+
+    #[test]
+    fn test_text_recovery_removes_the_embed() {
+        let server = MockServer::start(vec![(StatusCode::Ok, "{\"id\": \"111\"}")]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = DiscordNotifier::new(
+            format!("{}/api/webhooks/1/xxx", server.url).parse().unwrap(),
+            Localizer::default(),
+            Posts::default(),
+        );
+
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        notifier.notify_text(&client, &mut core, &alarm_details("OK", "ALARM"), "Resolved").unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[1].method, Method::Patch);
+        let body: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(body, json!({"content": "Resolved", "embeds": []}));
+    }
+
+    #[test]
+    fn test_group_edits_its_message() {
+        let server = MockServer::start(vec![(StatusCode::Ok, "{\"id\": \"111\"}")]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = DiscordNotifier::new(
+            format!("{}/api/webhooks/1/xxx", server.url).parse().unwrap(),
+            Localizer::default(),
+            Posts::default(),
+        );
+        let alarms = vec![alarm_details("ALARM", "OK")];
+        let mut group = GroupMessage {
+            id: "rtb|[RTB-US]|ops",
+            key: "[RTB-US]",
+            text: "1 alarms for [RTB-US]",
+            alarms: &alarms,
+            changed: &[0],
+            update: false,
+        };
+
+        notifier.notify_group(&client, &mut core, &group).unwrap();
+        group.update = true;
+        notifier.notify_group(&client, &mut core, &group).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, Method::Post);
+        assert_eq!(requests[0].body, "{\"content\":\"1 alarms for [RTB-US]\"}");
+        assert_eq!(requests[1].method, Method::Patch);
+        assert_eq!(requests[1].path, "/api/webhooks/1/xxx/messages/111");
+    }
+
+    #[test]
+    fn test_rate_limit_delay_bucket_exhausted() {
+        let mut headers = Headers::new();
+        headers.set_raw("X-RateLimit-Remaining", "0");
+        headers.set_raw("X-RateLimit-Reset-After", "1.5");
+
+        assert_eq!(rate_limit_delay(&StatusCode::NoContent, &headers), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_rate_limit_delay_bucket_not_exhausted() {
+        let mut headers = Headers::new();
+        headers.set_raw("X-RateLimit-Remaining", "4");
+        headers.set_raw("X-RateLimit-Reset-After", "1.5");
+
+        assert_eq!(rate_limit_delay(&StatusCode::NoContent, &headers), None);
+    }
+
+    #[test]
+    fn test_rate_limit_delay_too_many_requests() {
+        let mut headers = Headers::new();
+        headers.set_raw("Retry-After", "2");
+
+        assert_eq!(rate_limit_delay(&StatusCode::TooManyRequests, &headers), Some(Duration::from_secs(2)));
+        assert_eq!(rate_limit_delay(&StatusCode::TooManyRequests, &Headers::new()), Some(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,551 @@
+use base64;
+use chrono::Utc;
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, metric_graph_url};
+use model::AlarmDetails;
+use oncall::Mention;
+use routing::RoutingDecision;
+use sparkline::{self, FILENAME as SPARKLINE_FILENAME};
+use super::{Notifier, NotifierError, escape_html, hash, state_color};
+use super::posts::Posts;
+use super::smtp::{SmtpConfig, send_mail};
+
+const BOUNDARY: &str = "cloudwatch-bot-alternative";
+const MIXED_BOUNDARY: &str = "cloudwatch-bot-mixed";
+
+#[derive(Debug)]
+pub(crate) struct EmailNotifier {
+    pub smtp: SmtpConfig,
+    // Unless the route of the alarm has its own.
+    pub recipients: Vec<String>,
+    pub l10n: Localizer,
+    // Message-ID of the last mail sent for each alarm, so that the next
+    // transition replies to it.
+    posts: Posts,
+}
+
+// RFC 2047 encoded-word, only when the header is not plain ASCII.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?UTF-8?B?{}?=", base64::encode(value))
+    }
+}
+
+impl EmailNotifier {
+    pub fn new(smtp: SmtpConfig, recipients: Vec<String>, l10n: Localizer, posts: Posts) -> EmailNotifier {
+        EmailNotifier {
+            smtp: smtp,
+            recipients: recipients,
+            l10n: l10n,
+            posts: posts,
+        }
+    }
+
+    fn alarm_key(alarm: &AlarmDetails) -> &str {
+        alarm.arn.as_ref().unwrap_or(&alarm.name)
+    }
+
+    // All mails of an alarm reference the same root, even across restarts,
+    // which is enough for mail clients to thread them together.
+    fn thread_root_id(&self, alarm: &AlarmDetails) -> String {
+        format!("<alarm.{}@{}>", hash(&[Self::alarm_key(alarm)]), self.smtp.from_domain())
+    }
+
+    fn message_id(&self, alarm: &AlarmDetails) -> String {
+        let transition = [Self::alarm_key(alarm), &alarm.new_state.to_string(), &alarm.timestamp];
+        format!("<transition.{}@{}>", hash(&transition), self.smtp.from_domain())
+    }
+
+    fn rows(&self, alarm: &AlarmDetails) -> Vec<(String, String)> {
+        let l10n = &self.l10n;
+        let trigger = &alarm.trigger;
+        let dimensions: Vec<String> = trigger
+            .dimensions
+            .iter()
+            .map(|d| format!("{}={}", d.name, d.value))
+            .collect();
+        vec![
+            (l10n.text("label-metric"), format!("{} ({})", trigger.metric_name, trigger.statistic)),
+            (l10n.text("label-namespace"), trigger.namespace.clone()),
+            (l10n.text("label-dimensions"), dimensions.join(", ")),
+            (l10n.text("label-threshold"), l10n.threshold(alarm)),
+            (l10n.text("label-period"), l10n.period(alarm)),
+            (l10n.text("label-changed-at"), l10n.timestamp(alarm)),
+        ]
+    }
+
+    fn plain_text(&self, alarm: &AlarmDetails) -> String {
+        let l10n = &self.l10n;
+        let rows = self.rows(alarm);
+        let width = rows.iter().map(|&(ref label, _)| label.chars().count()).max().unwrap_or(0) + 1;
+
+        let mut text = format!(
+            "{}: {} {}\r\n\r\n{}\r\n\r\n",
+            l10n.state(&alarm.new_state),
+            alarm.name,
+            l10n.was_state(&alarm.previous_state),
+            alarm.reason
+        );
+        for (label, value) in rows {
+            text.push_str(&format!("{:width$} {}\r\n", format!("{}:", label), value, width = width));
+        }
+        if let Some(url) = alarm_console_url(alarm) {
+            text.push_str(&format!("\r\n{}: {}\r\n", l10n.text("open-in-cloudwatch"), url));
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            text.push_str(&format!("{}: {}\r\n", l10n.text("metric-graph"), url));
+        }
+        text
+    }
+
+    fn html(&self, alarm: &AlarmDetails) -> String {
+        let l10n = &self.l10n;
+        let rows = self.rows(alarm);
+        let rows: Vec<String> = rows.iter()
+            .map(|&(ref label, ref value)| {
+                format!("<tr><th align=\"left\">{}</th><td>{}</td></tr>", escape_html(label), escape_html(value))
+            })
+            .collect();
+
+        let mut html = format!(
+            "<html><body>\
+             <h2><span style=\"color: {}\">{}</span> {}</h2>\
+             <p>{} {}</p>\
+             <p>{}</p>\
+             <table>{}</table>",
+            state_color(&alarm.new_state),
+            escape_html(&l10n.state(&alarm.new_state)),
+            escape_html(&alarm.name),
+            escape_html(&alarm.description),
+            escape_html(&l10n.was_state(&alarm.previous_state)),
+            escape_html(&alarm.reason),
+            rows.concat()
+        );
+        if let Some(url) = alarm_console_url(alarm) {
+            html.push_str(&format!(
+                "<p><a href=\"{}\">{}</a></p>",
+                escape_html(&url),
+                escape_html(&l10n.text("open-in-cloudwatch"))
+            ));
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            html.push_str(&format!(
+                "<p><a href=\"{}\">{}</a></p>",
+                escape_html(&url),
+                escape_html(&l10n.text("metric-graph"))
+            ));
+        }
+        html.push_str("</body></html>");
+        html
+    }
+
+    // The plain text and HTML parts, rendered from the route's template when
+    // `text` is given. Mail can't ping anyone, the mentions head the body.
+    fn parts(&self, alarm: &AlarmDetails, text: Option<&str>, mentions: &[Mention]) -> (String, String) {
+        let (mut plain_text, mut html) = match text {
+            Some(text) => (
+                format!("{}\r\n", text.replace("\r\n", "\n").replace('\n', "\r\n")),
+                format!("<html><body><p>{}</p></body></html>", escape_html(text).replace('\n', "<br>")),
+            ),
+            None => (self.plain_text(alarm), self.html(alarm)),
+        };
+        if !mentions.is_empty() {
+            let mentions = mentions_line(mentions);
+            plain_text = format!("{}\r\n\r\n{}", mentions, plain_text);
+            html = html.replacen("<body>", &format!("<body><p>{}</p>", escape_html(&mentions)), 1);
+        }
+        (plain_text, html)
+    }
+
+    fn message(
+        &self,
+        alarm: &AlarmDetails,
+        recipients: &[String],
+        in_reply_to: Option<&str>,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> String {
+        let (plain_text, html) = self.parts(alarm, text, mentions);
+        let root_id = self.thread_root_id(alarm);
+        let in_reply_to = in_reply_to.unwrap_or(&root_id);
+        let references = if in_reply_to == root_id {
+            root_id.clone()
+        } else {
+            format!("{} {}", root_id, in_reply_to)
+        };
+
+        let alternative = format!(
+            "Content-Type: multipart/alternative; boundary=\"{}\"\r\n\
+             \r\n\
+             --{}\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             {}\r\n\
+             --{}\r\n\
+             Content-Type: text/html; charset=utf-8\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             \r\n\
+             {}\r\n\
+             --{}--",
+            BOUNDARY,
+            BOUNDARY,
+            base64::encode_config(&plain_text, base64::MIME),
+            BOUNDARY,
+            base64::encode_config(&html, base64::MIME),
+            BOUNDARY
+        );
+        // The chart goes along as an attachment, mail clients showing it
+        // inline below the message.
+        let body = match sparkline::render_png(alarm) {
+            Some(image) => format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\
+                 \r\n\
+                 --{}\r\n\
+                 {}\r\n\
+                 --{}\r\n\
+                 Content-Type: image/png\r\n\
+                 Content-Disposition: attachment; filename=\"{}\"\r\n\
+                 Content-Transfer-Encoding: base64\r\n\
+                 \r\n\
+                 {}\r\n\
+                 --{}--",
+                MIXED_BOUNDARY,
+                MIXED_BOUNDARY,
+                alternative,
+                MIXED_BOUNDARY,
+                SPARKLINE_FILENAME,
+                base64::encode_config(&image, base64::MIME),
+                MIXED_BOUNDARY
+            ),
+            None => alternative,
+        };
+
+        // The subject does not carry the state, some clients (Gmail) only
+        // thread mails with the same subject.
+        format!(
+            "From: {}\r\n\
+             To: {}\r\n\
+             Subject: {}\r\n\
+             Date: {}\r\n\
+             Message-ID: {}\r\n\
+             In-Reply-To: {}\r\n\
+             References: {}\r\n\
+             MIME-Version: 1.0\r\n\
+             {}",
+            self.smtp.from,
+            recipients.join(", "),
+            encode_header(&format!("[CloudWatch] {}", alarm.name)),
+            Utc::now().to_rfc2822(),
+            self.message_id(alarm),
+            in_reply_to,
+            references,
+            body
+        )
+    }
+
+    fn send(
+        &self,
+        alarm: &AlarmDetails,
+        recipients: &[String],
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let previous_id = self.posts.latest(alarm);
+        let in_reply_to = previous_id.as_ref().map(String::as_str);
+        let message = self.message(alarm, recipients, in_reply_to, text, mentions);
+
+        send_mail(&self.smtp, recipients, &message)?;
+        self.posts.remember_latest(alarm, self.message_id(alarm));
+        Ok(())
+    }
+}
+
+fn mentions_line(mentions: &[Mention]) -> String {
+    let mentions: Vec<String> = mentions
+        .iter()
+        .map(|mention| match *mention {
+            Mention::User(ref name) | Mention::Group(ref name) => format!("@{}", name),
+            Mention::Here => "@here".to_string(),
+        })
+        .collect();
+    mentions.join(" ")
+}
+
+impl Notifier for EmailNotifier {
+    fn notify(
+        &self,
+        _client: &Client<HttpsConnector<HttpConnector>>,
+        _core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.send(alarm, &self.recipients, None, &[])
+    }
+
+    fn notify_routed(
+        &self,
+        _client: &Client<HttpsConnector<HttpConnector>>,
+        _core: &mut Core,
+        alarm: &AlarmDetails,
+        decision: &RoutingDecision,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        match decision.recipients.get(self.posts.destination()) {
+            Some(recipients) => self.send(alarm, recipients, text, mentions),
+            None => self.send(alarm, &self.recipients, text, mentions),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::PostStore;
+    use notifiers::mock_server::{core, https_client};
+    use notifiers::smtp::SmtpSecurity;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[derive(Debug, Default, Clone)]
+    struct Envelope {
+        commands: Vec<String>,
+        data: String,
+    }
+
+    // Minimal SMTP server accepting every mail and keeping what it got.
+    fn start_sink() -> (u16, Arc<Mutex<Vec<Envelope>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mails = Arc::new(Mutex::new(Vec::new()));
+        let sink_mails = mails.clone();
+
+        thread::spawn(move || for stream in listener.incoming() {
+            let mut writer = stream.unwrap();
+            let mut reader = BufReader::new(writer.try_clone().unwrap());
+            let mut envelope = Envelope::default();
+            writer.write_all(b"220 sink ESMTP\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_right().to_string();
+                envelope.commands.push(line.clone());
+                let reply: &[u8] = if line.starts_with("EHLO") {
+                    b"250-sink\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 2.7.0 Authentication successful\r\n"
+                } else if line == "DATA" {
+                    writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").unwrap();
+                    let mut data = String::new();
+                    loop {
+                        let mut data_line = String::new();
+                        reader.read_line(&mut data_line).unwrap();
+                        if data_line == ".\r\n" {
+                            break;
+                        }
+                        data.push_str(&data_line);
+                    }
+                    envelope.data = data;
+                    b"250 2.0.0 Ok: queued\r\n"
+                } else if line == "QUIT" {
+                    // Recorded before replying, the client is done as soon
+                    // as it gets the reply.
+                    sink_mails.lock().unwrap().push(envelope.clone());
+                    writer.write_all(b"221 2.0.0 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 2.1.0 Ok\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+        });
+
+        (port, mails)
+    }
+
+    fn notifier(port: u16) -> EmailNotifier {
+        notifier_with(port, Posts::default())
+    }
+
+    fn notifier_with(port: u16, posts: Posts) -> EmailNotifier {
+        EmailNotifier::new(
+            SmtpConfig {
+                host: "127.0.0.1".to_string(),
+                port: port,
+                security: SmtpSecurity::None,
+                username: Some("bot".to_string()),
+                password: Some("secret".to_string()),
+                from: "cloudwatch-bot@example.org".to_string(),
+            },
+            vec!["ops@example.org".to_string(), "dba@example.org".to_string()],
+            Localizer::default(),
+            posts,
+        )
+    }
+
+    fn header<'a>(data: &'a str, name: &str) -> Option<&'a str> {
+        let prefix = format!("{}: ", name);
+        data.lines()
+            .find(|line| line.starts_with(&prefix))
+            .map(|line| line[prefix.len()..].trim_right())
+    }
+
+    #[test]
+    fn test_encode_header() {
+        assert_eq!(encode_header("[RTB-US] Latency"), "[RTB-US] Latency");
+        assert_eq!(encode_header("Latence élevée"), "=?UTF-8?B?TGF0ZW5jZSDDqWxldsOpZQ==?=");
+    }
+
+    #[test]
+    fn test_parts() {
+        let notifier = notifier(25);
+        let alarm = alarm_details("ALARM", "OK");
+
+        assert_eq!(notifier.plain_text(&alarm).starts_with("ALARM: [RTB-US] UnHealthyHostCount (was OK)\r\n"), true);
+        assert_eq!(notifier.plain_text(&alarm).contains("Threshold:  >= 1\r\n"), true);
+        assert_eq!(notifier.html(&alarm).contains("<tr><th align=\"left\">Threshold</th><td>&gt;= 1</td></tr>"), true);
+        assert_eq!(notifier.plain_text(&alarm).contains("Changed at: 2016-02-27 11:21:10 UTC\r\n"), true);
+    }
+
+    #[test]
+    fn test_parts_are_localized() {
+        let mut notifier = notifier(25);
+        notifier.l10n = Localizer {
+            locale: "fr".parse().unwrap(),
+            time_zone: "Europe/Paris".parse().unwrap(),
+        };
+        let alarm = alarm_details("ALARM", "OK");
+        let text = notifier.plain_text(&alarm);
+
+        assert_eq!(text.starts_with("ALARME: [RTB-US] UnHealthyHostCount (précédemment OK)\r\n"), true);
+        assert_eq!(text.contains("Seuil:          supérieur ou égal à 1\r\n"), true);
+        assert_eq!(text.contains("Modifiée le:    27/02/2016 12:21:10 CET\r\n"), true);
+        assert_eq!(notifier.html(&alarm).contains("<tr><th align=\"left\">Espace de noms</th><td>AWS/ELB</td></tr>"), true);
+    }
+
+    #[test]
+    fn test_message_attaches_sparkline() {
+        let notifier = notifier(25);
+        let mut alarm = alarm_details("ALARM", "OK");
+        assert_eq!(notifier.message(&alarm, &notifier.recipients, None, None, &[]).contains(SPARKLINE_FILENAME), false);
+
+        alarm.reason = "Threshold Crossed: 2 datapoints [3.0 (27/02/16 11:20:00), 2.0 (27/02/16 11:19:00)] \
+                        were greater than or equal to the threshold (1.0)."
+            .to_string();
+        let message = notifier.message(&alarm, &notifier.recipients, None, None, &[]);
+        assert_eq!(header(&message, "Content-Type"), Some("multipart/mixed; boundary=\"cloudwatch-bot-mixed\""));
+        assert_eq!(message.contains("Content-Type: multipart/alternative; boundary=\"cloudwatch-bot-alternative\""), true);
+        assert_eq!(message.contains("Content-Disposition: attachment; filename=\"sparkline.png\"\r\n"), true);
+        assert_eq!(message.ends_with("--cloudwatch-bot-mixed--"), true);
+    }
+
+    #[test]
+    fn test_parts_from_template_with_mentions() {
+        let notifier = notifier(25);
+        let alarm = alarm_details("ALARM", "OK");
+        let mentions = vec![Mention::User("alice".to_string()), Mention::Here];
+        let (plain_text, html) = notifier.parts(&alarm, Some("Disk <full>\non db-1"), &mentions);
+        assert_eq!(plain_text, "@alice @here\r\n\r\nDisk <full>\r\non db-1\r\n");
+        assert_eq!(html, "<html><body><p>@alice @here</p><p>Disk &lt;full&gt;<br>on db-1</p></body></html>");
+
+        let (plain_text, html) = notifier.parts(&alarm, None, &mentions);
+        assert_eq!(plain_text.starts_with("@alice @here\r\n\r\nALARM: "), true);
+        assert_eq!(html.starts_with("<html><body><p>@alice @here</p><h2>"), true);
+    }
+
+    #[test]
+    fn test_send_to_sink_and_thread_transitions() {
+        let (port, mails) = start_sink();
+        let notifier = notifier(port);
+        let mut core = core();
+        let client = https_client(&core);
+
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        notifier.notify(&client, &mut core, &alarm_details("OK", "ALARM")).unwrap();
+
+        let mails = mails.lock().unwrap().clone();
+        assert_eq!(mails.len(), 2);
+        assert_eq!(
+            mails[0].commands,
+            vec![
+                "EHLO example.org",
+                "AUTH PLAIN AGJvdABzZWNyZXQ=",
+                "MAIL FROM:<cloudwatch-bot@example.org>",
+                "RCPT TO:<ops@example.org>",
+                "RCPT TO:<dba@example.org>",
+                "DATA",
+                "QUIT",
+            ]
+        );
+
+        let (first, second) = (&mails[0].data, &mails[1].data);
+        let root_id = notifier.thread_root_id(&alarm_details("ALARM", "OK"));
+        assert_eq!(header(first, "Subject"), Some("[CloudWatch] [RTB-US] UnHealthyHostCount"));
+        assert_eq!(
+            header(first, "Content-Type"),
+            Some("multipart/alternative; boundary=\"cloudwatch-bot-alternative\"")
+        );
+        assert_eq!(header(first, "In-Reply-To"), Some(root_id.as_str()));
+        assert_eq!(header(second, "In-Reply-To"), header(first, "Message-ID"));
+        assert_eq!(
+            header(second, "References"),
+            Some(format!("{} {}", root_id, header(first, "Message-ID").unwrap()).as_str())
+        );
+        assert_eq!(header(first, "Message-ID") == header(second, "Message-ID"), false);
+    }
+
+    #[test]
+    fn test_threads_survive_a_restart() {
+        let (port, mails) = start_sink();
+        let store = Arc::new(PostStore::default());
+        let mut core = core();
+        let client = https_client(&core);
+
+        let notifier = notifier_with(port, Posts::new("ops", store.clone()));
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        let notifier = notifier_with(port, Posts::new("ops", store));
+        notifier.notify(&client, &mut core, &alarm_details("OK", "ALARM")).unwrap();
+
+        let mails = mails.lock().unwrap().clone();
+        assert_eq!(header(&mails[1].data, "In-Reply-To"), header(&mails[0].data, "Message-ID"));
+        assert_eq!(
+            header(&mails[0].data, "Message-ID"),
+            Some(notifier.message_id(&alarm_details("ALARM", "OK")).as_str())
+        );
+    }
+
+    #[test]
+    fn test_route_recipients() {
+        let (port, mails) = start_sink();
+        let notifier = notifier_with(port, Posts::new("mail", Arc::new(PostStore::default())));
+        let mut core = core();
+        let client = https_client(&core);
+        let alarm = alarm_details("ALARM", "OK");
+        let other = RoutingDecision::from_json(&json!({"recipients": {"other": ["dev@example.org"]}}));
+        let mail = RoutingDecision::from_json(&json!({"recipients": {"mail": ["dba@example.org"]}}));
+
+        notifier.notify_routed(&client, &mut core, &alarm, &other, None, &[]).unwrap();
+        notifier.notify_routed(&client, &mut core, &alarm, &mail, None, &[]).unwrap();
+
+        let mails = mails.lock().unwrap().clone();
+        let rcpt = |envelope: &Envelope| -> Vec<String> {
+            envelope.commands.iter().filter(|c| c.starts_with("RCPT TO:")).cloned().collect()
+        };
+        assert_eq!(rcpt(&mails[0]), vec!["RCPT TO:<ops@example.org>", "RCPT TO:<dba@example.org>"]);
+        assert_eq!(rcpt(&mails[1]), vec!["RCPT TO:<dba@example.org>"]);
+        assert_eq!(header(&mails[1].data, "To"), Some("dba@example.org"));
+    }
+}
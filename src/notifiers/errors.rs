@@ -0,0 +1,25 @@
+use hyper::{Error as HyperError, StatusCode};
+use std::io;
+
+#[derive(Debug)]
+pub(crate) enum NotifierError {
+    BadStatus(StatusCode),
+    HttpError(HyperError),
+    // The background connection of a persistent notifier is gone.
+    Disconnected,
+    IoError(io::Error),
+    // Unexpected reply code from a line based protocol server (SMTP...).
+    BadReply(u16, String),
+    // A successful response whose body isn't what the API documents.
+    InvalidResponse(String),
+    // An error the API reports in the body of a successful response (Slack).
+    ApiError(String),
+    // The URL of an API endpoint, built from the configuration, is invalid.
+    InvalidUrl(String),
+}
+
+impl From<io::Error> for NotifierError {
+    fn from(err: io::Error) -> NotifierError {
+        NotifierError::IoError(err)
+    }
+}
@@ -0,0 +1,154 @@
+use hyper::{Client, Uri};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, metric_graph_url};
+use model::AlarmDetails;
+use oncall::Mention;
+use super::{Notifier, NotifierError, escape_html, post_json, state_color};
+
+// Incoming webhook of a Google Chat space, posting cardsV2 messages.
+#[derive(Debug)]
+pub(crate) struct GoogleChatNotifier {
+    pub webhook_url: Uri,
+    pub l10n: Localizer,
+}
+
+fn decorated_text(label: &str, text: &str) -> Value {
+    json!({"decoratedText": {"topLabel": label, "text": escape_html(text)}})
+}
+
+impl GoogleChatNotifier {
+    fn message(&self, alarm: &AlarmDetails) -> Value {
+        let l10n = &self.l10n;
+        let trigger = &alarm.trigger;
+        let dimensions: Vec<String> = trigger
+            .dimensions
+            .iter()
+            .map(|d| format!("{}={}", d.name, d.value))
+            .collect();
+
+        let mut widgets = vec![
+            json!({"decoratedText": {
+                "topLabel": l10n.text("label-state"),
+                "text": format!(
+                    "<font color=\"{}\"><b>{}</b></font> {}",
+                    state_color(&alarm.new_state),
+                    escape_html(&l10n.state(&alarm.new_state)),
+                    escape_html(&l10n.was_state(&alarm.previous_state))
+                ),
+            }}),
+            json!({"textParagraph": {"text": escape_html(&alarm.reason)}}),
+            decorated_text(&l10n.text("label-metric"), &format!("{} ({})", trigger.metric_name, trigger.statistic)),
+            decorated_text(&l10n.text("label-namespace"), &trigger.namespace),
+            decorated_text(&l10n.text("label-dimensions"), &dimensions.join(", ")),
+            decorated_text(&l10n.text("label-threshold"), &l10n.threshold(alarm)),
+            decorated_text(&l10n.text("label-period"), &l10n.period(alarm)),
+        ];
+        let buttons: Vec<Value> = alarm_console_url(alarm)
+            .map(|url| (l10n.text("open-in-cloudwatch"), url))
+            .into_iter()
+            .chain(metric_graph_url(alarm).map(|url| (l10n.text("metric-graph"), url)))
+            .map(|(text, url)| json!({"text": text, "onClick": {"openLink": {"url": url}}}))
+            .collect();
+        if !buttons.is_empty() {
+            widgets.push(json!({"buttonList": {"buttons": buttons}}));
+        }
+
+        json!({
+            "text": format!("{}: {}", l10n.state(&alarm.new_state), alarm.name),
+            "cardsV2": [{
+                "cardId": "alarm",
+                "card": {
+                    "header": {
+                        "title": alarm.name,
+                        "subtitle": alarm.description,
+                    },
+                    "sections": [{"widgets": widgets}],
+                },
+            }],
+        })
+    }
+}
+
+impl Notifier for GoogleChatNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        post_json(client, core, self.webhook_url.clone(), &self.message(alarm)).map(|_| ())
+    }
+
+    // Mentions go in the text above the card. Users are mentioned by their
+    // numeric ID, there are no groups in Google Chat.
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        _text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let mut message = self.message(alarm);
+        let mentions: Vec<String> = mentions
+            .iter()
+            .filter_map(|mention| match *mention {
+                Mention::User(ref id) => Some(format!("<users/{}>", id)),
+                Mention::Here => Some("<users/all>".to_string()),
+                Mention::Group(_) => None,
+            })
+            .collect();
+        if !mentions.is_empty() {
+            let text = format!("{} {}", mentions.join(" "), message["text"].as_str().unwrap_or(""));
+            message["text"] = json!(text);
+        }
+        post_json(client, core, self.webhook_url.clone(), &message).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    fn notifier(locale: &str) -> GoogleChatNotifier {
+        GoogleChatNotifier {
+            webhook_url: "https://chat.googleapis.com/v1/spaces/AAA/messages?key=k&token=t".parse().unwrap(),
+            l10n: Localizer {
+                locale: locale.parse().unwrap(),
+                ..Localizer::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_message() {
+        let message = notifier("en").message(&alarm_details("ALARM", "OK"));
+        let card = &message["cardsV2"][0]["card"];
+        let widgets = &card["sections"][0]["widgets"];
+
+        assert_eq!(message["text"], "ALARM: [RTB-US] UnHealthyHostCount");
+        assert_eq!(card["header"]["title"], "[RTB-US] UnHealthyHostCount");
+        assert_eq!(widgets[0]["decoratedText"]["text"], "<font color=\"#d13212\"><b>ALARM</b></font> (was OK)");
+        assert_eq!(widgets[2]["decoratedText"]["text"], "UnHealthyHostCount (AVERAGE)");
+        assert_eq!(widgets[4]["decoratedText"]["text"], "LoadBalancerName=rtb");
+        assert_eq!(widgets[5]["decoratedText"]["text"], "&gt;= 1");
+        assert_eq!(widgets[7]["buttonList"]["buttons"][0]["text"], "Open in CloudWatch");
+    }
+
+    #[test]
+    fn test_message_is_localized() {
+        let message = notifier("fr").message(&alarm_details("ALARM", "OK"));
+        let widgets = &message["cardsV2"][0]["card"]["sections"][0]["widgets"];
+
+        assert_eq!(message["text"], "ALARME: [RTB-US] UnHealthyHostCount");
+        assert_eq!(widgets[0]["decoratedText"]["topLabel"], "État");
+        assert_eq!(widgets[0]["decoratedText"]["text"], "<font color=\"#d13212\"><b>ALARME</b></font> (précédemment OK)");
+        assert_eq!(widgets[7]["buttonList"]["buttons"][1]["text"], "Graphique de la métrique");
+    }
+}
@@ -0,0 +1,159 @@
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio_core::reactor::Core;
+
+use model::{AlarmDetails, AlarmState, EnumResult, ParseEnumError};
+use super::{NotifierError, truncate};
+
+// What an INSUFFICIENT_DATA transition does to the incident of an alarm.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum InsufficientDataAction {
+    Open,
+    Close,
+    Ignore,
+}
+
+lazy_static! {
+    static ref INSUFFICIENT_DATA_ACTIONS: HashMap<&'static str, InsufficientDataAction> = {
+        let mut map = HashMap::new();
+        map.insert("open", InsufficientDataAction::Open);
+        map.insert("close", InsufficientDataAction::Close);
+        map.insert("ignore", InsufficientDataAction::Ignore);
+        map
+    };
+}
+
+impl FromStr for InsufficientDataAction {
+    type Err = ParseEnumError<Self>;
+
+    fn from_str(action: &str) -> EnumResult<InsufficientDataAction> {
+        INSUFFICIENT_DATA_ACTIONS.get(action).cloned().ok_or(ParseEnumError {
+            value: action.to_string(),
+            mapping: INSUFFICIENT_DATA_ACTIONS.clone(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum IncidentAction {
+    Open,
+    Close,
+}
+
+// Incident tools (PagerDuty, Opsgenie...) keep one incident per alarm,
+// identified by an alias, that is opened on ALARM and closed on OK.
+pub(crate) trait IncidentManager {
+    // Longest alias accepted by the tool.
+    fn alias_limit(&self) -> usize;
+
+    fn insufficient_data(&self) -> &InsufficientDataAction;
+
+    fn open(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alias: &str,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError>;
+
+    fn close(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alias: &str,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError>;
+}
+
+// The ARN is unique across accounts and regions, the name only within one.
+pub(crate) fn incident_alias(alarm: &AlarmDetails, limit: usize) -> String {
+    truncate(alarm.arn.as_ref().unwrap_or(&alarm.name), limit)
+}
+
+// Whether an alarm in this state has an open incident.
+fn opens_incident(insufficient_data: &InsufficientDataAction, state: &AlarmState) -> bool {
+    match *state {
+        AlarmState::Alarm => true,
+        AlarmState::InsufficientData => *insufficient_data == InsufficientDataAction::Open,
+        AlarmState::Ok => false,
+    }
+}
+
+// Every recovery closes, the incident may have been opened before an ignored
+// INSUFFICIENT_DATA. Closing is idempotent on both APIs.
+pub(crate) fn incident_action(insufficient_data: &InsufficientDataAction, alarm: &AlarmDetails) -> Option<IncidentAction> {
+    if opens_incident(insufficient_data, &alarm.new_state) {
+        return Some(IncidentAction::Open);
+    }
+    match alarm.new_state {
+        AlarmState::Ok => Some(IncidentAction::Close),
+        AlarmState::InsufficientData if *insufficient_data == InsufficientDataAction::Ignore => None,
+        _ if opens_incident(insufficient_data, &alarm.previous_state) => Some(IncidentAction::Close),
+        _ => None,
+    }
+}
+
+pub(crate) fn notify_incident<M: IncidentManager>(
+    manager: &M,
+    client: &Client<HttpsConnector<HttpConnector>>,
+    core: &mut Core,
+    alarm: &AlarmDetails,
+) -> Result<(), NotifierError> {
+    let alias = incident_alias(alarm, manager.alias_limit());
+    match incident_action(manager.insufficient_data(), alarm) {
+        Some(IncidentAction::Open) => manager.open(client, core, &alias, alarm),
+        Some(IncidentAction::Close) => manager.close(client, core, &alias, alarm),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    fn action(insufficient_data: InsufficientDataAction, new_state: &str, previous_state: &str) -> Option<IncidentAction> {
+        incident_action(&insufficient_data, &alarm_details(new_state, previous_state))
+    }
+
+    #[test]
+    fn test_insufficient_data_action_from_string() {
+        let close: InsufficientDataAction = "close".parse().expect("Should not happen");
+        let no_match: EnumResult<InsufficientDataAction> = "resolve".parse();
+
+        assert_eq!(close, InsufficientDataAction::Close);
+        assert_eq!(no_match.is_ok(), false);
+    }
+
+    #[test]
+    fn test_incident_action_alarm_and_ok() {
+        assert_eq!(action(InsufficientDataAction::Ignore, "ALARM", "OK"), Some(IncidentAction::Open));
+        assert_eq!(action(InsufficientDataAction::Ignore, "OK", "ALARM"), Some(IncidentAction::Close));
+        assert_eq!(action(InsufficientDataAction::Ignore, "OK", "INSUFFICIENT_DATA"), Some(IncidentAction::Close));
+        assert_eq!(action(InsufficientDataAction::Close, "OK", "INSUFFICIENT_DATA"), Some(IncidentAction::Close));
+    }
+
+    #[test]
+    fn test_incident_action_insufficient_data() {
+        assert_eq!(action(InsufficientDataAction::Open, "INSUFFICIENT_DATA", "OK"), Some(IncidentAction::Open));
+        assert_eq!(action(InsufficientDataAction::Open, "OK", "INSUFFICIENT_DATA"), Some(IncidentAction::Close));
+        assert_eq!(action(InsufficientDataAction::Close, "INSUFFICIENT_DATA", "ALARM"), Some(IncidentAction::Close));
+        assert_eq!(action(InsufficientDataAction::Close, "INSUFFICIENT_DATA", "OK"), None);
+        assert_eq!(action(InsufficientDataAction::Ignore, "INSUFFICIENT_DATA", "ALARM"), None);
+    }
+
+    #[test]
+    fn test_incident_alias_falls_back_to_name() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        assert_eq!(
+            incident_alias(&alarm, 255),
+            "arn:aws:cloudwatch:us-east-1:097958131044:alarm:[RTB-US] UnHealthyHostCount"
+        );
+
+        alarm.arn = None;
+        assert_eq!(incident_alias(&alarm, 255), "[RTB-US] UnHealthyHostCount");
+    }
+}
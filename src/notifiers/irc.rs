@@ -0,0 +1,620 @@
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use native_tls::TlsConnector;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, TryRecvError, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::alarm_console_url;
+use model::{AlarmDetails, AlarmState};
+use oncall::Mention;
+use super::{Notifier, NotifierError};
+
+// RFC 1459 caps lines at 512 bytes including the trailing CRLF, and servers
+// prepend ":nick!user@host " when relaying our messages, so keep room for it.
+const MAX_LINE_BYTES: usize = 512;
+const PREFIX_ALLOWANCE: usize = 100;
+
+const POLL_INTERVAL_MS: u64 = 200;
+// Servers ping idle clients every few minutes, a longer silence means the
+// connection is dead even if the socket does not know it yet.
+const IDLE_TIMEOUT_SECS: u64 = 300;
+const MIN_RECONNECT_DELAY_SECS: u64 = 1;
+const MAX_RECONNECT_DELAY_SECS: u64 = 60;
+
+// Most servers apply a 2 seconds penalty per line and disconnect clients more
+// than 10 seconds ahead, stay a bit below that.
+const FLOOD_INTERVAL_SECS: u64 = 2;
+const FLOOD_BURST_SECS: u64 = 8;
+
+// Leaves time to connect, register and wait for flood control. Past this, the
+// lines are dropped unsent and the delivery fails, to be retried by the queue.
+const DELIVERY_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone)]
+pub(crate) struct IrcConfig {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    pub nickname: String,
+    pub realname: String,
+    pub nickserv_password: Option<String>,
+    pub channels: Vec<String>,
+}
+
+// Keeps a connection open in a background thread, alarms are handed over
+// through a channel and sent once the bot is registered and has joined, the
+// delivery waiting until they are written. The connection is only opened for
+// the first alarm, a configuration which is rejected or reloaded before never
+// connects, and it is closed once the notifier is dropped so that the one
+// replacing it can take the nickname.
+#[derive(Debug)]
+pub(crate) struct IrcNotifier {
+    config: IrcConfig,
+    sender: Mutex<Option<Sender<Delivery>>>,
+    l10n: Localizer,
+}
+
+// The lines of an alarm, `done` gets the result once they are all written.
+#[derive(Debug)]
+struct Delivery {
+    lines: Vec<String>,
+    sent: usize,
+    // Nobody waits for the lines anymore.
+    deadline: Instant,
+    done: Sender<io::Result<()>>,
+}
+
+impl Delivery {
+    fn finish(self, result: io::Result<()>) {
+        let _ = self.done.send(result);
+    }
+}
+
+// Every delivery waiting gets the error, the lines of the one being written
+// may have been partly sent.
+fn fail(pending: &mut VecDeque<Delivery>, err: &io::Error) {
+    for delivery in pending.drain(..) {
+        delivery.finish(Err(io::Error::new(err.kind(), err.to_string())));
+    }
+}
+
+fn expire(pending: &mut VecDeque<Delivery>, now: Instant) {
+    while pending.front().map_or(false, |delivery| delivery.deadline <= now) {
+        if let Some(delivery) = pending.pop_front() {
+            delivery.finish(Err(io::Error::new(ErrorKind::TimedOut, "not sent in time")));
+        }
+    }
+}
+
+impl IrcNotifier {
+    pub fn new(config: IrcConfig, l10n: Localizer) -> IrcNotifier {
+        IrcNotifier {
+            config: config,
+            sender: Mutex::new(None),
+            l10n: l10n,
+        }
+    }
+}
+
+fn state_color(state: &AlarmState) -> &'static str {
+    match *state {
+        AlarmState::Ok => "03",
+        AlarmState::Alarm => "04",
+        AlarmState::InsufficientData => "07",
+    }
+}
+
+fn text(alarm: &AlarmDetails, l10n: &Localizer) -> String {
+    let mut text = format!(
+        "\x02\x03{}{}\x0f \x02{}\x02: {}\n{} {}",
+        state_color(&alarm.new_state),
+        l10n.state(&alarm.new_state),
+        alarm.name,
+        alarm.reason,
+        alarm.trigger.namespace,
+        l10n.condition(alarm)
+    );
+    if let Some(url) = alarm_console_url(alarm) {
+        text.push_str(&format!(" | {}", url));
+    }
+    text
+}
+
+// Splits `text` into PRIVMSG payloads to `target` which fit in an IRC line,
+// breaking on whitespace when possible and never inside a UTF-8 character.
+fn split_message(target: &str, text: &str) -> Vec<String> {
+    let limit = MAX_LINE_BYTES - "\r\n".len() - PREFIX_ALLOWANCE - "PRIVMSG  :".len() - target.len();
+    let mut lines = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut rest = paragraph.trim();
+        while rest.len() > limit {
+            let mut cut = limit;
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            if let Some(space) = rest[..cut].rfind(' ') {
+                if space > 0 {
+                    cut = space;
+                }
+            }
+            lines.push(rest[..cut].to_string());
+            rest = rest[cut..].trim_left();
+        }
+        if !rest.is_empty() {
+            lines.push(rest.to_string());
+        }
+    }
+    lines
+}
+
+#[derive(Debug)]
+struct FloodControl {
+    interval: Duration,
+    burst: Duration,
+    clock: Instant,
+}
+
+impl FloodControl {
+    fn new(now: Instant) -> FloodControl {
+        FloodControl {
+            interval: Duration::from_secs(FLOOD_INTERVAL_SECS),
+            burst: Duration::from_secs(FLOOD_BURST_SECS),
+            clock: now,
+        }
+    }
+
+    // How long to wait before the next line can be sent.
+    fn ready_in(&self, now: Instant) -> Duration {
+        let allowed = now + self.burst;
+        if self.clock > allowed {
+            self.clock - allowed
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
+    fn record(&mut self, now: Instant) {
+        self.clock = cmp::max(self.clock, now) + self.interval;
+    }
+}
+
+trait Stream: Read + Write + Send {}
+impl<S: Read + Write + Send> Stream for S {}
+
+fn open(config: &IrcConfig) -> io::Result<Box<Stream>> {
+    let tcp = TcpStream::connect((config.host.as_str(), config.port))?;
+    if !config.tls {
+        tcp.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS)))?;
+        return Ok(Box::new(tcp));
+    }
+
+    let connector = TlsConnector::builder()
+        .and_then(|builder| builder.build())
+        .map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))?;
+    let tls = connector
+        .connect(&config.host, tcp)
+        .map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))?;
+    // Only after the handshake, which does not expect reads to time out.
+    tls.get_ref().set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS)))?;
+    Ok(Box::new(tls))
+}
+
+// Splits a raw IRC line into its command and parameters, dropping the prefix.
+fn parse_line(line: &str) -> (&str, &str) {
+    let line = if line.starts_with(':') {
+        line.splitn(2, ' ').nth(1).unwrap_or("")
+    } else {
+        line
+    };
+    let mut parts = line.splitn(2, ' ');
+    (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+}
+
+struct Session<'a> {
+    config: &'a IrcConfig,
+    stream: Box<Stream>,
+    nickname: String,
+    registered: bool,
+    flood_control: FloodControl,
+}
+
+impl<'a> Session<'a> {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.stream.flush()
+    }
+
+    fn register(&mut self) -> io::Result<()> {
+        let nick = format!("NICK {}", self.nickname);
+        let user = format!("USER {} 0 * :{}", self.nickname, self.config.realname);
+        self.write_line(&nick)?;
+        self.write_line(&user)
+    }
+
+    fn handle_line(&mut self, line: &str) -> io::Result<()> {
+        match parse_line(line) {
+            ("PING", token) => self.write_line(&format!("PONG {}", token)),
+            // RPL_WELCOME
+            ("001", _) => {
+                self.registered = true;
+                let config = self.config;
+                if let Some(ref password) = config.nickserv_password {
+                    self.write_line(&format!("PRIVMSG NickServ :IDENTIFY {}", password))?;
+                }
+                for channel in &config.channels {
+                    self.write_line(&format!("JOIN {}", channel))?;
+                }
+                Ok(())
+            }
+            // ERR_NICKNAMEINUSE
+            ("433", _) if !self.registered => {
+                self.nickname.push('_');
+                let nick = format!("NICK {}", self.nickname);
+                self.write_line(&nick)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Writes the next line of the first delivery.
+    fn send(&mut self, pending: &mut VecDeque<Delivery>, now: Instant) -> io::Result<()> {
+        let finished = match pending.front_mut() {
+            Some(delivery) => {
+                self.write_line(&delivery.lines[delivery.sent])?;
+                self.flood_control.record(now);
+                delivery.sent += 1;
+                delivery.sent == delivery.lines.len()
+            }
+            None => false,
+        };
+        if finished {
+            if let Some(delivery) = pending.pop_front() {
+                delivery.finish(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    // Runs until the connection fails, or until the notifier is gone.
+    fn run(&mut self, receiver: &Receiver<Delivery>, pending: &mut VecDeque<Delivery>) -> io::Result<()> {
+        self.register()?;
+
+        let mut buffer = Vec::new();
+        let mut chunk = [0; 4096];
+        let mut last_activity = Instant::now();
+
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed by server")),
+                Ok(n) => {
+                    last_activity = Instant::now();
+                    buffer.extend_from_slice(&chunk[..n]);
+                    while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                        let line: Vec<u8> = buffer.drain(..end + 1).collect();
+                        let line = String::from_utf8_lossy(&line);
+                        self.handle_line(line.trim_right())?;
+                    }
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    if last_activity.elapsed() > Duration::from_secs(IDLE_TIMEOUT_SECS) {
+                        return Err(io::Error::new(ErrorKind::TimedOut, "no activity from server"));
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+
+            if !receive(receiver, pending) {
+                return self.write_line("QUIT");
+            }
+            expire(pending, Instant::now());
+
+            while self.registered && !pending.is_empty() {
+                let now = Instant::now();
+                if self.flood_control.ready_in(now) > Duration::from_secs(0) {
+                    break;
+                }
+                self.send(pending, now)?;
+            }
+        }
+    }
+}
+
+// Moves the deliveries handed over to `pending`, `false` once the notifier is
+// gone: nobody waits for them anymore.
+fn receive(receiver: &Receiver<Delivery>, pending: &mut VecDeque<Delivery>) -> bool {
+    loop {
+        match receiver.try_recv() {
+            Ok(delivery) => pending.push_back(delivery),
+            Err(TryRecvError::Empty) => return true,
+            Err(TryRecvError::Disconnected) => return false,
+        }
+    }
+}
+
+fn run(config: &IrcConfig, receiver: &Receiver<Delivery>) {
+    let mut pending = VecDeque::new();
+    let mut reconnect_delay = Duration::from_secs(MIN_RECONNECT_DELAY_SECS);
+
+    loop {
+        let err = match open(config) {
+            Ok(stream) => {
+                let mut session = Session {
+                    config: config,
+                    stream: stream,
+                    nickname: config.nickname.clone(),
+                    registered: false,
+                    flood_control: FloodControl::new(Instant::now()),
+                };
+                let result = session.run(receiver, &mut pending);
+                if session.registered {
+                    reconnect_delay = Duration::from_secs(MIN_RECONNECT_DELAY_SECS);
+                }
+                match result {
+                    Ok(()) => return,
+                    Err(err) => err,
+                }
+            }
+            Err(err) => err,
+        };
+
+        // The queue retries the deliveries, rather than have them wait for a
+        // connection which may not come back in time.
+        if !receive(receiver, &mut pending) {
+            return;
+        }
+        fail(&mut pending, &err);
+
+        let retry_at = Instant::now() + reconnect_delay;
+        while Instant::now() < retry_at {
+            thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+            if !receive(receiver, &mut pending) {
+                return;
+            }
+            fail(&mut pending, &err);
+        }
+        reconnect_delay = cmp::min(reconnect_delay * 2, Duration::from_secs(MAX_RECONNECT_DELAY_SECS));
+    }
+}
+
+impl Notifier for IrcNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.notify_text(client, core, alarm, &text(alarm, &self.l10n))
+    }
+
+    fn notify_text(
+        &self,
+        _client: &Client<HttpsConnector<HttpConnector>>,
+        _core: &mut Core,
+        _alarm: &AlarmDetails,
+        text: &str,
+    ) -> Result<(), NotifierError> {
+        let mut lines = Vec::new();
+        for channel in &self.config.channels {
+            for line in split_message(channel, text) {
+                lines.push(format!("PRIVMSG {} :{}", channel, line));
+            }
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let (done, result) = channel();
+        let delivery = Delivery {
+            lines: lines,
+            sent: 0,
+            deadline: Instant::now() + Duration::from_secs(DELIVERY_TIMEOUT_SECS),
+            done: done,
+        };
+        {
+            let mut sender = self.sender.lock().expect("IRC sender lock poisoned");
+            if sender.is_none() {
+                let (connection, receiver) = channel();
+                let config = self.config.clone();
+                thread::spawn(move || run(&config, &receiver));
+                *sender = Some(connection);
+            }
+            let sent = sender.as_ref().map(|sender| sender.send(delivery).is_ok());
+            if sent != Some(true) {
+                // The connection thread is gone, the next delivery starts another.
+                *sender = None;
+                return Err(NotifierError::Disconnected);
+            }
+        }
+
+        // Connecting may block past the deadline, don't wait for it forever.
+        match result.recv_timeout(Duration::from_secs(DELIVERY_TIMEOUT_SECS + 1)) {
+            Ok(result) => result.map_err(NotifierError::IoError),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                Err(NotifierError::IoError(io::Error::new(ErrorKind::TimedOut, "not sent in time")))
+            }
+        }
+    }
+
+    // Clients highlight messages starting with the user's nick.
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let text = match text {
+            Some(text) => text.to_string(),
+            None => self::text(alarm, &self.l10n),
+        };
+        let nicks: Vec<&str> = mentions
+            .iter()
+            .filter_map(|mention| match *mention {
+                Mention::User(ref nick) => Some(nick.as_str()),
+                _ => None,
+            })
+            .collect();
+        if nicks.is_empty() {
+            return self.notify_text(client, core, alarm, &text);
+        }
+        self.notify_text(client, core, alarm, &format!("{}: {}", nicks.join(", "), text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{core, https_client};
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    fn config(port: u16) -> IrcConfig {
+        IrcConfig {
+            host: "127.0.0.1".to_string(),
+            port: port,
+            tls: false,
+            nickname: "cwbot".to_string(),
+            realname: "CloudWatch bot".to_string(),
+            nickserv_password: Some("hunter2".to_string()),
+            channels: vec!["#ops".to_string()],
+        }
+    }
+
+    fn read_line(reader: &mut BufRead) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).expect("Failed to read from client");
+        line.trim_right().to_string()
+    }
+
+    #[test]
+    fn test_split_message_short() {
+        assert_eq!(split_message("#ops", "first line\nsecond line\n\n"), vec!["first line", "second line"]);
+    }
+
+    #[test]
+    fn test_split_message_long() {
+        let text = "é".repeat(300) + " " + &"word ".repeat(200);
+        let lines = split_message("#ops", &text);
+
+        assert_eq!(lines.len() > 2, true);
+        for line in &lines {
+            assert_eq!(format!("PRIVMSG #ops :{}\r\n", line).len() + PREFIX_ALLOWANCE <= MAX_LINE_BYTES, true);
+        }
+        assert_eq!(lines.concat().replace(' ', ""), text.replace(' ', ""));
+    }
+
+    #[test]
+    fn test_flood_control() {
+        let start = Instant::now();
+        let mut flood_control = FloodControl::new(start);
+
+        for _ in 0..5 {
+            assert_eq!(flood_control.ready_in(start), Duration::from_secs(0));
+            flood_control.record(start);
+        }
+        assert_eq!(flood_control.ready_in(start), Duration::from_secs(2));
+        assert_eq!(flood_control.ready_in(start + Duration::from_secs(2)), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("PING :irc.example.org"), ("PING", ":irc.example.org"));
+        assert_eq!(parse_line(":irc.example.org 001 cwbot :Welcome"), ("001", "cwbot :Welcome"));
+    }
+
+    #[test]
+    fn test_text() {
+        let text = text(&alarm_details("ALARM", "OK"), &Localizer::default());
+        assert_eq!(text.starts_with("\x02\x0304ALARM\x0f \x02[RTB-US] UnHealthyHostCount\x02: Threshold Crossed"), true);
+    }
+
+    // Delivers an alarm from another thread, it waits for the lines to be
+    // written.
+    fn notify(notifier: &Arc<IrcNotifier>) -> thread::JoinHandle<bool> {
+        let notifier = notifier.clone();
+        thread::spawn(move || {
+            let mut core = core();
+            let client = https_client(&core);
+            notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).is_ok()
+        })
+    }
+
+    // Accepts the bot and lets it in under the second nickname.
+    fn register(listener: &TcpListener) -> (BufReader<TcpStream>, TcpStream) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+
+        assert_eq!(read_line(&mut reader), "NICK cwbot");
+        assert_eq!(read_line(&mut reader), "USER cwbot 0 * :CloudWatch bot");
+        writer.write_all(b":irc.test 433 * cwbot :Nickname is already in use\r\n").unwrap();
+        assert_eq!(read_line(&mut reader), "NICK cwbot_");
+        writer.write_all(b":irc.test 001 cwbot_ :Welcome\r\n").unwrap();
+        assert_eq!(read_line(&mut reader), "PRIVMSG NickServ :IDENTIFY hunter2");
+        assert_eq!(read_line(&mut reader), "JOIN #ops");
+        (reader, writer)
+    }
+
+    #[test]
+    fn test_registers_joins_and_reconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let notifier = Arc::new(IrcNotifier::new(config(listener.local_addr().unwrap().port()), Localizer::default()));
+
+        // Nothing is sent yet, the notifier doesn't connect.
+        listener.set_nonblocking(true).unwrap();
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+        assert_eq!(listener.accept().is_err(), true);
+        listener.set_nonblocking(false).unwrap();
+
+        // The first alarm waits for the connection, the second one is sent
+        // once the bot is back.
+        let mut first = Some(notify(&notifier));
+        for _ in 0..2 {
+            let (mut reader, mut writer) = register(&listener);
+            let delivery = first.take().unwrap_or_else(|| notify(&notifier));
+            assert_eq!(read_line(&mut reader).starts_with("PRIVMSG #ops :\x02\x0304ALARM"), true);
+            assert_eq!(read_line(&mut reader).starts_with("PRIVMSG #ops :AWS/ELB UnHealthyHostCount >= 1"), true);
+            assert_eq!(delivery.join().unwrap(), true);
+
+            writer.write_all(b"PING :irc.test\r\n").unwrap();
+            assert_eq!(read_line(&mut reader), "PONG :irc.test");
+            // Dropping the connection, the notifier should come back.
+        }
+    }
+
+    #[test]
+    fn test_delivery_fails_without_server() {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let notifier = Arc::new(IrcNotifier::new(config(port), Localizer::default()));
+
+        assert_eq!(notify(&notifier).join().unwrap(), false);
+    }
+
+    #[test]
+    fn test_quits_once_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let notifier = Arc::new(IrcNotifier::new(config(listener.local_addr().unwrap().port()), Localizer::default()));
+
+        let delivery = notify(&notifier);
+        let (mut reader, _writer) = register(&listener);
+        read_line(&mut reader);
+        read_line(&mut reader);
+        assert_eq!(delivery.join().unwrap(), true);
+
+        drop(notifier);
+        assert_eq!(read_line(&mut reader), "QUIT");
+    }
+}
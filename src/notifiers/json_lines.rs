@@ -0,0 +1,159 @@
+use chrono::Utc;
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tokio_core::reactor::Core;
+
+use model::AlarmDetails;
+use oncall::Mention;
+use routing::RoutingDecision;
+use super::{Notifier, NotifierError, alarm_json};
+
+// Writes every alarm as one JSON document per line, for log shipping and for
+// running the bot locally without any chat credentials.
+pub(crate) struct JsonLinesNotifier {
+    output: Mutex<Box<Write + Send>>,
+}
+
+impl fmt::Debug for JsonLinesNotifier {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "JsonLinesNotifier")
+    }
+}
+
+impl JsonLinesNotifier {
+    pub fn new(output: Box<Write + Send>) -> JsonLinesNotifier {
+        JsonLinesNotifier { output: Mutex::new(output) }
+    }
+
+    pub fn stdout() -> JsonLinesNotifier {
+        JsonLinesNotifier::new(Box::new(io::stdout()))
+    }
+
+    pub fn file(path: &Path) -> io::Result<JsonLinesNotifier> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonLinesNotifier::new(Box::new(file)))
+    }
+
+    fn line(alarm: &AlarmDetails, routing: &Value) -> String {
+        let mut alarm = alarm_json(alarm);
+        let envelope = alarm.as_object_mut().and_then(|a| a.remove("envelope")).unwrap_or(Value::Null);
+
+        json!({
+            "recorded_at": Utc::now().to_rfc3339(),
+            "envelope": envelope,
+            "alarm": alarm,
+            "routing": routing,
+        }).to_string()
+    }
+
+    // The whole line goes in a single write so concurrent deliveries never
+    // interleave.
+    pub(crate) fn write_event(&self, alarm: &AlarmDetails, routing: &Value) -> Result<(), NotifierError> {
+        let mut line = Self::line(alarm, routing);
+        line.push('\n');
+
+        let mut output = self.output.lock().expect("JSON lines output lock poisoned");
+        output.write_all(line.as_bytes())?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+impl Notifier for JsonLinesNotifier {
+    fn notify(
+        &self,
+        _client: &Client<HttpsConnector<HttpConnector>>,
+        _core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.write_event(alarm, &Value::Null)
+    }
+
+    // The line is the same with a template or mentions, it holds the whole
+    // alarm and decision already.
+    fn notify_routed(
+        &self,
+        _client: &Client<HttpsConnector<HttpConnector>>,
+        _core: &mut Core,
+        alarm: &AlarmDetails,
+        decision: &RoutingDecision,
+        _text: Option<&str>,
+        _mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        self.write_event(alarm, &decision.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{core, https_client};
+    use routing::Router;
+    use serde_json;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_event() {
+        let buffer = SharedBuffer::default();
+        let notifier = JsonLinesNotifier::new(Box::new(buffer.clone()));
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.envelope.message_id = "d921a633-3dbb-528e-a15c-e978b55d6156".to_string();
+
+        notifier.write_event(&alarm, &json!({"destinations": ["rtb"]})).unwrap();
+        notifier.write_event(&alarm_details("OK", "ALARM"), &Value::Null).unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(output.ends_with('\n'), true);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["envelope"]["message_id"], "d921a633-3dbb-528e-a15c-e978b55d6156");
+        assert_eq!(lines[0]["alarm"]["name"], "[RTB-US] UnHealthyHostCount");
+        assert_eq!(lines[0]["alarm"].get("envelope"), None);
+        assert_eq!(lines[0]["routing"]["destinations"][0], "rtb");
+        assert_eq!(lines[1]["alarm"]["new_state"], "OK");
+        assert_eq!(lines[1]["routing"], Value::Null);
+    }
+
+    #[test]
+    fn test_routing_with_template_and_mentions() {
+        let buffer = SharedBuffer::default();
+        let notifier = JsonLinesNotifier::new(Box::new(buffer.clone()));
+        let alarm = alarm_details("ALARM", "OK");
+        let mut router = Router::default();
+        router.default_destinations = vec!["audit".to_string()];
+        let decision = router.route(&alarm);
+        let mut core = core();
+        let client = https_client(&core);
+
+        notifier.notify_routed(&client, &mut core, &alarm, &decision, Some("text"), &[]).unwrap();
+        notifier
+            .notify_routed(&client, &mut core, &alarm, &decision, None, &[Mention::Here])
+            .unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines[0]["routing"]["destinations"][0], "audit");
+        assert_eq!(lines[1]["routing"]["destinations"][0], "audit");
+    }
+}
@@ -0,0 +1,519 @@
+use hyper::{Client, Method, Request, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::{Authorization, Bearer, ContentType};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, encode_component, metric_graph_url};
+use model::AlarmDetails;
+use oncall::Mention;
+use sparkline::{self, FILENAME as SPARKLINE_FILENAME};
+use super::{GroupMessage, Notifier, NotifierError, escape_html, hash, json_request, read_json, send, state_color};
+use super::posts::{Posts, resolved_after};
+
+const MAX_ATTEMPTS: u32 = 3;
+
+#[derive(Debug)]
+pub(crate) struct MatrixNotifier {
+    pub homeserver_url: Uri,
+    pub access_token: String,
+    pub room_id: String,
+    pub l10n: Localizer,
+    pub posts: Posts,
+}
+
+impl MatrixNotifier {
+    // The same transition always gets the same transaction ID, so the
+    // homeserver drops the duplicates when a send is retried.
+    fn transaction_id(&self, alarm: &AlarmDetails) -> String {
+        let key = alarm.arn.as_ref().unwrap_or(&alarm.name);
+        let transition = [self.room_id.as_str(), key, &alarm.new_state.to_string(), &alarm.timestamp];
+        format!("cloudwatch-bot.{}", hash(&transition))
+    }
+
+    // Every version of a group's message is a transaction of its own.
+    fn group_transaction_id(&self, group: &GroupMessage) -> String {
+        format!("cloudwatch-bot.group.{}", hash(&[&self.room_id, group.id, group.text]))
+    }
+
+    fn send_url(&self, transaction_id: &str) -> Result<Uri, NotifierError> {
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            self.homeserver_url.as_ref().trim_right_matches('/'),
+            encode_component(&self.room_id),
+            transaction_id
+        );
+        url.parse().map_err(|_| NotifierError::InvalidUrl(url))
+    }
+
+    fn upload_url(&self) -> Result<Uri, NotifierError> {
+        let url = format!(
+            "{}/_matrix/media/v3/upload?filename={}",
+            self.homeserver_url.as_ref().trim_right_matches('/'),
+            SPARKLINE_FILENAME
+        );
+        url.parse().map_err(|_| NotifierError::InvalidUrl(url))
+    }
+
+    // Returns the `mxc://` URI of the uploaded image.
+    fn upload(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        image: Vec<u8>,
+    ) -> Result<String, NotifierError> {
+        let mut req = Request::new(Method::Post, self.upload_url()?);
+        req.headers_mut().set(ContentType::png());
+        req.headers_mut().set(Authorization(Bearer { token: self.access_token.clone() }));
+        req.set_body(image);
+
+        let resp = send(client, core, req)?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(NotifierError::BadStatus(status));
+        }
+        read_json(core, resp)?["content_uri"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| NotifierError::InvalidResponse("missing content_uri".to_string()))
+    }
+
+    fn image_content(&self, alarm: &AlarmDetails, content_uri: &str, size: usize) -> Value {
+        json!({
+            "msgtype": "m.image",
+            "body": self.l10n.message("datapoints", &[("metric", &alarm.trigger.metric_name)]),
+            "filename": SPARKLINE_FILENAME,
+            "url": content_uri,
+            "info": {"mimetype": "image/png", "size": size},
+        })
+    }
+
+    fn content(&self, alarm: &AlarmDetails) -> Value {
+        let l10n = &self.l10n;
+        let trigger = &alarm.trigger;
+        let state = l10n.state(&alarm.new_state);
+        let threshold = l10n.condition(alarm);
+        let console_url = alarm_console_url(alarm);
+
+        let mut body = format!(
+            "{}: {}\n{}\n{} {}",
+            state,
+            alarm.name,
+            alarm.reason,
+            trigger.namespace,
+            threshold
+        );
+        let mut formatted_body = format!(
+            "<strong><font color=\"{}\">{}</font></strong>: <strong>{}</strong><br/>{}<br/><code>{}</code> {}",
+            state_color(&alarm.new_state),
+            escape_html(&state),
+            escape_html(&alarm.name),
+            escape_html(&alarm.reason),
+            escape_html(&trigger.namespace),
+            escape_html(&threshold)
+        );
+        if let Some(url) = console_url {
+            body.push_str(&format!("\n{}", url));
+            formatted_body.push_str(&format!(
+                "<br/><a href=\"{}\">{}</a>",
+                escape_html(&url),
+                escape_html(&l10n.text("open-in-cloudwatch"))
+            ));
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            body.push_str(&format!("\n{}", url));
+            formatted_body.push_str(&format!(
+                "<br/><a href=\"{}\">{}</a>",
+                escape_html(&url),
+                escape_html(&l10n.text("metric-graph"))
+            ));
+        }
+
+        json!({
+            "msgtype": "m.notice",
+            "body": body,
+            "format": "org.matrix.custom.html",
+            "formatted_body": formatted_body,
+        })
+    }
+
+    fn send_content(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        transaction_id: &str,
+        content: &Value,
+    ) -> Result<Option<String>, NotifierError> {
+        let url = self.send_url(transaction_id)?;
+        let mut result = Err(NotifierError::BadStatus(StatusCode::ServiceUnavailable));
+
+        for _ in 0..MAX_ATTEMPTS {
+            let mut req = json_request(Method::Put, url.clone(), content);
+            req.headers_mut().set(Authorization(Bearer { token: self.access_token.clone() }));
+
+            result = send(client, core, req).and_then(|resp| {
+                let status = resp.status();
+                if status.is_success() {
+                    Ok(resp)
+                } else {
+                    Err(NotifierError::BadStatus(status))
+                }
+            });
+            match result {
+                Ok(_) => break,
+                Err(NotifierError::BadStatus(status)) if !status.is_server_error() && status != StatusCode::TooManyRequests => {
+                    break
+                }
+                Err(_) => continue,
+            }
+        }
+        // The ID of the event, to edit it later.
+        let resp = result?;
+        Ok(read_json(core, resp).ok().and_then(|event| event["event_id"].as_str().map(str::to_string)))
+    }
+}
+
+// An edit of `event_id`, clients which don't support edits show the new
+// content after an asterisk.
+fn replacement(content: Value, event_id: &str) -> Value {
+    let mut fallback = content.clone();
+    fallback["body"] = json!(format!("* {}", content["body"].as_str().unwrap_or("")));
+    if let Some(formatted_body) = content["formatted_body"].as_str() {
+        fallback["formatted_body"] = json!(format!("* {}", formatted_body));
+    }
+    fallback["m.new_content"] = content;
+    fallback["m.relates_to"] = json!({"rel_type": "m.replace", "event_id": event_id});
+    fallback
+}
+
+// Pills for the users, `@room` for everyone. Notices don't notify anyone
+// under the default push rules, so a message mentioning someone is sent as
+// text.
+fn mention(content: &mut Value, mentions: &[Mention]) {
+    let mut user_ids = Vec::new();
+    let mut names = Vec::new();
+    let mut pills = Vec::new();
+    let mut room = false;
+    for mention in mentions {
+        match *mention {
+            Mention::User(ref id) => {
+                names.push(id.clone());
+                pills.push(format!("<a href=\"https://matrix.to/#/{}\">{}</a>", escape_html(id), escape_html(id)));
+                user_ids.push(id.clone());
+            }
+            Mention::Here => {
+                names.push("@room".to_string());
+                pills.push("@room".to_string());
+                room = true;
+            }
+            Mention::Group(_) => {}
+        }
+    }
+    if names.is_empty() {
+        return;
+    }
+
+    let body = content["body"].as_str().unwrap_or("").to_string();
+    let formatted_body = match content["formatted_body"].as_str() {
+        Some(formatted_body) => formatted_body.to_string(),
+        None => escape_html(&body).replace('\n', "<br/>"),
+    };
+    content["msgtype"] = json!("m.text");
+    content["body"] = json!(format!("{}: {}", names.join(" "), body));
+    content["format"] = json!("org.matrix.custom.html");
+    content["formatted_body"] = json!(format!("{}: {}", pills.join(" "), formatted_body));
+    content["m.mentions"] = json!({"user_ids": user_ids, "room": room});
+}
+
+impl Notifier for MatrixNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, None, &[])
+    }
+
+    fn notify_text(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: &str,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, Some(text), &[])
+    }
+
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let transaction_id = self.transaction_id(alarm);
+        let post = self.posts.get(alarm);
+        let mut content = match text {
+            Some(text) => json!({"msgtype": "m.notice", "body": text}),
+            None => self.content(alarm),
+        };
+        mention(&mut content, mentions);
+
+        // The recovery replaces the message of the alarm.
+        if let Some(post) = post {
+            if text.is_none() {
+                if let Some(resolved) = resolved_after(&self.l10n, &post, alarm) {
+                    let body = format!("{}\n{}", content["body"].as_str().unwrap_or(""), resolved);
+                    let formatted_body = format!(
+                        "{}<br/><em>{}</em>",
+                        content["formatted_body"].as_str().unwrap_or(""),
+                        escape_html(&resolved)
+                    );
+                    content["body"] = json!(body);
+                    content["formatted_body"] = json!(formatted_body);
+                }
+            }
+            self.send_content(client, core, &transaction_id, &replacement(content, &post.id))?;
+            self.posts.forget(alarm);
+            return Ok(());
+        }
+
+        if let Some(event_id) = self.send_content(client, core, &transaction_id, &content)? {
+            self.posts.remember(alarm, event_id);
+        }
+
+        // Sent as a follow-up event, a notice cannot carry an image.
+        if text.is_none() {
+            if let Some(image) = sparkline::render_png(alarm) {
+                let size = image.len();
+                let content_uri = self.upload(client, core, image)?;
+                let content = self.image_content(alarm, &content_uri, size);
+                self.send_content(client, core, &format!("{}.sparkline", transaction_id), &content)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn can_group(&self) -> bool {
+        true
+    }
+
+    fn notify_group(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        group: &GroupMessage,
+    ) -> Result<(), NotifierError> {
+        let transaction_id = self.group_transaction_id(group);
+        let lines: Vec<String> = group.text.lines().map(escape_html).collect();
+        let content = json!({
+            "msgtype": "m.notice",
+            "body": group.text,
+            "format": "org.matrix.custom.html",
+            "formatted_body": lines.join("<br/>"),
+        });
+
+        let id = if group.update { self.posts.group(group.id) } else { None };
+        if let Some(id) = id {
+            return self.send_content(client, core, &transaction_id, &replacement(content, &id))
+                .map(|_| ());
+        }
+        if let Some(event_id) = self.send_content(client, core, &transaction_id, &content)? {
+            self.posts.remember_group(group.id, event_id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+    use serde_json;
+
+    fn notifier(homeserver_url: &str) -> MatrixNotifier {
+        MatrixNotifier {
+            homeserver_url: homeserver_url.parse().unwrap(),
+            access_token: "syt_secret".to_string(),
+            room_id: "!ops:example.org".to_string(),
+            l10n: Localizer::default(),
+            posts: Posts::default(),
+        }
+    }
+
+    #[test]
+    fn test_content() {
+        let content = notifier("https://matrix.example.org").content(&alarm_details("ALARM", "OK"));
+        let formatted_body = content["formatted_body"].as_str().unwrap();
+
+        assert_eq!(content["msgtype"], "m.notice");
+        assert_eq!(content["format"], "org.matrix.custom.html");
+        assert_eq!(content["body"].as_str().unwrap().lines().next(), Some("ALARM: [RTB-US] UnHealthyHostCount"));
+        assert_eq!(
+            formatted_body.starts_with(
+                "<strong><font color=\"#d13212\">ALARM</font></strong>: <strong>[RTB-US] UnHealthyHostCount</strong>",
+            ),
+            true
+        );
+        assert_eq!(formatted_body.contains("UnHealthyHostCount &gt;= 1"), true);
+    }
+
+    #[test]
+    fn test_mention() {
+        let mut content = notifier("https://matrix.example.org").content(&alarm_details("ALARM", "OK"));
+        mention(
+            &mut content,
+            &[Mention::User("@alice:example.org".to_string()), Mention::Group("ops".to_string()), Mention::Here],
+        );
+
+        assert_eq!(content["msgtype"], "m.text");
+        assert_eq!(content["body"].as_str().unwrap().starts_with("@alice:example.org @room: ALARM: "), true);
+        assert_eq!(
+            content["formatted_body"].as_str().unwrap().starts_with(
+                "<a href=\"https://matrix.to/#/@alice:example.org\">@alice:example.org</a> @room: <strong>",
+            ),
+            true
+        );
+        assert_eq!(content["m.mentions"], json!({"user_ids": ["@alice:example.org"], "room": true}));
+
+        let mut content = json!({"msgtype": "m.notice", "body": "Disk full"});
+        mention(&mut content, &[Mention::Group("ops".to_string())]);
+        assert_eq!(content, json!({"msgtype": "m.notice", "body": "Disk full"}));
+    }
+
+    #[test]
+    fn test_content_is_localized() {
+        let mut notifier = notifier("https://matrix.example.org");
+        notifier.l10n.locale = "fr".parse().unwrap();
+        let content = notifier.content(&alarm_details("ALARM", "OK"));
+        let formatted_body = content["formatted_body"].as_str().unwrap();
+
+        assert_eq!(content["body"].as_str().unwrap().lines().next(), Some("ALARME: [RTB-US] UnHealthyHostCount"));
+        assert_eq!(formatted_body.contains("UnHealthyHostCount supérieur ou égal à 1"), true);
+        assert_eq!(formatted_body.contains(">Ouvrir dans CloudWatch</a>"), true);
+    }
+
+    #[test]
+    fn test_transaction_id_is_stable_per_transition() {
+        let notifier = notifier("https://matrix.example.org");
+        let alarm = alarm_details("ALARM", "OK");
+        let recovery = alarm_details("OK", "ALARM");
+
+        assert_eq!(notifier.transaction_id(&alarm), notifier.transaction_id(&alarm_details("ALARM", "OK")));
+        assert_eq!(notifier.transaction_id(&alarm) == notifier.transaction_id(&recovery), false);
+    }
+
+    #[test]
+    fn test_notify_against_mock_homeserver() {
+        let server = MockServer::start(vec![(StatusCode::Ok, "{\"event_id\": \"$event\"}")]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = notifier(&server.url);
+        let alarm = alarm_details("ALARM", "OK");
+
+        let res = notifier.notify(&client, &mut core, &alarm);
+        assert_eq!(res.is_ok(), true);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, Method::Put);
+        assert_eq!(
+            requests[0].path,
+            format!("/_matrix/client/v3/rooms/!ops%3Aexample.org/send/m.room.message/{}", notifier.transaction_id(&alarm))
+        );
+        assert_eq!(
+            requests[0].headers.get::<Authorization<Bearer>>(),
+            Some(&Authorization(Bearer { token: "syt_secret".to_string() }))
+        );
+        assert_eq!(requests[0].body.contains("\"formatted_body\""), true);
+    }
+
+    #[test]
+    fn test_notify_uploads_sparkline() {
+        let server = MockServer::start(vec![
+            (StatusCode::Ok, "{\"event_id\": \"$event\"}"),
+            (StatusCode::Ok, "{\"content_uri\": \"mxc://example.org/sparkline\"}"),
+            (StatusCode::Ok, "{\"event_id\": \"$image\"}"),
+        ]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = notifier(&server.url);
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.reason = "Threshold Crossed: 2 datapoints [3.0 (27/02/16 11:20:00), 2.0 (27/02/16 11:19:00)] \
+                        were greater than or equal to the threshold (1.0)."
+            .to_string();
+
+        notifier.notify(&client, &mut core, &alarm).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].method, Method::Post);
+        assert_eq!(requests[1].path, "/_matrix/media/v3/upload?filename=sparkline.png");
+        assert_eq!(requests[1].headers.get::<ContentType>(), Some(&ContentType::png()));
+        assert_eq!(
+            requests[2].path,
+            format!(
+                "/_matrix/client/v3/rooms/!ops%3Aexample.org/send/m.room.message/{}.sparkline",
+                notifier.transaction_id(&alarm)
+            )
+        );
+        assert_eq!(requests[2].body.contains("\"url\":\"mxc://example.org/sparkline\""), true);
+    }
+
+    #[test]
+    fn test_recovery_edits_the_alarm_message() {
+        let server = MockServer::start(vec![
+            (StatusCode::Ok, "{\"event_id\": \"$alarm\"}"),
+            (StatusCode::Ok, "{\"event_id\": \"$edit\"}"),
+        ]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = notifier(&server.url);
+        let mut recovery = alarm_details("OK", "ALARM");
+        recovery.timestamp = "2016-02-27T11:26:10.602+0000".to_string();
+
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        notifier.notify(&client, &mut core, &recovery).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        let edit: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(edit["m.relates_to"], json!({"rel_type": "m.replace", "event_id": "$alarm"}));
+        assert_eq!(edit["body"].as_str().unwrap().starts_with("* OK: "), true);
+        assert_eq!(edit["m.new_content"]["body"].as_str().unwrap().ends_with("\nResolved after 5m"), true);
+        assert_eq!(notifier.posts.get(&recovery), None);
+    }
+
+    #[test]
+    fn test_notify_retries_with_same_transaction_id() {
+        let server = MockServer::start(vec![
+            (StatusCode::BadGateway, ""),
+            (StatusCode::Ok, "{\"event_id\": \"$event\"}"),
+        ]);
+        let mut core = core();
+        let client = https_client(&core);
+
+        let res = notifier(&server.url).notify(&client, &mut core, &alarm_details("ALARM", "OK"));
+        assert_eq!(res.is_ok(), true);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, requests[1].path);
+    }
+
+    #[test]
+    fn test_notify_does_not_retry_client_errors() {
+        let server = MockServer::start(vec![(StatusCode::Forbidden, "{\"errcode\": \"M_FORBIDDEN\"}")]);
+        let mut core = core();
+        let client = https_client(&core);
+
+        let res = notifier(&server.url).notify(&client, &mut core, &alarm_details("ALARM", "OK"));
+        assert_eq!(res.is_ok(), false);
+        assert_eq!(server.requests().len(), 1);
+    }
+}
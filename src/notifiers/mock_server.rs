@@ -0,0 +1,107 @@
+use futures::{Future, Stream};
+use hyper::{self, Client, Method, StatusCode};
+use hyper::client::HttpConnector;
+use hyper::header::Headers;
+use hyper::server::{Http, Request, Response, Service};
+use hyper_tls::HttpsConnector;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+use tokio_core::reactor::Core;
+
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedRequest {
+    pub method: Method,
+    pub path: String,
+    pub headers: Headers,
+    pub body: String,
+}
+
+// A local HTTP server standing in for chat APIs in tests. It answers with the
+// given responses in order, the last one being repeated once the others are
+// exhausted.
+#[derive(Debug)]
+pub(crate) struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+struct MockService {
+    responses: Arc<Mutex<VecDeque<(StatusCode, String)>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl Service for MockService {
+    type Request = Request;
+    type Response = Response;
+    type Error = hyper::Error;
+    type Future = Box<Future<Item = Response, Error = hyper::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        let (method, uri, _, headers, body) = req.deconstruct();
+        let requests = self.requests.clone();
+        let responses = self.responses.clone();
+
+        Box::new(body.concat2().map(move |chunk| {
+            requests.lock().unwrap().push(RecordedRequest {
+                method: method,
+                path: uri.as_ref().to_string(),
+                headers: headers,
+                body: String::from_utf8_lossy(&chunk).into_owned(),
+            });
+
+            let mut responses = responses.lock().unwrap();
+            let (status, body) = if responses.len() > 1 {
+                responses.pop_front().unwrap()
+            } else {
+                responses[0].clone()
+            };
+            Response::new().with_status(status).with_body(body)
+        }))
+    }
+}
+
+impl MockServer {
+    pub fn start(responses: Vec<(StatusCode, &str)>) -> MockServer {
+        let responses: VecDeque<(StatusCode, String)> = responses.into_iter().map(|(s, b)| (s, b.to_string())).collect();
+        let responses = Arc::new(Mutex::new(responses));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (tx, rx) = channel();
+
+        let server_requests = requests.clone();
+        thread::spawn(move || {
+            let addr = "127.0.0.1:0".parse().unwrap();
+            let server = Http::new()
+                .bind(&addr, move || {
+                    Ok(MockService {
+                        responses: responses.clone(),
+                        requests: server_requests.clone(),
+                    })
+                })
+                .expect("Failed to start mock server");
+            tx.send(server.local_addr().unwrap()).unwrap();
+            server.run().unwrap();
+        });
+
+        MockServer {
+            url: format!("http://{}", rx.recv().unwrap()),
+            requests: requests,
+        }
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+pub(crate) fn core() -> Core {
+    Core::new().expect("Failed to init Tokio event loop")
+}
+
+pub(crate) fn https_client(core: &Core) -> Client<HttpsConnector<HttpConnector>> {
+    let handle = core.handle();
+    Client::configure()
+        .connector(HttpsConnector::new(4, &handle).expect("Failed to create HTTPS connector"))
+        .build(&handle)
+}
@@ -0,0 +1,322 @@
+mod discord;
+mod email;
+mod errors;
+mod google_chat;
+mod incident;
+mod irc;
+mod json_lines;
+mod matrix;
+#[cfg(test)]
+pub(crate) mod mock_server;
+mod opsgenie;
+mod pagerduty;
+mod posts;
+mod slack;
+mod smtp;
+mod teams;
+mod telegram;
+mod webhook;
+mod zulip;
+
+pub(crate) use self::discord::DiscordNotifier;
+pub(crate) use self::email::EmailNotifier;
+pub(crate) use self::errors::NotifierError;
+pub(crate) use self::google_chat::GoogleChatNotifier;
+pub(crate) use self::irc::{IrcConfig, IrcNotifier};
+pub(crate) use self::json_lines::JsonLinesNotifier;
+pub(crate) use self::matrix::MatrixNotifier;
+pub(crate) use self::opsgenie::{API_URL as OPSGENIE_API_URL, OpsgenieNotifier};
+pub(crate) use self::pagerduty::{EVENTS_URL as PAGERDUTY_EVENTS_URL, PagerDutyNotifier};
+pub(crate) use self::posts::{PostStore, Posts};
+pub(crate) use self::slack::{API_URL as SLACK_API_URL, SlackNotifier};
+pub(crate) use self::smtp::{SmtpConfig, SmtpSecurity};
+pub(crate) use self::teams::TeamsNotifier;
+pub(crate) use self::telegram::{TelegramChat, TelegramNotifier, send_message_url as telegram_send_message_url};
+pub(crate) use self::webhook::WebhookNotifier;
+pub(crate) use self::zulip::ZulipNotifier;
+
+use futures::Stream;
+use hyper::{Client, Method, Request, Response, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::ContentType;
+use hyper_tls::HttpsConnector;
+use serde_json::{self, Map, Value};
+use sha2::{Digest, Sha256};
+use tokio_core::reactor::Core;
+
+use links::{alarm_console_url, encode_component, metric_graph_url};
+use model::{AlarmDetails, AlarmState};
+use oncall::Mention;
+use routing::RoutingDecision;
+
+// The consolidated message of a group of alarms, `changed` being the indices
+// of the alarms which joined or changed state since it was last sent.
+#[derive(Debug)]
+pub(crate) struct GroupMessage<'a> {
+    pub id: &'a str,
+    // What the alarms have in common, "[RTB-US]".
+    pub key: &'a str,
+    pub text: &'a str,
+    pub alarms: &'a [AlarmDetails],
+    pub changed: &'a [usize],
+    // Whether the message was sent already and should be edited.
+    pub update: bool,
+}
+
+pub(crate) trait Notifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError>;
+
+    // Every delivery goes through here: `text` is rendered from the user
+    // template of the destination, if any, and `mentions` are who to ping.
+    // Only notifiers which use the routing decision need to override this.
+    fn notify_routed(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        _decision: &RoutingDecision,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        match (text, mentions.is_empty()) {
+            (None, true) => self.notify(client, core, alarm),
+            (Some(text), true) => self.notify_text(client, core, alarm, text),
+            (text, false) => self.notify_mentioning(client, core, alarm, text, mentions),
+        }
+    }
+
+    // Sends `text`, rendered from a user template, instead of the built-in
+    // message. Notifiers without a plain text message keep the built-in one.
+    fn notify_text(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        _text: &str,
+    ) -> Result<(), NotifierError> {
+        self.notify(client, core, alarm)
+    }
+
+    // Sends the message, rendered from a user template when `text` is given,
+    // pinging `mentions`. Notifiers which can't mention anyone send it as is.
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        _mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        match text {
+            Some(text) => self.notify_text(client, core, alarm, text),
+            None => self.notify(client, core, alarm),
+        }
+    }
+
+    // Only notifiers which can edit their messages send groups as one.
+    fn can_group(&self) -> bool {
+        false
+    }
+
+    fn notify_group(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        group: &GroupMessage,
+    ) -> Result<(), NotifierError> {
+        for &index in group.changed {
+            if let Some(alarm) = group.alarms.get(index) {
+                self.notify(client, core, alarm)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn json_request(method: Method, uri: Uri, payload: &Value) -> Request {
+    let mut req = Request::new(method, uri);
+    req.headers_mut().set(ContentType::json());
+    req.set_body(payload.to_string());
+    req
+}
+
+// Builds an `application/x-www-form-urlencoded` request.
+pub(crate) fn form_request(method: Method, uri: Uri, params: &[(&str, &str)]) -> Request {
+    let body: Vec<String> = params
+        .iter()
+        .map(|&(key, value)| format!("{}={}", encode_component(key), encode_component(value)))
+        .collect();
+
+    let mut req = Request::new(method, uri);
+    req.headers_mut().set(ContentType::form_url_encoded());
+    req.set_body(body.join("&"));
+    req
+}
+
+// Sends the request whatever the response status is, for notifiers which need
+// to inspect non-2xx responses (rate limits, API errors...).
+pub(crate) fn send(
+    client: &Client<HttpsConnector<HttpConnector>>,
+    core: &mut Core,
+    req: Request,
+) -> Result<Response, NotifierError> {
+    core.run(client.request(req)).map_err(NotifierError::HttpError)
+}
+
+pub(crate) fn read_json(core: &mut Core, resp: Response) -> Result<Value, NotifierError> {
+    let body = core.run(resp.body().concat2()).map_err(NotifierError::HttpError)?;
+    serde_json::from_slice(&body).map_err(|err| NotifierError::InvalidResponse(err.to_string()))
+}
+
+pub(crate) fn request_json(
+    client: &Client<HttpsConnector<HttpConnector>>,
+    core: &mut Core,
+    method: Method,
+    uri: Uri,
+    payload: &Value,
+) -> Result<Response, NotifierError> {
+    send(client, core, json_request(method, uri, payload))
+}
+
+pub(crate) fn post_json(
+    client: &Client<HttpsConnector<HttpConnector>>,
+    core: &mut Core,
+    uri: Uri,
+    payload: &Value,
+) -> Result<Response, NotifierError> {
+    let resp = request_json(client, core, Method::Post, uri, payload)?;
+    let status = resp.status();
+    if status.is_success() {
+        Ok(resp)
+    } else {
+        Err(NotifierError::BadStatus(status))
+    }
+}
+
+// Unlike `DefaultHasher`, the same from one build to the next: what's derived
+// from it (Message-IDs, transaction IDs) must not change with an upgrade.
+pub(crate) fn hash(parts: &[&str]) -> String {
+    let hex: Vec<String> = Sha256::digest(parts.join("\n").as_bytes())
+        .iter()
+        .take(16)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    hex.concat()
+}
+
+// Truncates `text` to at most `max_chars` characters (not bytes), ending it
+// with an ellipsis when something had to be cut.
+pub(crate) fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+// The normalized alarm, as exposed to the notifiers which forward it to
+// other systems.
+pub(crate) fn alarm_json(alarm: &AlarmDetails) -> Value {
+    let trigger = &alarm.trigger;
+    let dimensions: Map<String, Value> = trigger
+        .dimensions
+        .iter()
+        .map(|d| (d.name.clone(), Value::String(d.value.clone())))
+        .collect();
+    let envelope = &alarm.envelope;
+    let attributes: Map<String, Value> = envelope
+        .message_attributes
+        .iter()
+        .map(|(name, attribute)| (name.clone(), Value::String(attribute.value.clone())))
+        .collect();
+
+    json!({
+        "name": alarm.name,
+        "description": alarm.description,
+        "arn": alarm.arn,
+        "account_id": alarm.account_id,
+        "region": alarm.region,
+        "region_code": alarm.region_code(),
+        "new_state": alarm.new_state.to_string(),
+        "previous_state": alarm.previous_state.to_string(),
+        "reason": alarm.reason,
+        "timestamp": alarm.timestamp,
+        "console_url": alarm_console_url(alarm),
+        "metric_graph_url": metric_graph_url(alarm),
+        "trigger": {
+            "metric_name": trigger.metric_name,
+            "namespace": trigger.namespace,
+            "statistic": trigger.statistic,
+            "dimensions": dimensions,
+            "comparison_operator": trigger.op.symbol(),
+            "threshold": trigger.threshold,
+            "period": trigger.period,
+            "evaluation_periods": trigger.nb_periods,
+        },
+        "envelope": {
+            "message_id": envelope.message_id,
+            "topic_arn": envelope.topic_arn,
+            "subject": envelope.subject,
+            "timestamp": envelope.timestamp,
+            "message_attributes": attributes,
+        },
+    })
+}
+
+pub(crate) fn state_color(state: &AlarmState) -> &'static str {
+    match *state {
+        AlarmState::Ok => "#1d8102",
+        AlarmState::Alarm => "#d13212",
+        AlarmState::InsufficientData => "#ff9900",
+    }
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("short", 10), "short");
+        assert_eq!(truncate("exactly10!", 10), "exactly10!");
+        assert_eq!(truncate("much too long", 10), "much too …");
+        assert_eq!(truncate("ééééé", 3), "éé…");
+    }
+
+    #[test]
+    fn test_hash() {
+        assert_eq!(hash(&["a", "b"]), "7e18f737311b2dc3b2f269dd78396b03");
+    }
+
+    #[test]
+    fn test_alarm_json() {
+        let alarm = alarm_json(&alarm_details("ALARM", "OK"));
+
+        assert_eq!(alarm["name"], "[RTB-US] UnHealthyHostCount");
+        assert_eq!(alarm["region_code"], "us-east-1");
+        assert_eq!(alarm["new_state"], "ALARM");
+        assert_eq!(alarm["trigger"]["dimensions"]["LoadBalancerName"], "rtb");
+        assert_eq!(alarm["trigger"]["threshold"], 1.0);
+        assert_eq!(alarm["trigger"]["period"], 60);
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("<b>\"Tom\" & 'Jerry'</b>"), "&lt;b&gt;&quot;Tom&quot; &amp; &#39;Jerry&#39;&lt;/b&gt;");
+    }
+}
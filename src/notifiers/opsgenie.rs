@@ -0,0 +1,194 @@
+use hyper::{Client, Method, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::Authorization;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use tokio_core::reactor::Core;
+
+use links::{alarm_console_url, encode_component, metric_graph_url};
+use model::AlarmDetails;
+use super::{Notifier, NotifierError, json_request, send, truncate};
+use super::incident::{IncidentManager, InsufficientDataAction, notify_incident};
+
+pub(crate) const API_URL: &str = "https://api.opsgenie.com";
+
+// https://docs.opsgenie.com/docs/alert-api#create-alert
+const MESSAGE_LIMIT: usize = 130;
+const ALIAS_LIMIT: usize = 512;
+const DESCRIPTION_LIMIT: usize = 15000;
+const TAG_LIMIT: usize = 50;
+
+#[derive(Debug)]
+pub(crate) struct OpsgenieNotifier {
+    // https://api.eu.opsgenie.com for accounts hosted in the EU.
+    pub api_url: Uri,
+    pub api_key: String,
+    pub insufficient_data: InsufficientDataAction,
+}
+
+impl OpsgenieNotifier {
+    fn url(&self, path: &str) -> Result<Uri, NotifierError> {
+        let url = format!("{}{}", self.api_url.as_ref().trim_right_matches('/'), path);
+        url.parse().map_err(|_| NotifierError::InvalidUrl(url))
+    }
+
+    fn create_alert(&self, alias: &str, alarm: &AlarmDetails) -> Value {
+        let trigger = &alarm.trigger;
+        let tags: Vec<String> = trigger
+            .dimensions
+            .iter()
+            .map(|d| truncate(&format!("{}:{}", d.name, d.value), TAG_LIMIT))
+            .collect();
+        let mut details = json!({
+            "State": alarm.new_state.to_string(),
+            "Namespace": trigger.namespace,
+            "Metric": trigger.metric_name,
+            "Threshold": format!("{} {}", trigger.op.symbol(), trigger.threshold),
+            "Account": alarm.account_id,
+            "Region": alarm.region,
+        });
+        if let Some(url) = alarm_console_url(alarm) {
+            details["Console"] = json!(url);
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            details["Metric graph"] = json!(url);
+        }
+
+        json!({
+            "message": truncate(&format!("{}: {}", alarm.new_state, alarm.name), MESSAGE_LIMIT),
+            "alias": alias,
+            "description": truncate(&alarm.reason, DESCRIPTION_LIMIT),
+            "tags": tags,
+            "details": details,
+            "entity": trigger.namespace,
+            "source": "cloudwatch-bot",
+        })
+    }
+
+    fn close_alert(&self, alarm: &AlarmDetails) -> Value {
+        json!({
+            "source": "cloudwatch-bot",
+            "note": truncate(&alarm.reason, DESCRIPTION_LIMIT),
+        })
+    }
+
+    // Opsgenie answers 202 Accepted and processes the request asynchronously.
+    fn post(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        uri: Uri,
+        body: &Value,
+    ) -> Result<(), NotifierError> {
+        let mut req = json_request(Method::Post, uri, body);
+        req.headers_mut().set(Authorization(format!("GenieKey {}", self.api_key)));
+
+        let status = send(client, core, req)?.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(NotifierError::BadStatus(status))
+        }
+    }
+}
+
+impl IncidentManager for OpsgenieNotifier {
+    fn alias_limit(&self) -> usize {
+        ALIAS_LIMIT
+    }
+
+    fn insufficient_data(&self) -> &InsufficientDataAction {
+        &self.insufficient_data
+    }
+
+    fn open(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alias: &str,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.post(client, core, self.url("/v2/alerts")?, &self.create_alert(alias, alarm))
+    }
+
+    fn close(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alias: &str,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        let path = format!("/v2/alerts/{}/close?identifierType=alias", encode_component(alias));
+        self.post(client, core, self.url(&path)?, &self.close_alert(alarm))
+    }
+}
+
+impl Notifier for OpsgenieNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        notify_incident(self, client, core, alarm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+    use serde_json;
+
+    fn notifier(api_url: &str) -> OpsgenieNotifier {
+        OpsgenieNotifier {
+            api_url: api_url.parse().unwrap(),
+            api_key: "eb243592-faa2-4ba2-a551q-1afdf565c889".to_string(),
+            insufficient_data: InsufficientDataAction::Ignore,
+        }
+    }
+
+    #[test]
+    fn test_create_alert() {
+        let alert = notifier(API_URL).create_alert("alias", &alarm_details("ALARM", "OK"));
+
+        assert_eq!(alert["message"], "ALARM: [RTB-US] UnHealthyHostCount");
+        assert_eq!(alert["alias"], "alias");
+        assert_eq!(
+            alert["description"],
+            "Threshold Crossed: 1 datapoint (0.4482758620689655) was not greater than or equal to the threshold (1.0)."
+        );
+        assert_eq!(alert["tags"], json!(["LoadBalancerName:rtb"]));
+        assert_eq!(alert["details"]["Threshold"], ">= 1");
+        assert_eq!(alert["entity"], "AWS/ELB");
+    }
+
+    #[test]
+    fn test_notify_lifecycle_against_mock_server() {
+        let server = MockServer::start(vec![(StatusCode::Accepted, "{\"result\": \"Request will be processed\"}")]);
+        let notifier = notifier(&server.url);
+        let mut core = core();
+        let client = https_client(&core);
+
+        for &(new_state, previous_state) in &[("ALARM", "OK"), ("OK", "ALARM")] {
+            let res = notifier.notify(&client, &mut core, &alarm_details(new_state, previous_state));
+            assert_eq!(res.is_ok(), true);
+        }
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].path, "/v2/alerts");
+        assert_eq!(
+            requests[0].headers.get::<Authorization<String>>(),
+            Some(&Authorization("GenieKey eb243592-faa2-4ba2-a551q-1afdf565c889".to_string()))
+        );
+        let alert: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(alert["alias"], "arn:aws:cloudwatch:us-east-1:097958131044:alarm:[RTB-US] UnHealthyHostCount");
+        assert_eq!(
+            requests[1].path,
+            "/v2/alerts/arn%3Aaws%3Acloudwatch%3Aus-east-1%3A097958131044%3Aalarm%3A%5BRTB-US%5D%20UnHealthyHostCount/close?identifierType=alias"
+        );
+    }
+}
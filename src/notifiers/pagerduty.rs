@@ -0,0 +1,226 @@
+use hyper::{Client, Uri};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio_core::reactor::Core;
+
+use links::{alarm_console_url, metric_graph_url};
+use model::{AlarmDetails, AlarmState, EnumResult, ParseEnumError};
+use super::{Notifier, NotifierError, post_json, truncate};
+use super::incident::{IncidentManager, InsufficientDataAction, notify_incident};
+
+pub(crate) const EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+// https://developer.pagerduty.com/docs/events-api-v2/trigger-events/
+const SUMMARY_LIMIT: usize = 1024;
+const DEDUP_KEY_LIMIT: usize = 255;
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Severity {
+    Critical,
+    Error,
+    Warning,
+    Info,
+}
+
+lazy_static! {
+    static ref SEVERITIES: HashMap<&'static str, Severity> = {
+        let mut map = HashMap::new();
+        map.insert("critical", Severity::Critical);
+        map.insert("error", Severity::Error);
+        map.insert("warning", Severity::Warning);
+        map.insert("info", Severity::Info);
+        map
+    };
+}
+
+impl FromStr for Severity {
+    type Err = ParseEnumError<Self>;
+
+    fn from_str(severity: &str) -> EnumResult<Severity> {
+        SEVERITIES.get(severity).cloned().ok_or(ParseEnumError {
+            value: severity.to_string(),
+            mapping: SEVERITIES.clone(),
+        })
+    }
+}
+
+impl Severity {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Severity::Critical => "critical",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PagerDutyNotifier {
+    pub events_url: Uri,
+    pub routing_key: String,
+    pub alarm_severity: Severity,
+    pub insufficient_data_severity: Severity,
+    pub insufficient_data: InsufficientDataAction,
+}
+
+impl PagerDutyNotifier {
+    fn trigger_event(&self, dedup_key: &str, alarm: &AlarmDetails) -> Value {
+        let trigger = &alarm.trigger;
+        let severity = match alarm.new_state {
+            AlarmState::InsufficientData => &self.insufficient_data_severity,
+            _ => &self.alarm_severity,
+        };
+        let dimensions: HashMap<&str, &str> = trigger
+            .dimensions
+            .iter()
+            .map(|d| (d.name.as_str(), d.value.as_str()))
+            .collect();
+        let links: Vec<Value> = alarm_console_url(alarm)
+            .map(|url| (url, "Open in CloudWatch"))
+            .into_iter()
+            .chain(metric_graph_url(alarm).map(|url| (url, "Metric graph")))
+            .map(|(url, text)| json!({"href": url, "text": text}))
+            .collect();
+
+        json!({
+            "routing_key": self.routing_key,
+            "event_action": "trigger",
+            "dedup_key": dedup_key,
+            "client": "cloudwatch-bot",
+            "links": links,
+            "payload": {
+                "summary": truncate(&format!("{}: {}", alarm.new_state, alarm.name), SUMMARY_LIMIT),
+                "source": format!("{} {}", alarm.account_id, alarm.region),
+                "severity": severity.as_str(),
+                "group": trigger.namespace,
+                "class": trigger.metric_name,
+                "custom_details": {
+                    "description": alarm.description,
+                    "reason": alarm.reason,
+                    "previous_state": alarm.previous_state.to_string(),
+                    "state_change_time": alarm.timestamp,
+                    "threshold": format!("{} {}", trigger.op.symbol(), trigger.threshold),
+                    "dimensions": dimensions,
+                },
+            },
+        })
+    }
+
+    fn resolve_event(&self, dedup_key: &str) -> Value {
+        json!({
+            "routing_key": self.routing_key,
+            "event_action": "resolve",
+            "dedup_key": dedup_key,
+        })
+    }
+}
+
+impl IncidentManager for PagerDutyNotifier {
+    fn alias_limit(&self) -> usize {
+        DEDUP_KEY_LIMIT
+    }
+
+    fn insufficient_data(&self) -> &InsufficientDataAction {
+        &self.insufficient_data
+    }
+
+    fn open(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alias: &str,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        post_json(client, core, self.events_url.clone(), &self.trigger_event(alias, alarm)).map(|_| ())
+    }
+
+    fn close(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alias: &str,
+        _alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        post_json(client, core, self.events_url.clone(), &self.resolve_event(alias)).map(|_| ())
+    }
+}
+
+impl Notifier for PagerDutyNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        notify_incident(self, client, core, alarm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+    use serde_json;
+
+    fn notifier(events_url: &str, insufficient_data: InsufficientDataAction) -> PagerDutyNotifier {
+        PagerDutyNotifier {
+            events_url: events_url.parse().unwrap(),
+            routing_key: "R0UT1NGK3Y".to_string(),
+            alarm_severity: Severity::Critical,
+            insufficient_data_severity: Severity::Warning,
+            insufficient_data: insufficient_data,
+        }
+    }
+
+    #[test]
+    fn test_severity_from_string() {
+        let critical: Severity = "critical".parse().expect("Should not happen");
+        let no_match: EnumResult<Severity> = "fatal".parse();
+
+        assert_eq!(critical, Severity::Critical);
+        assert_eq!(no_match.is_ok(), false);
+    }
+
+    #[test]
+    fn test_trigger_event() {
+        let notifier = notifier(EVENTS_URL, InsufficientDataAction::Open);
+        let event = notifier.trigger_event("alias", &alarm_details("INSUFFICIENT_DATA", "OK"));
+
+        assert_eq!(event["event_action"], "trigger");
+        assert_eq!(event["dedup_key"], "alias");
+        assert_eq!(event["payload"]["summary"], "INSUFFICIENT_DATA: [RTB-US] UnHealthyHostCount");
+        assert_eq!(event["payload"]["severity"], "warning");
+        assert_eq!(event["payload"]["custom_details"]["dimensions"]["LoadBalancerName"], "rtb");
+        assert_eq!(event["links"][0]["text"], "Open in CloudWatch");
+    }
+
+    #[test]
+    fn test_notify_lifecycle_against_mock_server() {
+        let server = MockServer::start(vec![(StatusCode::Accepted, "{\"status\": \"success\"}")]);
+        let notifier = notifier(&format!("{}/v2/enqueue", server.url), InsufficientDataAction::Ignore);
+        let mut core = core();
+        let client = https_client(&core);
+
+        for &(new_state, previous_state) in &[("ALARM", "OK"), ("INSUFFICIENT_DATA", "ALARM"), ("OK", "ALARM")] {
+            let res = notifier.notify(&client, &mut core, &alarm_details(new_state, previous_state));
+            assert_eq!(res.is_ok(), true);
+        }
+
+        let events: Vec<Value> = server
+            .requests()
+            .iter()
+            .map(|req| serde_json::from_str(&req.body).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event_action"], "trigger");
+        assert_eq!(events[1]["event_action"], "resolve");
+        assert_eq!(events[0]["dedup_key"], "arn:aws:cloudwatch:us-east-1:097958131044:alarm:[RTB-US] UnHealthyHostCount");
+        assert_eq!(events[0]["dedup_key"], events[1]["dedup_key"]);
+    }
+}
@@ -0,0 +1,308 @@
+mod store;
+
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use config::PostsConfig;
+use l10n::Localizer;
+use model::{AlarmDetails, AlarmState};
+use templates::format_duration;
+use self::store::{Entry, Store};
+
+// A message posted for an alarm which hasn't recovered yet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Post {
+    pub id: String,
+    // State change time of the transition which was posted, empty for the
+    // message of a group.
+    pub timestamp: String,
+}
+
+#[derive(Debug, Default)]
+struct Posted {
+    store: Option<Store>,
+    posts: HashMap<String, Post>,
+}
+
+// The messages posted by every notifier, shared by the configurations so that
+// a reload doesn't forget them. On disk once opened with a directory, a
+// failure to write them is reported but doesn't fail the delivery: the
+// recovery is then posted anew after a restart.
+#[derive(Debug, Default)]
+pub(crate) struct PostStore {
+    state: Mutex<Posted>,
+}
+
+impl PostStore {
+    // The configuration is parsed with the store, which is only opened once
+    // it is known where to keep the messages.
+    pub fn open(&self, config: &PostsConfig) -> io::Result<()> {
+        let directory = match config.directory {
+            Some(ref directory) => directory,
+            None => return Ok(()),
+        };
+        let store = Store::open(directory)?;
+        let mut state = self.lock();
+        for entry in store.load()? {
+            state.posts.insert(entry.key, entry.post);
+        }
+        state.store = Some(store);
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<Posted> {
+        self.state.lock().expect("Posts lock poisoned")
+    }
+
+    fn get(&self, key: &str) -> Option<Post> {
+        self.lock().posts.get(key).cloned()
+    }
+
+    fn insert(&self, key: String, post: Post) {
+        let mut state = self.lock();
+        if let Some(ref store) = state.store {
+            let entry = Entry {
+                key: key.clone(),
+                post: post.clone(),
+            };
+            if let Err(err) = store.save(&entry) {
+                let _ = writeln!(io::stderr(), "Failed to save the posted message of {}: {}", key, err);
+            }
+        }
+        state.posts.insert(key, post);
+    }
+
+    fn remove(&self, key: &str) {
+        let mut state = self.lock();
+        if state.posts.remove(key).is_none() {
+            return;
+        }
+        if let Some(ref store) = state.store {
+            if let Err(err) = store.remove(key) {
+                let _ = writeln!(io::stderr(), "Failed to forget the posted message of {}: {}", key, err);
+            }
+        }
+    }
+}
+
+// The messages posted by a notifier for the alarms currently firing, so that
+// their recovery edits the message instead of posting a new one. Keyed by the
+// destination, which notifiers don't share.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Posts {
+    destination: String,
+    store: Arc<PostStore>,
+}
+
+fn key(alarm: &AlarmDetails) -> String {
+    alarm.arn.as_ref().unwrap_or(&alarm.name).clone()
+}
+
+impl Posts {
+    pub fn new(destination: &str, store: Arc<PostStore>) -> Posts {
+        Posts {
+            destination: destination.to_string(),
+            store: store,
+        }
+    }
+
+    pub fn destination(&self) -> &str {
+        &self.destination
+    }
+
+    fn alarm_key(&self, alarm: &AlarmDetails) -> String {
+        format!("{}|alarm|{}", self.destination, key(alarm))
+    }
+
+    fn group_key(&self, group_id: &str) -> String {
+        format!("{}|group|{}", self.destination, group_id)
+    }
+
+    // A later transition of a firing alarm (ALARM -> INSUFFICIENT_DATA) is
+    // posted on its own, the recovery then edits the latest message. A
+    // recovery posted anew, its message having been deleted, forgets it.
+    pub fn remember(&self, alarm: &AlarmDetails, id: String) {
+        if alarm.new_state == AlarmState::Ok {
+            return self.forget(alarm);
+        }
+        let post = Post {
+            id: id,
+            timestamp: alarm.timestamp.clone(),
+        };
+        self.store.insert(self.alarm_key(alarm), post);
+    }
+
+    // The message a recovery should edit, if any. It is only forgotten once
+    // edited, a failed edit is retried with it.
+    pub fn get(&self, alarm: &AlarmDetails) -> Option<Post> {
+        if alarm.new_state != AlarmState::Ok {
+            return None;
+        }
+        self.store.get(&self.alarm_key(alarm))
+    }
+
+    pub fn forget(&self, alarm: &AlarmDetails) {
+        self.store.remove(&self.alarm_key(alarm));
+    }
+
+    // A group which starts again posts a new message, which replaces the
+    // one remembered.
+    pub fn remember_group(&self, group_id: &str, id: String) {
+        let post = Post {
+            id: id,
+            timestamp: String::new(),
+        };
+        self.store.insert(self.group_key(group_id), post);
+    }
+
+    pub fn group(&self, group_id: &str) -> Option<String> {
+        self.store.get(&self.group_key(group_id)).map(|post| post.id)
+    }
+
+    // The latest message sent for an alarm whatever its state, for the next
+    // one to follow up on. Never forgotten.
+    pub fn remember_latest(&self, alarm: &AlarmDetails, id: String) {
+        let post = Post {
+            id: id,
+            timestamp: alarm.timestamp.clone(),
+        };
+        self.store.insert(format!("{}|latest|{}", self.destination, key(alarm)), post);
+    }
+
+    pub fn latest(&self, alarm: &AlarmDetails) -> Option<String> {
+        self.store.get(&format!("{}|latest|{}", self.destination, key(alarm))).map(|post| post.id)
+    }
+
+    fn delivered_key(&self, alarm: &AlarmDetails, target: &str) -> String {
+        let transition = format!("{}|{}|{}", key(alarm), alarm.new_state, alarm.timestamp);
+        format!("{}|delivered|{}|{}", self.destination, target, transition)
+    }
+
+    // The targets of a notifier (Telegram chats) which got a transition, so
+    // that a retry only goes to the others. Forgotten once they all got it.
+    pub fn remember_delivered(&self, alarm: &AlarmDetails, target: &str) {
+        let post = Post {
+            id: String::new(),
+            timestamp: alarm.timestamp.clone(),
+        };
+        self.store.insert(self.delivered_key(alarm, target), post);
+    }
+
+    pub fn delivered(&self, alarm: &AlarmDetails, target: &str) -> bool {
+        self.store.get(&self.delivered_key(alarm, target)).is_some()
+    }
+
+    pub fn forget_delivered(&self, alarm: &AlarmDetails, target: &str) {
+        self.store.remove(&self.delivered_key(alarm, target));
+    }
+}
+
+// "Resolved after 12m", `None` when a timestamp can't be parsed.
+pub(crate) fn resolved_after(l10n: &Localizer, post: &Post, alarm: &AlarmDetails) -> Option<String> {
+    let format = "%Y-%m-%dT%H:%M:%S%.f%z";
+    let start = DateTime::parse_from_str(&post.timestamp, format).ok();
+    let end = DateTime::parse_from_str(&alarm.timestamp, format).ok();
+    match (start, end) {
+        (Some(start), Some(end)) => {
+            let seconds = end.signed_duration_since(start).num_seconds().max(0) as u64;
+            Some(l10n.message("resolved-after", &[("duration", &format_duration(seconds))]))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn test_remember_until_recovery() {
+        let posts = Posts::default();
+        let alarm = alarm_details("ALARM", "OK");
+        let recovery = alarm_details("OK", "ALARM");
+
+        posts.remember(&alarm, "1".to_string());
+        assert_eq!(posts.get(&alarm), None);
+        assert_eq!(posts.get(&recovery).map(|post| post.id), Some("1".to_string()));
+        assert_eq!(posts.get(&recovery).map(|post| post.id), Some("1".to_string()));
+        posts.forget(&recovery);
+        assert_eq!(posts.get(&recovery), None);
+
+        posts.remember(&alarm, "1".to_string());
+        posts.remember(&recovery, "2".to_string());
+        assert_eq!(posts.get(&recovery), None);
+    }
+
+    #[test]
+    fn test_destinations_are_kept_apart() {
+        let store = Arc::new(PostStore::default());
+        let ops = Posts::new("ops", store.clone());
+        let dev = Posts::new("dev", store.clone());
+        let recovery = alarm_details("OK", "ALARM");
+
+        ops.remember(&alarm_details("ALARM", "OK"), "1".to_string());
+        ops.remember_group("g", "2".to_string());
+        assert_eq!(dev.get(&recovery), None);
+        assert_eq!(dev.group("g"), None);
+        assert_eq!(Posts::new("ops", store).group("g"), Some("2".to_string()));
+    }
+
+    #[test]
+    fn test_posts_survive_a_restart() {
+        let directory = env::temp_dir().join("cloudwatch_bot_test_posts_restart");
+        let _ = fs::remove_dir_all(&directory);
+        let config = PostsConfig { directory: Some(directory.clone()) };
+        let recovery = alarm_details("OK", "ALARM");
+
+        let store = Arc::new(PostStore::default());
+        store.open(&config).unwrap();
+        let posts = Posts::new("ops", store);
+        posts.remember(&alarm_details("ALARM", "OK"), "1".to_string());
+        posts.remember_group("g", "2".to_string());
+
+        let store = Arc::new(PostStore::default());
+        store.open(&config).unwrap();
+        let posts = Posts::new("ops", store);
+        assert_eq!(posts.get(&recovery).map(|post| post.id), Some("1".to_string()));
+        assert_eq!(posts.group("g"), Some("2".to_string()));
+        posts.forget(&recovery);
+
+        let store = Arc::new(PostStore::default());
+        store.open(&config).unwrap();
+        assert_eq!(Posts::new("ops", store).get(&recovery), None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_delivered_per_transition() {
+        let posts = Posts::default();
+        let alarm = alarm_details("ALARM", "OK");
+
+        posts.remember_delivered(&alarm, "ops");
+        assert_eq!(posts.delivered(&alarm, "ops"), true);
+        assert_eq!(posts.delivered(&alarm, "dev"), false);
+        assert_eq!(posts.delivered(&alarm_details("OK", "ALARM"), "ops"), false);
+        posts.forget_delivered(&alarm, "ops");
+        assert_eq!(posts.delivered(&alarm, "ops"), false);
+    }
+
+    #[test]
+    fn test_resolved_after() {
+        let l10n = Localizer::default();
+        let post = Post {
+            id: "1".to_string(),
+            timestamp: "2016-02-27T11:09:10.534+0000".to_string(),
+        };
+        let mut recovery = alarm_details("OK", "ALARM");
+        recovery.timestamp = "2016-02-27T11:21:10.534+0000".to_string();
+
+        assert_eq!(resolved_after(&l10n, &post, &recovery), Some("Resolved after 12m".to_string()));
+        recovery.timestamp = "yesterday".to_string();
+        assert_eq!(resolved_after(&l10n, &post, &recovery), None);
+    }
+}
@@ -0,0 +1,72 @@
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::Post;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub key: String,
+    pub post: Post,
+}
+
+// One JSON file per message, named after a hash of its key. Written next to
+// their final name then renamed, like the queued deliveries.
+#[derive(Debug)]
+pub(crate) struct Store {
+    directory: PathBuf,
+}
+
+impl Store {
+    pub fn open(directory: &Path) -> io::Result<Store> {
+        fs::create_dir_all(directory)?;
+        Ok(Store { directory: directory.to_path_buf() })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let hex: Vec<String> = Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        self.directory.join(format!("{}.json", hex.concat()))
+    }
+
+    pub fn save(&self, entry: &Entry) -> io::Result<()> {
+        let path = self.path(&entry.key);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec(entry).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&json)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)
+    }
+
+    pub fn remove(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(key)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    // Unreadable files are reported and left alone.
+    pub fn load(&self) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.directory)? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |extension| extension != "json") {
+                continue;
+            }
+            let entry = File::open(&path).and_then(|file| {
+                serde_json::from_reader(file).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+            });
+            match entry {
+                Ok(entry) => entries.push(entry),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "{}: skipping posted message: {}", path.display(), err);
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,391 @@
+use hyper::{Client, Method, Request, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::{Authorization, Bearer, ContentType};
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use std::io::{self, Write};
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, metric_graph_url};
+use model::AlarmDetails;
+use oncall::Mention;
+use sparkline::{self, FILENAME as SPARKLINE_FILENAME};
+use super::{GroupMessage, Notifier, NotifierError, form_request, json_request, read_json, send, state_color};
+use super::posts::{Post, Posts, resolved_after};
+
+pub(crate) const API_URL: &str = "https://slack.com/api";
+
+#[derive(Debug)]
+pub(crate) struct SlackNotifier {
+    pub api_url: Uri,
+    // Bot token, with the `chat:write` and `files:write` scopes.
+    pub token: String,
+    // The ID of the channel, `chat.update` doesn't take names.
+    pub channel: String,
+    pub l10n: Localizer,
+    pub posts: Posts,
+}
+
+// Only these are taken as markup in Slack's text.
+// https://api.slack.com/reference/surfaces/formatting#escaping
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+impl SlackNotifier {
+    fn method_url(&self, method: &str) -> Result<Uri, NotifierError> {
+        let url = format!("{}/{}", self.api_url.as_ref().trim_right_matches('/'), method);
+        url.parse().map_err(|_| NotifierError::InvalidUrl(url))
+    }
+
+    // Slack answers API errors with a 200 and `"ok": false`.
+    fn call(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        mut req: Request,
+    ) -> Result<Value, NotifierError> {
+        req.headers_mut().set(Authorization(Bearer { token: self.token.clone() }));
+        let resp = send(client, core, req)?;
+        let status = resp.status();
+        if !status.is_success() {
+            return Err(NotifierError::BadStatus(status));
+        }
+        let body = read_json(core, resp)?;
+        if body["ok"] == json!(true) {
+            Ok(body)
+        } else {
+            Err(NotifierError::ApiError(body["error"].as_str().unwrap_or("unknown error").to_string()))
+        }
+    }
+
+    fn post_message(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        payload: &Value,
+    ) -> Result<Option<String>, NotifierError> {
+        let req = json_request(Method::Post, self.method_url("chat.postMessage")?, payload);
+        let message = self.call(client, core, req)?;
+        Ok(message["ts"].as_str().map(str::to_string))
+    }
+
+    // `false` when the message is gone, it should then be posted anew.
+    fn update_message(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        ts: &str,
+        payload: &Value,
+    ) -> Result<bool, NotifierError> {
+        let mut payload = payload.clone();
+        payload["ts"] = json!(ts);
+        let req = json_request(Method::Post, self.method_url("chat.update")?, &payload);
+        match self.call(client, core, req) {
+            Err(NotifierError::ApiError(ref error)) if error == "message_not_found" => Ok(false),
+            result => result.map(|_| true),
+        }
+    }
+
+    // In the thread of the alarm's message, attachments can't carry files.
+    // https://api.slack.com/messaging/files#uploading_files: ask for an upload
+    // URL, send the image there, then share it in the channel.
+    fn upload(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        ts: &str,
+        image: &[u8],
+    ) -> Result<(), NotifierError> {
+        let length = image.len().to_string();
+        let req = form_request(
+            Method::Post,
+            self.method_url("files.getUploadURLExternal")?,
+            &[("filename", SPARKLINE_FILENAME), ("length", &length)],
+        );
+        let target = self.call(client, core, req)?;
+        let (upload_url, file_id) = match (target["upload_url"].as_str(), target["file_id"].as_str()) {
+            (Some(upload_url), Some(file_id)) => (upload_url.to_string(), file_id),
+            _ => return Err(NotifierError::InvalidResponse(target.to_string())),
+        };
+
+        let uri = upload_url.parse().map_err(|_| NotifierError::InvalidUrl(upload_url.clone()))?;
+        let mut req = Request::new(Method::Post, uri);
+        req.headers_mut().set(ContentType::png());
+        req.set_body(image.to_vec());
+        let status = send(client, core, req)?.status();
+        if !status.is_success() {
+            return Err(NotifierError::BadStatus(status));
+        }
+
+        let title = self.l10n.message("datapoints", &[("metric", &alarm.trigger.metric_name)]);
+        let files = json!([{"id": file_id, "title": title}]).to_string();
+        let req = form_request(
+            Method::Post,
+            self.method_url("files.completeUploadExternal")?,
+            &[("files", &files), ("channel_id", &self.channel), ("thread_ts", ts)],
+        );
+        self.call(client, core, req).map(|_| ())
+    }
+
+    // `post` is the message of the alarm being edited on recovery.
+    fn attachment(&self, alarm: &AlarmDetails, post: Option<&Post>) -> Value {
+        let l10n = &self.l10n;
+        let trigger = &alarm.trigger;
+        let title = format!("{}: {}", l10n.state(&alarm.new_state), alarm.name);
+        let fields = vec![
+            (l10n.text("label-metric"), format!("{} ({})", trigger.metric_name, trigger.statistic)),
+            (l10n.text("label-namespace"), trigger.namespace.clone()),
+            (l10n.text("label-threshold"), l10n.threshold(alarm)),
+            (l10n.text("label-period"), l10n.period(alarm)),
+        ];
+        let fields: Vec<Value> = fields
+            .iter()
+            .map(|&(ref title, ref value)| json!({"title": title, "value": escape(value), "short": true}))
+            .collect();
+
+        let mut text = escape(&alarm.reason);
+        if let Some(url) = metric_graph_url(alarm) {
+            text.push_str(&format!("\n<{}|{}>", url, escape(&l10n.text("metric-graph"))));
+        }
+        let footer = match post.and_then(|post| resolved_after(l10n, post, alarm)) {
+            Some(resolved) => format!("{} · {}", resolved, l10n.transition(alarm)),
+            None => l10n.transition(alarm),
+        };
+
+        let mut attachment = json!({
+            "fallback": title,
+            "color": state_color(&alarm.new_state),
+            "title": title,
+            "text": text,
+            "fields": fields,
+            "footer": footer,
+        });
+        if let Some(url) = alarm_console_url(alarm) {
+            attachment["title_link"] = json!(url);
+        }
+        attachment
+    }
+}
+
+// Users and user groups by ID, which is what Slack resolves in messages.
+fn mentions_line(mentions: &[Mention]) -> String {
+    let mentions: Vec<String> = mentions
+        .iter()
+        .map(|mention| match *mention {
+            Mention::User(ref id) => format!("<@{}>", id),
+            Mention::Group(ref id) => format!("<!subteam^{}>", id),
+            Mention::Here => "<!here>".to_string(),
+        })
+        .collect();
+    mentions.join(" ")
+}
+
+impl Notifier for SlackNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, None, &[])
+    }
+
+    fn notify_text(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: &str,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, Some(text), &[])
+    }
+
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let post = self.posts.get(alarm);
+        let mut payload = match text {
+            Some(text) => json!({"channel": self.channel, "text": text}),
+            None => json!({"channel": self.channel, "attachments": [self.attachment(alarm, post.as_ref())]}),
+        };
+        if !mentions.is_empty() {
+            let text = match payload["text"].as_str() {
+                Some(text) => format!("{} {}", mentions_line(mentions), text),
+                None => mentions_line(mentions),
+            };
+            payload["text"] = json!(text);
+        }
+
+        // The recovery replaces the message of the alarm, unless it has been
+        // deleted in the meantime.
+        if let Some(post) = post {
+            if self.update_message(client, core, &post.id, &payload)? {
+                self.posts.forget(alarm);
+                return Ok(());
+            }
+        }
+
+        let ts = match self.post_message(client, core, &payload)? {
+            Some(ts) => ts,
+            None => return Ok(()),
+        };
+        self.posts.remember(alarm, ts.clone());
+
+        // The message is out, a failed upload isn't worth posting it again.
+        if text.is_none() {
+            if let Some(image) = sparkline::render_png(alarm) {
+                if let Err(err) = self.upload(client, core, alarm, &ts, &image) {
+                    let _ = writeln!(io::stderr(), "Failed to upload the sparkline of {}: {:?}", alarm.name, err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn can_group(&self) -> bool {
+        true
+    }
+
+    fn notify_group(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        group: &GroupMessage,
+    ) -> Result<(), NotifierError> {
+        let payload = json!({"channel": self.channel, "text": escape(group.text)});
+        let id = if group.update { self.posts.group(group.id) } else { None };
+        if let Some(id) = id {
+            if self.update_message(client, core, &id, &payload)? {
+                return Ok(());
+            }
+        }
+
+        if let Some(ts) = self.post_message(client, core, &payload)? {
+            self.posts.remember_group(group.id, ts);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+    use serde_json;
+
+    fn notifier(api_url: &str) -> SlackNotifier {
+        SlackNotifier {
+            api_url: api_url.parse().unwrap(),
+            token: "xoxb-secret".to_string(),
+            channel: "C0123456789".to_string(),
+            l10n: Localizer::default(),
+            posts: Posts::default(),
+        }
+    }
+
+    #[test]
+    fn test_attachment() {
+        let attachment = notifier(API_URL).attachment(&alarm_details("ALARM", "OK"), None);
+
+        assert_eq!(attachment["title"], "ALARM: [RTB-US] UnHealthyHostCount");
+        assert_eq!(attachment["color"], "#d13212");
+        assert_eq!(attachment["fields"][0]["value"], "UnHealthyHostCount (AVERAGE)");
+        assert_eq!(attachment["footer"], "OK -> ALARM at 2016-02-27 11:21:10 UTC");
+    }
+
+    #[test]
+    fn test_mentions_line() {
+        assert_eq!(
+            mentions_line(&[Mention::User("U1".to_string()), Mention::Group("S1".to_string()), Mention::Here]),
+            "<@U1> <!subteam^S1> <!here>"
+        );
+    }
+
+    #[test]
+    fn test_notify_uploads_sparkline() {
+        let files = MockServer::start(vec![(StatusCode::Ok, "OK - 1234")]);
+        let upload_url = format!("{}/upload/v1/abc", files.url);
+        let target = json!({"ok": true, "upload_url": upload_url, "file_id": "F1"}).to_string();
+        let server = MockServer::start(vec![
+            (StatusCode::Ok, "{\"ok\": true, \"ts\": \"1700000000.000100\"}"),
+            (StatusCode::Ok, &target),
+            (StatusCode::Ok, "{\"ok\": true, \"files\": [{\"id\": \"F1\"}]}"),
+        ]);
+        let mut core = core();
+        let client = https_client(&core);
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.reason = "Threshold Crossed: 2 datapoints [3.0 (27/02/16 11:20:00), 2.0 (27/02/16 11:19:00)] \
+                        were greater than or equal to the threshold (1.0)."
+            .to_string();
+
+        notifier(&server.url)
+            .notify_mentioning(&client, &mut core, &alarm, None, &[Mention::User("U1".to_string())])
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].path, "/chat.postMessage");
+        assert_eq!(
+            requests[0].headers.get::<Authorization<Bearer>>().map(|auth| auth.token.as_str()),
+            Some("xoxb-secret")
+        );
+        let payload: Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(payload["channel"], "C0123456789");
+        assert_eq!(payload["text"], "<@U1>");
+        assert_eq!(requests[1].path, "/files.getUploadURLExternal");
+        assert_eq!(requests[1].body.starts_with("filename=sparkline.png&length="), true);
+        let uploads = files.requests();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].path, "/upload/v1/abc");
+        assert_eq!(uploads[0].headers.get::<ContentType>(), Some(&ContentType::png()));
+        assert_eq!(requests[2].path, "/files.completeUploadExternal");
+        assert_eq!(requests[2].body.contains("&channel_id=C0123456789&thread_ts=1700000000.000100"), true);
+    }
+
+    #[test]
+    fn test_recovery_updates_the_alarm_message() {
+        let server = MockServer::start(vec![
+            (StatusCode::Ok, "{\"ok\": true, \"ts\": \"1.0\"}"),
+            (StatusCode::Ok, "{\"ok\": false, \"error\": \"message_not_found\"}"),
+            (StatusCode::Ok, "{\"ok\": true, \"ts\": \"2.0\"}"),
+        ]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = notifier(&server.url);
+
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        notifier.notify(&client, &mut core, &alarm_details("OK", "ALARM")).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].path, "/chat.update");
+        let payload: Value = serde_json::from_str(&requests[1].body).unwrap();
+        assert_eq!(payload["ts"], "1.0");
+        assert_eq!(payload["attachments"][0]["footer"].as_str().map(|f| f.starts_with("Resolved after 0s")), Some(true));
+        // Deleted in the meantime, the recovery is posted anew.
+        assert_eq!(requests[2].path, "/chat.postMessage");
+        assert_eq!(notifier.posts.get(&alarm_details("OK", "ALARM")), None);
+    }
+
+    #[test]
+    fn test_api_errors() {
+        let server = MockServer::start(vec![(StatusCode::Ok, "{\"ok\": false, \"error\": \"channel_not_found\"}")]);
+        let mut core = core();
+        let client = https_client(&core);
+
+        match notifier(&server.url).notify(&client, &mut core, &alarm_details("ALARM", "OK")) {
+            Err(NotifierError::ApiError(error)) => assert_eq!(error, "channel_not_found"),
+            result => panic!("unexpected result {:?}", result),
+        }
+    }
+}
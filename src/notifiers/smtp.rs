@@ -0,0 +1,244 @@
+use base64;
+use native_tls::{TlsConnector, TlsStream};
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::str::FromStr;
+use std::time::Duration;
+
+use model::{EnumResult, ParseEnumError};
+use super::NotifierError;
+
+const TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SmtpSecurity {
+    None,
+    // Plain connection upgraded with STARTTLS, usually on port 587.
+    StartTls,
+    // TLS from the start (SMTPS), usually on port 465.
+    Tls,
+}
+
+lazy_static! {
+    static ref SMTP_SECURITIES: HashMap<&'static str, SmtpSecurity> = {
+        let mut map = HashMap::new();
+        map.insert("none", SmtpSecurity::None);
+        map.insert("starttls", SmtpSecurity::StartTls);
+        map.insert("tls", SmtpSecurity::Tls);
+        map
+    };
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = ParseEnumError<Self>;
+
+    fn from_str(security: &str) -> EnumResult<SmtpSecurity> {
+        SMTP_SECURITIES.get(security).cloned().ok_or(ParseEnumError {
+            value: security.to_string(),
+            mapping: SMTP_SECURITIES.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+impl SmtpConfig {
+    pub fn from_domain(&self) -> &str {
+        self.from.rsplit('@').next().unwrap_or("localhost")
+    }
+}
+
+fn read_line<S: Read>(stream: &mut S) -> io::Result<String> {
+    // Byte by byte, so that nothing past the reply is consumed before a
+    // STARTTLS upgrade.
+    let mut line = Vec::new();
+    let mut byte = [0; 1];
+    while line.last() != Some(&b'\n') {
+        if stream.read(&mut byte)? == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed by SMTP server"));
+        }
+        line.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&line).trim_right().to_string())
+}
+
+// Reads a possibly multi-line reply ("250-first", "250 last").
+fn read_reply<S: Read>(stream: &mut S) -> io::Result<(u16, String)> {
+    let mut text = String::new();
+    loop {
+        let line = read_line(stream)?;
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok()).ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidData, format!("invalid SMTP reply: {}", line))
+        })?;
+        text.push_str(line.get(4..).unwrap_or(""));
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, text));
+        }
+        text.push('\n');
+    }
+}
+
+fn expect<S: Read>(stream: &mut S, expected: u16) -> Result<String, NotifierError> {
+    let (code, text) = read_reply(stream)?;
+    if code == expected {
+        Ok(text)
+    } else {
+        Err(NotifierError::BadReply(code, text))
+    }
+}
+
+fn command<S: Read + Write>(stream: &mut S, line: &str, expected: u16) -> Result<String, NotifierError> {
+    stream.write_all(format!("{}\r\n", line).as_bytes())?;
+    stream.flush()?;
+    expect(stream, expected)
+}
+
+// Lines starting with a dot get another one, so that they are not mistaken
+// for the end of the DATA section.
+fn dot_stuff(message: &str) -> String {
+    let lines: Vec<String> = message
+        .split("\r\n")
+        .map(|line| if line.starts_with('.') {
+            format!(".{}", line)
+        } else {
+            line.to_string()
+        })
+        .collect();
+    lines.join("\r\n")
+}
+
+fn tls_connect(host: &str, tcp: TcpStream) -> Result<TlsStream<TcpStream>, NotifierError> {
+    let connector = TlsConnector::builder()
+        .and_then(|builder| builder.build())
+        .map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))?;
+    let tls = connector
+        .connect(host, tcp)
+        .map_err(|e| io::Error::new(ErrorKind::Other, e.to_string()))?;
+    Ok(tls)
+}
+
+fn transaction<S: Read + Write>(
+    config: &SmtpConfig,
+    stream: &mut S,
+    recipients: &[String],
+    message: &str,
+) -> Result<(), NotifierError> {
+    command(stream, &format!("EHLO {}", config.from_domain()), 250)?;
+    if let (&Some(ref username), &Some(ref password)) = (&config.username, &config.password) {
+        let credentials = base64::encode(&format!("\0{}\0{}", username, password));
+        command(stream, &format!("AUTH PLAIN {}", credentials), 235)?;
+    }
+
+    command(stream, &format!("MAIL FROM:<{}>", config.from), 250)?;
+    for recipient in recipients {
+        command(stream, &format!("RCPT TO:<{}>", recipient), 250)?;
+    }
+    command(stream, "DATA", 354)?;
+    command(stream, &format!("{}\r\n.", dot_stuff(message)), 250)?;
+    // The server accepted the message, a failing QUIT must not make the queue
+    // send it again.
+    let _ = command(stream, "QUIT", 221);
+    Ok(())
+}
+
+pub(crate) fn send_mail(config: &SmtpConfig, recipients: &[String], message: &str) -> Result<(), NotifierError> {
+    let mut tcp = TcpStream::connect((config.host.as_str(), config.port))?;
+    tcp.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+    tcp.set_write_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+
+    match config.security {
+        SmtpSecurity::None => {
+            expect(&mut tcp, 220)?;
+            transaction(config, &mut tcp, recipients, message)
+        }
+        SmtpSecurity::Tls => {
+            let mut tls = tls_connect(&config.host, tcp)?;
+            expect(&mut tls, 220)?;
+            transaction(config, &mut tls, recipients, message)
+        }
+        SmtpSecurity::StartTls => {
+            expect(&mut tcp, 220)?;
+            command(&mut tcp, &format!("EHLO {}", config.from_domain()), 250)?;
+            command(&mut tcp, "STARTTLS", 220)?;
+            let mut tls = tls_connect(&config.host, tcp)?;
+            transaction(config, &mut tls, recipients, message)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_reply_multi_line() {
+        let mut reply: &[u8] = b"250-smtp.example.org\r\n250-STARTTLS\r\n250 AUTH PLAIN LOGIN\r\n";
+        assert_eq!(
+            read_reply(&mut reply).unwrap(),
+            (250, "smtp.example.org\nSTARTTLS\nAUTH PLAIN LOGIN".to_string())
+        );
+    }
+
+    #[test]
+    fn test_read_reply_invalid() {
+        let mut reply: &[u8] = b"hello\r\n";
+        assert_eq!(read_reply(&mut reply).is_ok(), false);
+    }
+
+    // Replays the server's replies and records the client's commands.
+    struct Conversation {
+        replies: io::Cursor<Vec<u8>>,
+        commands: Vec<u8>,
+    }
+
+    impl Read for Conversation {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Conversation {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.commands.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_transaction_ignores_quit() {
+        let config = SmtpConfig {
+            host: "smtp.example.org".to_string(),
+            port: 25,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "bot@example.org".to_string(),
+        };
+        // The connection is closed instead of answering QUIT.
+        let mut conversation = Conversation {
+            replies: io::Cursor::new(b"250 hello\r\n250 ok\r\n250 ok\r\n354 go ahead\r\n250 queued\r\n".to_vec()),
+            commands: Vec::new(),
+        };
+        transaction(&config, &mut conversation, &["ops@example.org".to_string()], "Subject: x\r\n\r\nbody").unwrap();
+        let commands = String::from_utf8(conversation.commands).unwrap();
+        assert_eq!(commands.starts_with("EHLO example.org\r\nMAIL FROM:<bot@example.org>\r\n"), true);
+        assert_eq!(commands.ends_with("body\r\n.\r\nQUIT\r\n"), true);
+    }
+
+    #[test]
+    fn test_dot_stuff() {
+        assert_eq!(dot_stuff("a\r\n.b\r\n..c"), "a\r\n..b\r\n...c");
+    }
+}
@@ -0,0 +1,171 @@
+use hyper::{Client, Uri};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, metric_graph_url};
+use model::{AlarmDetails, AlarmState};
+use super::{Notifier, NotifierError, post_json};
+
+// Works with both the legacy "Incoming Webhook" connector and the
+// "Post to a channel when a webhook request is received" workflow, as both
+// accept a message with an Adaptive Card attachment.
+#[derive(Debug)]
+pub(crate) struct TeamsNotifier {
+    pub webhook_url: Uri,
+    pub runbook_url: Option<String>,
+    pub l10n: Localizer,
+}
+
+impl TeamsNotifier {
+    fn card(&self, alarm: &AlarmDetails) -> Value {
+        let l10n = &self.l10n;
+        let trigger = &alarm.trigger;
+        let dimensions: Vec<String> = trigger
+            .dimensions
+            .iter()
+            .map(|d| format!("{}={}", d.name, d.value))
+            .collect();
+
+        let mut actions = Vec::new();
+        if let Some(url) = alarm_console_url(alarm) {
+            actions.push(json!({"type": "Action.OpenUrl", "title": l10n.text("open-in-cloudwatch"), "url": url}));
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            actions.push(json!({"type": "Action.OpenUrl", "title": l10n.text("metric-graph"), "url": url}));
+        }
+        if let Some(ref url) = self.runbook_url {
+            actions.push(json!({"type": "Action.OpenUrl", "title": l10n.text("runbook"), "url": url}));
+        }
+
+        json!({
+            "type": "message",
+            "attachments": [{
+                "contentType": "application/vnd.microsoft.card.adaptive",
+                "contentUrl": null,
+                "content": {
+                    "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                    "type": "AdaptiveCard",
+                    "version": "1.4",
+                    "msteams": {"width": "Full"},
+                    "body": [
+                        {
+                            "type": "Container",
+                            "style": container_style(&alarm.new_state),
+                            "bleed": true,
+                            "items": [{
+                                "type": "TextBlock",
+                                "text": format!("{}: {}", l10n.state(&alarm.new_state), alarm.name),
+                                "weight": "Bolder",
+                                "size": "Medium",
+                                "wrap": true,
+                            }],
+                        },
+                        {
+                            "type": "TextBlock",
+                            "text": alarm.reason,
+                            "wrap": true,
+                        },
+                        {
+                            "type": "FactSet",
+                            "facts": [
+                                {"title": l10n.text("label-metric"), "value": format!("{} ({})", trigger.metric_name, trigger.statistic)},
+                                {"title": l10n.text("label-namespace"), "value": trigger.namespace},
+                                {"title": l10n.text("label-dimensions"), "value": dimensions.join(", ")},
+                                {"title": l10n.text("label-threshold"), "value": l10n.threshold(alarm)},
+                                {"title": l10n.text("label-period"), "value": l10n.period(alarm)},
+                            ],
+                        },
+                    ],
+                    "actions": actions,
+                },
+            }],
+        })
+    }
+}
+
+fn container_style(state: &AlarmState) -> &'static str {
+    match *state {
+        AlarmState::Ok => "good",
+        AlarmState::Alarm => "attention",
+        AlarmState::InsufficientData => "warning",
+    }
+}
+
+impl Notifier for TeamsNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        post_json(client, core, self.webhook_url.clone(), &self.card(alarm)).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    fn notifier(runbook_url: Option<&str>) -> TeamsNotifier {
+        TeamsNotifier {
+            webhook_url: "https://example.webhook.office.com/webhookb2/xxx".parse().unwrap(),
+            runbook_url: runbook_url.map(|url| url.to_string()),
+            l10n: Localizer::default(),
+        }
+    }
+
+    #[test]
+    fn test_card_facts() {
+        let card = notifier(None).card(&alarm_details("ALARM", "OK"));
+        let content = &card["attachments"][0]["content"];
+        let facts = &content["body"][2]["facts"];
+
+        assert_eq!(content["type"], "AdaptiveCard");
+        assert_eq!(content["body"][0]["items"][0]["text"], "ALARM: [RTB-US] UnHealthyHostCount");
+        assert_eq!(facts[0]["value"], "UnHealthyHostCount (AVERAGE)");
+        assert_eq!(facts[1]["value"], "AWS/ELB");
+        assert_eq!(facts[2]["value"], "LoadBalancerName=rtb");
+        assert_eq!(facts[3]["value"], ">= 1");
+        assert_eq!(facts[4]["value"], "5 x 60s");
+    }
+
+    #[test]
+    fn test_card_color_follows_state() {
+        let alarm = notifier(None).card(&alarm_details("ALARM", "OK"));
+        let ok = notifier(None).card(&alarm_details("OK", "ALARM"));
+        let insufficient = notifier(None).card(&alarm_details("INSUFFICIENT_DATA", "OK"));
+
+        assert_eq!(alarm["attachments"][0]["content"]["body"][0]["style"], "attention");
+        assert_eq!(ok["attachments"][0]["content"]["body"][0]["style"], "good");
+        assert_eq!(insufficient["attachments"][0]["content"]["body"][0]["style"], "warning");
+    }
+
+    #[test]
+    fn test_card_actions() {
+        let without_runbook = notifier(None).card(&alarm_details("ALARM", "OK"));
+        let with_runbook = notifier(Some("https://wiki.example.org/rtb")).card(&alarm_details("ALARM", "OK"));
+
+        let actions = &with_runbook["attachments"][0]["content"]["actions"];
+        assert_eq!(without_runbook["attachments"][0]["content"]["actions"].as_array().map(Vec::len), Some(2));
+        assert_eq!(actions[0]["title"], "Open in CloudWatch");
+        assert_eq!(actions[1]["title"], "Metric graph");
+        assert_eq!(actions[2]["url"], "https://wiki.example.org/rtb");
+    }
+
+    #[test]
+    fn test_card_is_localized() {
+        let mut notifier = notifier(None);
+        notifier.l10n.locale = "fr".parse().unwrap();
+        let card = notifier.card(&alarm_details("ALARM", "OK"));
+        let content = &card["attachments"][0]["content"];
+
+        assert_eq!(content["body"][0]["items"][0]["text"], "ALARME: [RTB-US] UnHealthyHostCount");
+        assert_eq!(content["body"][2]["facts"][3]["title"], "Seuil");
+        assert_eq!(content["body"][2]["facts"][3]["value"], "supérieur ou égal à 1");
+        assert_eq!(content["actions"][0]["title"], "Ouvrir dans CloudWatch");
+    }
+}
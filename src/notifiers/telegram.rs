@@ -0,0 +1,324 @@
+use hyper::{Client, Uri};
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, metric_graph_url};
+use model::{AlarmDetails, AlarmState};
+use oncall::Mention;
+use super::{Notifier, NotifierError, post_json, truncate};
+use super::posts::Posts;
+
+// Telegram refuses messages longer than 4096 characters, the reason is the
+// only unbounded part of ours.
+const REASON_LIMIT: usize = 3000;
+
+#[derive(Debug, Clone)]
+pub(crate) struct TelegramChat {
+    pub chat_id: String,
+    // Topic of a forum supergroup, `None` posts in the general topic.
+    pub thread_id: Option<i64>,
+}
+
+impl TelegramChat {
+    fn target(&self) -> String {
+        match self.thread_id {
+            Some(thread_id) => format!("{}#{}", self.chat_id, thread_id),
+            None => self.chat_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TelegramNotifier {
+    pub bot_token: String,
+    pub chats: Vec<TelegramChat>,
+    pub l10n: Localizer,
+    // The chats which already got a transition whose delivery failed for
+    // others.
+    pub posts: Posts,
+}
+
+// Every character reserved by MarkdownV2 must be escaped outside of entities.
+// https://core.telegram.org/bots/api#markdownv2-style
+pub(crate) fn escape_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '_' | '*' | '[' | ']' | '(' | ')' | '~' | '`' | '>' | '#' | '+' | '-' | '=' | '|' | '{' | '}' | '.' |
+            '!' | '\\' => escaped.push('\\'),
+            _ => (),
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Inside the (...) part of an inline link, only ')' and '\' must be escaped.
+fn escape_link_url(url: &str) -> String {
+    url.replace('\\', "\\\\").replace(')', "\\)")
+}
+
+fn state_emoji(state: &AlarmState) -> &'static str {
+    match *state {
+        AlarmState::Ok => "✅",
+        AlarmState::Alarm => "🔴",
+        AlarmState::InsufficientData => "⚠️",
+    }
+}
+
+// Checked when the configuration is parsed. Tokens look like
+// "123456:ABC-DEF", hyper would take anything in the path. The token is left
+// out of the error, which ends up in the logs.
+pub(crate) fn send_message_url(bot_token: &str) -> Result<Uri, NotifierError> {
+    let invalid = || NotifierError::InvalidUrl("https://api.telegram.org/bot<token>/sendMessage".to_string());
+    let url_safe = |c: char| (c.is_ascii() && c.is_alphanumeric()) || c == ':' || c == '_' || c == '-';
+    if bot_token.is_empty() || !bot_token.chars().all(url_safe) {
+        return Err(invalid());
+    }
+    format!("https://api.telegram.org/bot{}/sendMessage", bot_token).parse().map_err(|_| invalid())
+}
+
+impl TelegramNotifier {
+    fn text(&self, alarm: &AlarmDetails) -> String {
+        let l10n = &self.l10n;
+        let mut text = format!(
+            "{} *{}*: *{}*\n{}\n\n{} {}",
+            state_emoji(&alarm.new_state),
+            escape_markdown_v2(&l10n.state(&alarm.new_state)),
+            escape_markdown_v2(&alarm.name),
+            escape_markdown_v2(&truncate(&alarm.reason, REASON_LIMIT)),
+            escape_markdown_v2(&alarm.trigger.namespace),
+            escape_markdown_v2(&l10n.condition(alarm))
+        );
+        if let Some(url) = alarm_console_url(alarm) {
+            text.push_str(&format!(
+                "\n[{}]({})",
+                escape_markdown_v2(&l10n.text("open-in-cloudwatch")),
+                escape_link_url(&url)
+            ));
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            text.push_str(&format!("\n[{}]({})", escape_markdown_v2(&l10n.text("metric-graph")), escape_link_url(&url)));
+        }
+        text
+    }
+
+    fn payload(&self, chat: &TelegramChat, text: &str) -> Value {
+        let mut payload = self.plain_payload(chat, text);
+        payload["parse_mode"] = json!("MarkdownV2");
+        payload
+    }
+
+    // Templated text is sent as is, users would otherwise have to escape
+    // every MarkdownV2 character themselves.
+    fn plain_payload(&self, chat: &TelegramChat, text: &str) -> Value {
+        let mut payload = json!({
+            "chat_id": chat.chat_id,
+            "text": text,
+            "disable_web_page_preview": true,
+        });
+        if let Some(thread_id) = chat.thread_id {
+            payload["message_thread_id"] = json!(thread_id);
+        }
+        payload
+    }
+
+    // Every chat is tried even if a previous one failed, the first error is
+    // reported. The queue retries the delivery, which then skips the chats
+    // that already got it.
+    fn send<F>(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        payload: F,
+    ) -> Result<(), NotifierError>
+    where
+        F: Fn(&TelegramChat) -> Value,
+    {
+        let url = send_message_url(&self.bot_token)?;
+        let tracked = self.chats.len() > 1;
+        let mut result = Ok(());
+        for chat in &self.chats {
+            if tracked && self.posts.delivered(alarm, &chat.target()) {
+                continue;
+            }
+            match post_json(client, core, url.clone(), &payload(chat)) {
+                Ok(_) if tracked => self.posts.remember_delivered(alarm, &chat.target()),
+                Ok(_) => {}
+                Err(err) => if result.is_ok() {
+                    result = Err(err);
+                },
+            }
+        }
+        if tracked && result.is_ok() {
+            for chat in &self.chats {
+                self.posts.forget_delivered(alarm, &chat.target());
+            }
+        }
+        result
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        let text = self.text(alarm);
+        self.send(client, core, alarm, |chat| self.payload(chat, &text))
+    }
+
+    fn notify_text(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: &str,
+    ) -> Result<(), NotifierError> {
+        self.send(client, core, alarm, |chat| self.plain_payload(chat, text))
+    }
+
+    // Only users can be mentioned, by username.
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let usernames: Vec<String> = mentions
+            .iter()
+            .filter_map(|mention| match *mention {
+                Mention::User(ref username) => Some(format!("@{}", username.trim_left_matches('@'))),
+                _ => None,
+            })
+            .collect();
+        if usernames.is_empty() {
+            return match text {
+                Some(text) => self.notify_text(client, core, alarm, text),
+                None => self.notify(client, core, alarm),
+            };
+        }
+
+        match text {
+            Some(text) => {
+                let text = format!("{} {}", usernames.join(" "), text);
+                self.send(client, core, alarm, |chat| self.plain_payload(chat, &text))
+            }
+            None => {
+                let text = format!("{}\n{}", escape_markdown_v2(&usernames.join(" ")), self.text(alarm));
+                self.send(client, core, alarm, |chat| self.payload(chat, &text))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{core, https_client};
+
+    fn notifier() -> TelegramNotifier {
+        TelegramNotifier {
+            bot_token: "123456:ABC-DEF".to_string(),
+            chats: vec![
+                TelegramChat {
+                    chat_id: "-1001234567890".to_string(),
+                    thread_id: None,
+                },
+                TelegramChat {
+                    chat_id: "@rtb_oncall".to_string(),
+                    thread_id: Some(42),
+                },
+            ],
+            l10n: Localizer::default(),
+            posts: Posts::default(),
+        }
+    }
+
+    #[test]
+    fn test_escape_markdown_v2() {
+        assert_eq!(escape_markdown_v2("[RTB-US] UnHealthyHostCount"), "\\[RTB\\-US\\] UnHealthyHostCount");
+        assert_eq!(escape_markdown_v2("1.0 (avg) > 2!"), "1\\.0 \\(avg\\) \\> 2\\!");
+        assert_eq!(escape_markdown_v2("a\\b_c*d"), "a\\\\b\\_c\\*d");
+    }
+
+    #[test]
+    fn test_text() {
+        let text = notifier().text(&alarm_details("INSUFFICIENT_DATA", "OK"));
+        let mut lines = text.lines();
+
+        assert_eq!(lines.next(), Some("⚠️ *INSUFFICIENT\\_DATA*: *\\[RTB\\-US\\] UnHealthyHostCount*"));
+        assert_eq!(
+            lines.next(),
+            Some(
+                "Threshold Crossed: 1 datapoint \\(0\\.4482758620689655\\) was not greater than or equal to the threshold \\(1\\.0\\)\\.",
+            )
+        );
+        assert_eq!(lines.nth(1), Some("AWS/ELB UnHealthyHostCount \\>\\= 1"));
+        assert_eq!(
+            lines.next(),
+            Some(
+                "[Open in CloudWatch](https://console.aws.amazon.com/cloudwatch/home?region=us-east-1#alarmsV2:alarm/%5BRTB-US%5D%20UnHealthyHostCount)",
+            )
+        );
+    }
+
+    #[test]
+    fn test_text_is_localized() {
+        let mut notifier = notifier();
+        notifier.l10n.locale = "ja".parse().unwrap();
+        let text = notifier.text(&alarm_details("ALARM", "OK"));
+        let lines: Vec<&str> = text.lines().collect();
+
+        assert_eq!(lines[0], "🔴 *アラーム*: *\\[RTB\\-US\\] UnHealthyHostCount*");
+        assert_eq!(lines[3], "AWS/ELB UnHealthyHostCount が 1 以上");
+        assert_eq!(lines[4].starts_with("[CloudWatch で開く]("), true);
+    }
+
+    #[test]
+    fn test_payload_per_chat() {
+        let notifier = notifier();
+        let general = notifier.payload(&notifier.chats[0], "text");
+        let forum_topic = notifier.payload(&notifier.chats[1], "text");
+
+        assert_eq!(general["chat_id"], "-1001234567890");
+        assert_eq!(general["parse_mode"], "MarkdownV2");
+        assert_eq!(general.get("message_thread_id"), None);
+        assert_eq!(forum_topic["chat_id"], "@rtb_oncall");
+        assert_eq!(forum_topic["message_thread_id"], 42);
+    }
+
+    #[test]
+    fn test_retry_skips_delivered_chats() {
+        let notifier = notifier();
+        let alarm = alarm_details("OK", "ALARM");
+        for chat in &notifier.chats {
+            notifier.posts.remember_delivered(&alarm, &chat.target());
+        }
+
+        // Nothing is sent to Telegram, so this doesn't need the network.
+        let mut core = core();
+        let client = https_client(&core);
+        notifier.notify(&client, &mut core, &alarm).unwrap();
+        for chat in &notifier.chats {
+            assert_eq!(notifier.posts.delivered(&alarm, &chat.target()), false);
+        }
+    }
+
+    #[test]
+    fn test_send_message_url() {
+        assert_eq!(
+            send_message_url(&notifier().bot_token).unwrap().to_string(),
+            "https://api.telegram.org/bot123456:ABC-DEF/sendMessage"
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use hmac::{Hmac, Mac};
+use hyper::{Client, Method, Request, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::ContentType;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio_core::reactor::Core;
+
+use model::AlarmDetails;
+use super::{Notifier, NotifierError, alarm_json, send};
+
+pub(crate) const SIGNATURE_HEADER: &str = "X-CloudWatch-Bot-Signature";
+
+#[derive(Debug)]
+pub(crate) struct WebhookNotifier {
+    pub url: Uri,
+    // JSON document whose strings may reference the alarm fields, e.g.
+    // "{{trigger.namespace}}". `None` sends the normalized alarm as is.
+    pub template: Option<Value>,
+    pub headers: Vec<(String, String)>,
+    // When set, the body is signed with HMAC-SHA256 in `SIGNATURE_HEADER`.
+    pub secret: Option<String>,
+}
+
+// Looks up a dotted path ("trigger.dimensions.LoadBalancerName") in `context`.
+fn lookup<'a>(context: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').fold(Some(context), |value, key| value.and_then(|v| v.get(key)))
+}
+
+fn interpolate(text: &str, context: &Value) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+        rendered.push_str(&rest[..start]);
+        match lookup(context, rest[start + 2..end].trim()) {
+            Some(&Value::String(ref value)) => rendered.push_str(value),
+            Some(&Value::Null) | None => (),
+            Some(value) => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+fn single_placeholder(text: &str) -> Option<&str> {
+    let trimmed = text.trim();
+    if trimmed.len() < 4 || !trimmed.starts_with("{{") || !trimmed.ends_with("}}") {
+        return None;
+    }
+    let path = &trimmed[2..trimmed.len() - 2];
+    if path.contains("{{") || path.contains("}}") {
+        None
+    } else {
+        Some(path.trim())
+    }
+}
+
+// A string made of a single placeholder is replaced by the referenced value
+// itself, so numbers and objects keep their JSON type.
+pub(crate) fn render(template: &Value, context: &Value) -> Value {
+    match *template {
+        Value::String(ref text) => match single_placeholder(text) {
+            Some(path) => lookup(context, path).cloned().unwrap_or(Value::Null),
+            None => Value::String(interpolate(text, context)),
+        },
+        Value::Array(ref values) => Value::Array(values.iter().map(|v| render(v, context)).collect()),
+        Value::Object(ref map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), render(v, context))).collect()),
+        ref other => other.clone(),
+    }
+}
+
+pub(crate) fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.input(body);
+    let hex: Vec<String> = mac.result().code().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hex.concat())
+}
+
+impl WebhookNotifier {
+    fn body(&self, alarm: &AlarmDetails) -> String {
+        let context = alarm_json(alarm);
+        match self.template {
+            Some(ref template) => render(template, &context).to_string(),
+            None => context.to_string(),
+        }
+    }
+
+    fn request(&self, body: String) -> Request {
+        let mut req = Request::new(Method::Post, self.url.clone());
+        req.headers_mut().set(ContentType::json());
+        for &(ref name, ref value) in &self.headers {
+            req.headers_mut().set_raw(name.clone(), value.clone());
+        }
+        if let Some(ref secret) = self.secret {
+            req.headers_mut().set_raw(SIGNATURE_HEADER, signature(secret, body.as_bytes()));
+        }
+        req.set_body(body);
+        req
+    }
+}
+
+impl Notifier for WebhookNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        let status = send(client, core, self.request(self.body(alarm)))?.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(NotifierError::BadStatus(status))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+    use serde_json;
+
+    #[test]
+    fn test_render() {
+        let context = alarm_json(&alarm_details("ALARM", "OK"));
+        let template = json!({
+            "title": "{{ new_state }}: {{name}}",
+            "lb": "{{trigger.dimensions.LoadBalancerName}}",
+            "threshold": "{{trigger.threshold}}",
+            "dimensions": "{{trigger.dimensions}}",
+            "missing": "[{{nope}}]",
+            "static": [1, true],
+        });
+
+        assert_eq!(
+            render(&template, &context),
+            json!({
+                "title": "ALARM: [RTB-US] UnHealthyHostCount",
+                "lb": "rtb",
+                "threshold": 1.0,
+                "dimensions": {"LoadBalancerName": "rtb"},
+                "missing": "[]",
+                "static": [1, true],
+            })
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            signature("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_notify_against_mock_server() {
+        let server = MockServer::start(vec![(StatusCode::NoContent, "")]);
+        let notifier = WebhookNotifier {
+            url: format!("{}/hooks/alarms", server.url).parse().unwrap(),
+            template: Some(json!({"alarm": "{{name}}", "state": "{{new_state}}"})),
+            headers: vec![("X-Team".to_string(), "rtb".to_string())],
+            secret: Some("s3cr3t".to_string()),
+        };
+        let mut core = core();
+        let client = https_client(&core);
+
+        let res = notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK"));
+        assert_eq!(res.is_ok(), true);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "/hooks/alarms");
+        assert_eq!(
+            serde_json::from_str::<Value>(&requests[0].body).unwrap(),
+            json!({"alarm": "[RTB-US] UnHealthyHostCount", "state": "ALARM"})
+        );
+        let raw = |name| requests[0].headers.get_raw(name).and_then(|r| r.one()).map(|v| v.to_vec());
+        assert_eq!(raw("X-Team"), Some(b"rtb".to_vec()));
+        assert_eq!(raw(SIGNATURE_HEADER), Some(signature("s3cr3t", requests[0].body.as_bytes()).into_bytes()));
+    }
+}
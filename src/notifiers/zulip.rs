@@ -0,0 +1,326 @@
+use hyper::{Client, Method, Response, StatusCode, Uri};
+use hyper::client::HttpConnector;
+use hyper::header::{Authorization, Basic};
+use hyper_tls::HttpsConnector;
+use tokio_core::reactor::Core;
+
+use l10n::Localizer;
+use links::{alarm_console_url, metric_graph_url};
+use model::{AlarmDetails, AlarmState};
+use oncall::Mention;
+use super::{GroupMessage, Notifier, NotifierError, form_request, read_json, send, truncate};
+use super::posts::{Posts, resolved_after};
+
+// https://zulip.com/api/send-message, longer topics are rejected.
+const TOPIC_LIMIT: usize = 60;
+
+#[derive(Debug)]
+pub(crate) struct ZulipNotifier {
+    pub site_url: Uri,
+    pub bot_email: String,
+    pub api_key: String,
+    pub stream: String,
+    // Defaults to the alarm name, so that every transition of an alarm ends
+    // up in the same topic.
+    pub topic: Option<String>,
+    pub l10n: Localizer,
+    pub posts: Posts,
+}
+
+fn state_emoji(state: &AlarmState) -> &'static str {
+    match *state {
+        AlarmState::Ok => ":check:",
+        AlarmState::Alarm => ":red_circle:",
+        AlarmState::InsufficientData => ":warning:",
+    }
+}
+
+impl ZulipNotifier {
+    fn messages_url(&self) -> Result<Uri, NotifierError> {
+        let url = format!("{}/api/v1/messages", self.site_url.as_ref().trim_right_matches('/'));
+        url.parse().map_err(|_| NotifierError::InvalidUrl(url))
+    }
+
+    fn message_url(&self, id: &str) -> Result<Uri, NotifierError> {
+        let url = format!("{}/api/v1/messages/{}", self.site_url.as_ref().trim_right_matches('/'), id);
+        url.parse().map_err(|_| NotifierError::InvalidUrl(url))
+    }
+
+    fn send(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        method: Method,
+        uri: Uri,
+        params: &[(&str, &str)],
+    ) -> Result<Response, NotifierError> {
+        let mut req = form_request(method, uri, params);
+        req.headers_mut().set(Authorization(Basic {
+            username: self.bot_email.clone(),
+            password: Some(self.api_key.clone()),
+        }));
+
+        let resp = send(client, core, req)?;
+        let status = resp.status();
+        if status.is_success() {
+            Ok(resp)
+        } else {
+            Err(NotifierError::BadStatus(status))
+        }
+    }
+
+    fn topic(&self, alarm: &AlarmDetails) -> String {
+        truncate(self.topic.as_ref().unwrap_or(&alarm.name), TOPIC_LIMIT)
+    }
+
+    // The topic names the alarm, unless it is fixed.
+    fn content(&self, alarm: &AlarmDetails) -> String {
+        let l10n = &self.l10n;
+        let state = match self.topic {
+            Some(_) => format!("**{}** {}", l10n.state(&alarm.new_state), alarm.name),
+            None => format!("**{}**", l10n.state(&alarm.new_state)),
+        };
+        let mut content = format!(
+            "{} {} {}: {}\n`{}` {}",
+            state_emoji(&alarm.new_state),
+            state,
+            l10n.was_state(&alarm.previous_state),
+            alarm.reason,
+            alarm.trigger.namespace,
+            l10n.condition(alarm)
+        );
+        if let Some(url) = alarm_console_url(alarm) {
+            content.push_str(&format!("\n[{}]({})", l10n.text("open-in-cloudwatch"), url));
+        }
+        if let Some(url) = metric_graph_url(alarm) {
+            content.push_str(&format!(" | [{}]({})", l10n.text("metric-graph"), url));
+        }
+        content
+    }
+}
+
+// Users and groups by name, `@**all**` notifies everyone subscribed to the
+// stream.
+fn mentions_line(mentions: &[Mention]) -> String {
+    let mentions: Vec<String> = mentions
+        .iter()
+        .map(|mention| match *mention {
+            Mention::User(ref name) => format!("@**{}**", name),
+            Mention::Group(ref name) => format!("@*{}*", name),
+            Mention::Here => "@**all**".to_string(),
+        })
+        .collect();
+    mentions.join(" ")
+}
+
+impl Notifier for ZulipNotifier {
+    fn notify(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, None, &[])
+    }
+
+    fn notify_text(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        content: &str,
+    ) -> Result<(), NotifierError> {
+        self.notify_mentioning(client, core, alarm, Some(content), &[])
+    }
+
+    fn notify_mentioning(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        alarm: &AlarmDetails,
+        text: Option<&str>,
+        mentions: &[Mention],
+    ) -> Result<(), NotifierError> {
+        let post = self.posts.get(alarm);
+        let mut content = match text {
+            Some(text) => text.to_string(),
+            None => self.content(alarm),
+        };
+        if !mentions.is_empty() {
+            content = format!("{}\n{}", mentions_line(mentions), content);
+        }
+
+        // The recovery replaces the message of the alarm, unless it has been
+        // deleted in the meantime.
+        if let Some(post) = post {
+            if text.is_none() {
+                if let Some(resolved) = resolved_after(&self.l10n, &post, alarm) {
+                    content.push_str(&format!("\n*{}*", resolved));
+                }
+            }
+            match self.send(client, core, Method::Patch, self.message_url(&post.id)?, &[("content", &content)]) {
+                Err(NotifierError::BadStatus(StatusCode::BadRequest)) => {}
+                Err(err) => return Err(err),
+                Ok(_) => {
+                    self.posts.forget(alarm);
+                    return Ok(());
+                }
+            }
+        }
+
+        let topic = self.topic(alarm);
+        let resp = self.send(
+            client,
+            core,
+            Method::Post,
+            self.messages_url()?,
+            &[("type", "stream"), ("to", &self.stream), ("topic", &topic), ("content", &content)],
+        )?;
+        if let Some(id) = read_json(core, resp).ok().and_then(|message| message["id"].as_u64()) {
+            self.posts.remember(alarm, id.to_string());
+        }
+        Ok(())
+    }
+
+    fn can_group(&self) -> bool {
+        true
+    }
+
+    // Posted under the group's key rather than an alarm name, unless the
+    // topic is fixed.
+    fn notify_group(
+        &self,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        group: &GroupMessage,
+    ) -> Result<(), NotifierError> {
+        let id = if group.update { self.posts.group(group.id) } else { None };
+        if let Some(id) = id {
+            match self.send(client, core, Method::Patch, self.message_url(&id)?, &[("content", group.text)]) {
+                Err(NotifierError::BadStatus(StatusCode::BadRequest)) => {}
+                result => return result.map(|_| ()),
+            }
+        }
+
+        let topic = truncate(self.topic.as_ref().map_or(group.key, String::as_str), TOPIC_LIMIT);
+        let resp = self.send(
+            client,
+            core,
+            Method::Post,
+            self.messages_url()?,
+            &[("type", "stream"), ("to", &self.stream), ("topic", &topic), ("content", group.text)],
+        )?;
+        if let Some(id) = read_json(core, resp).ok().and_then(|message| message["id"].as_u64()) {
+            self.posts.remember_group(group.id, id.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use notifiers::mock_server::{MockServer, core, https_client};
+
+    fn notifier(site_url: &str, topic: Option<&str>) -> ZulipNotifier {
+        ZulipNotifier {
+            site_url: site_url.parse().unwrap(),
+            bot_email: "cloudwatch-bot@example.zulipchat.com".to_string(),
+            api_key: "secret".to_string(),
+            stream: "ops alerts".to_string(),
+            topic: topic.map(|t| t.to_string()),
+            l10n: Localizer::default(),
+            posts: Posts::default(),
+        }
+    }
+
+    #[test]
+    fn test_topic_defaults_to_alarm_name() {
+        let alarm = alarm_details("ALARM", "OK");
+
+        assert_eq!(notifier("https://example.zulipchat.com", None).topic(&alarm), "[RTB-US] UnHealthyHostCount");
+        assert_eq!(notifier("https://example.zulipchat.com", Some("rtb")).topic(&alarm), "rtb");
+    }
+
+    #[test]
+    fn test_content() {
+        let content = notifier("https://example.zulipchat.com", None).content(&alarm_details("OK", "ALARM"));
+        let mut lines = content.lines();
+
+        assert_eq!(lines.next().map(|l| l.starts_with(":check: **OK** (was ALARM): Threshold Crossed")), Some(true));
+        assert_eq!(lines.next(), Some("`AWS/ELB` UnHealthyHostCount >= 1"));
+    }
+
+    #[test]
+    fn test_content_names_the_alarm_in_a_fixed_topic() {
+        let content = notifier("https://example.zulipchat.com", Some("rtb")).content(&alarm_details("ALARM", "OK"));
+
+        assert_eq!(
+            content.starts_with(":red_circle: **ALARM** [RTB-US] UnHealthyHostCount (was OK): Threshold Crossed"),
+            true
+        );
+    }
+
+    #[test]
+    fn test_mentions_line() {
+        assert_eq!(
+            mentions_line(&[Mention::User("Alice".to_string()), Mention::Group("ops".to_string()), Mention::Here]),
+            "@**Alice** @*ops* @**all**"
+        );
+    }
+
+    #[test]
+    fn test_content_is_localized() {
+        let mut notifier = notifier("https://example.zulipchat.com", None);
+        notifier.l10n.locale = "fr".parse().unwrap();
+        let content = notifier.content(&alarm_details("ALARM", "OK"));
+        let mut lines = content.lines();
+
+        assert_eq!(
+            lines.next().map(|l| l.starts_with(":red_circle: **ALARME** (précédemment OK): Threshold Crossed")),
+            Some(true)
+        );
+        assert_eq!(lines.next(), Some("`AWS/ELB` UnHealthyHostCount supérieur ou égal à 1"));
+        assert_eq!(lines.next().map(|l| l.starts_with("[Ouvrir dans CloudWatch](")), Some(true));
+    }
+
+    #[test]
+    fn test_notify_against_mock_server() {
+        let server = MockServer::start(vec![(StatusCode::Ok, "{\"result\": \"success\", \"id\": 42}")]);
+        let mut core = core();
+        let client = https_client(&core);
+
+        let res = notifier(&server.url, None).notify(&client, &mut core, &alarm_details("ALARM", "OK"));
+        assert_eq!(res.is_ok(), true);
+
+        let requests = server.requests();
+        assert_eq!(requests[0].path, "/api/v1/messages");
+        assert_eq!(
+            requests[0].body.starts_with("type=stream&to=ops%20alerts&topic=%5BRTB-US%5D%20UnHealthyHostCount&content="),
+            true
+        );
+        assert_eq!(
+            requests[0].headers.get::<Authorization<Basic>>().map(|auth| auth.username.as_str()),
+            Some("cloudwatch-bot@example.zulipchat.com")
+        );
+    }
+
+    #[test]
+    fn test_recovery_edits_the_alarm_message() {
+        let server = MockServer::start(vec![(StatusCode::Ok, "{\"result\": \"success\", \"id\": 42}")]);
+        let mut core = core();
+        let client = https_client(&core);
+        let notifier = notifier(&server.url, None);
+
+        notifier.notify(&client, &mut core, &alarm_details("ALARM", "OK")).unwrap();
+        notifier.notify(&client, &mut core, &alarm_details("OK", "ALARM")).unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, Method::Patch);
+        assert_eq!(requests[1].path, "/api/v1/messages/42");
+        assert_eq!(requests[1].body.starts_with("content=%3Acheck%3A"), true);
+        assert_eq!(requests[1].body.contains("Resolved%20after%200s"), true);
+    }
+}
@@ -0,0 +1,158 @@
+use chrono::{DateTime, Duration, Utc};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+// Who to ping with an alarm. IDs are those of the destination's chat system:
+// Discord user and role IDs, Matrix user IDs ("@alice:example.org"), user
+// names for Telegram, Zulip and IRC...
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Mention {
+    User(String),
+    // Discord role, Slack or Zulip user group.
+    Group(String),
+    // Everyone in the channel or room.
+    Here,
+}
+
+impl FromStr for Mention {
+    type Err = String;
+
+    fn from_str(mention: &str) -> Result<Mention, String> {
+        if mention == "here" || mention == "@here" {
+            return Ok(Mention::Here);
+        }
+        let mut parts = mention.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("user"), Some(id)) if !id.is_empty() => Ok(Mention::User(id.to_string())),
+            (Some("group"), Some(id)) if !id.is_empty() => Ok(Mention::Group(id.to_string())),
+            _ => Err(format!(
+                "invalid mention `{}`, expected `user:<id>`, `group:<id>`, `here` or `rotation:<name>`",
+                mention
+            )),
+        }
+    }
+}
+
+impl Display for Mention {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mention::User(ref id) => write!(fmt, "user:{}", id),
+            Mention::Group(ref id) => write!(fmt, "group:{}", id),
+            Mention::Here => write!(fmt, "here"),
+        }
+    }
+}
+
+// A mention as configured on a route, rotations are resolved to whoever is
+// on call when the alarm is routed.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum MentionTarget {
+    Mention(Mention),
+    Rotation(String),
+}
+
+impl FromStr for MentionTarget {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<MentionTarget, String> {
+        if target.starts_with("rotation:") && target.len() > "rotation:".len() {
+            Ok(MentionTarget::Rotation(target["rotation:".len()..].to_string()))
+        } else {
+            target.parse().map(MentionTarget::Mention)
+        }
+    }
+}
+
+// Someone taking over the rotation for a while (holidays, swapped shifts).
+#[derive(Debug)]
+pub(crate) struct Override {
+    pub member: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+// Members take turns for `shift` each, in order, from `start` on.
+#[derive(Debug)]
+pub(crate) struct Rotation {
+    pub members: Vec<String>,
+    pub start: DateTime<Utc>,
+    pub shift: Duration,
+    pub overrides: Vec<Override>,
+}
+
+impl Rotation {
+    // Overrides win over the rotation, the first one listed when several
+    // cover `now`. Nobody is on call before the rotation starts.
+    pub fn on_call(&self, now: DateTime<Utc>) -> Option<&str> {
+        if let Some(over) = self.overrides.iter().find(|o| o.start <= now && now < o.end) {
+            return Some(&over.member);
+        }
+        if now < self.start || self.members.is_empty() {
+            return None;
+        }
+        let shifts = now.signed_duration_since(self.start).num_seconds() / self.shift.num_seconds();
+        Some(&self.members[(shifts as usize) % self.members.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_mentions() {
+        assert_eq!("user:123".parse(), Ok(MentionTarget::Mention(Mention::User("123".to_string()))));
+        assert_eq!(
+            "user:@alice:example.org".parse(),
+            Ok(MentionTarget::Mention(Mention::User("@alice:example.org".to_string())))
+        );
+        assert_eq!("group:456".parse(), Ok(MentionTarget::Mention(Mention::Group("456".to_string()))));
+        assert_eq!("@here".parse(), Ok(MentionTarget::Mention(Mention::Here)));
+        assert_eq!("rotation:rtb".parse(), Ok(MentionTarget::Rotation("rtb".to_string())));
+        assert_eq!(
+            "alice".parse::<MentionTarget>(),
+            Err("invalid mention `alice`, expected `user:<id>`, `group:<id>`, `here` or `rotation:<name>`".to_string())
+        );
+        assert_eq!("user:".parse::<MentionTarget>().is_err(), true);
+    }
+
+    #[test]
+    fn test_weekly_rotation() {
+        let rotation = Rotation {
+            members: vec!["alice".to_string(), "bob".to_string(), "carol".to_string()],
+            start: time("2024-01-01T09:00:00Z"),
+            shift: Duration::weeks(1),
+            overrides: vec![],
+        };
+
+        assert_eq!(rotation.on_call(time("2023-12-31T09:00:00Z")), None);
+        assert_eq!(rotation.on_call(time("2024-01-01T09:00:00Z")), Some("alice"));
+        assert_eq!(rotation.on_call(time("2024-01-08T08:59:59Z")), Some("alice"));
+        assert_eq!(rotation.on_call(time("2024-01-08T09:00:00Z")), Some("bob"));
+        assert_eq!(rotation.on_call(time("2024-01-22T10:00:00Z")), Some("alice"));
+    }
+
+    #[test]
+    fn test_override() {
+        let rotation = Rotation {
+            members: vec!["alice".to_string(), "bob".to_string()],
+            start: time("2024-01-01T09:00:00Z"),
+            shift: Duration::weeks(1),
+            overrides: vec![
+                Override {
+                    member: "dave".to_string(),
+                    start: time("2024-01-03T00:00:00Z"),
+                    end: time("2024-01-05T00:00:00Z"),
+                },
+            ],
+        };
+
+        assert_eq!(rotation.on_call(time("2024-01-02T23:59:59Z")), Some("alice"));
+        assert_eq!(rotation.on_call(time("2024-01-03T00:00:00Z")), Some("dave"));
+        assert_eq!(rotation.on_call(time("2024-01-05T00:00:00Z")), Some("alice"));
+    }
+}
@@ -0,0 +1,591 @@
+mod store;
+
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_tls::HttpsConnector;
+use serde_json::Value;
+use rand::{self, Rng};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_core::reactor::Core;
+
+use config::{Config, QueueConfig, SharedConfig};
+use l10n::Localizer;
+use model::AlarmDetails;
+use notifiers::{GroupMessage, Notifier};
+use oncall::Mention;
+use routing::{self, RoutingDecision};
+use self::store::Store;
+
+const POLL_INTERVAL_SECS: u64 = 1;
+
+static SEQUENCE: AtomicUsize = ATOMIC_USIZE_INIT;
+
+// The consolidated message of a group, sent instead of the alarm's own.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct GroupDelivery {
+    pub id: String,
+    pub key: String,
+    // The latest notification of every member, in the order they joined.
+    pub notifications: Vec<String>,
+    pub changed: Vec<usize>,
+    pub update: bool,
+}
+
+// The message of one destination, rendered when the notification came in so
+// that a reload doesn't change what is eventually sent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Job {
+    // Sortable in the order jobs were queued.
+    pub id: String,
+    pub destination: String,
+    // The SNS notification as received, the alarm is parsed again from it.
+    pub notification: String,
+    pub routing: Value,
+    pub text: Option<String>,
+    pub mentions: Vec<String>,
+    pub attempts: u32,
+    // Unix time, in seconds.
+    pub next_attempt: u64,
+    pub last_error: Option<String>,
+    #[serde(default)]
+    pub group: Option<GroupDelivery>,
+}
+
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_else(|_| Duration::from_secs(0))
+}
+
+impl Job {
+    fn new(destination: &str, notification: &str, routing: Value, text: Option<String>, mentions: Vec<String>) -> Job {
+        let now = now();
+        let sequence = SEQUENCE.fetch_add(1, Ordering::SeqCst) % 10_000;
+        Job {
+            id: format!("{:010}{:09}-{:04}", now.as_secs(), now.subsec_nanos(), sequence),
+            destination: destination.to_string(),
+            notification: notification.to_string(),
+            routing: routing,
+            text: text,
+            mentions: mentions,
+            attempts: 0,
+            next_attempt: 0,
+            last_error: None,
+            group: None,
+        }
+    }
+
+    // One job per destination of the decision.
+    pub fn all(config: &Config, decision: &RoutingDecision, alarm: &AlarmDetails, notification: &str) -> Vec<Job> {
+        let routing = decision.to_json();
+        decision
+            .destinations
+            .iter()
+            .map(|destination| {
+                let (text, mentions) = routing::render(config, decision, alarm, destination);
+                let mentions = mentions.iter().map(Mention::to_string).collect();
+                Job::new(destination, notification, routing.clone(), text, mentions)
+            })
+            .collect()
+    }
+
+    // A notice written by the bot rather than the alarm's message, in the
+    // language of each destination and without any mention.
+    pub fn notices<F>(config: &Config, decision: &RoutingDecision, notification: &str, notice: F) -> Vec<Job>
+    where
+        F: Fn(&Localizer) -> String,
+    {
+        let routing = decision.to_json();
+        decision
+            .destinations
+            .iter()
+            .map(|destination| {
+                let text = match config.localizers.get(destination) {
+                    Some(l10n) => notice(l10n),
+                    None => notice(&Localizer::default()),
+                };
+                Job::new(destination, notification, routing.clone(), Some(text), Vec::new())
+            })
+            .collect()
+    }
+
+    // The message of a group to one of its destinations, `text` being
+    // rendered in the destination's language.
+    pub fn group(destination: &str, routing: Value, text: String, group: GroupDelivery) -> Job {
+        let notification = group.notifications.last().cloned().unwrap_or_default();
+        let mut job = Job::new(destination, &notification, routing, Some(text), Vec::new());
+        job.group = Some(group);
+        job
+    }
+}
+
+fn deliver_group(
+    notifier: &Notifier,
+    client: &Client<HttpsConnector<HttpConnector>>,
+    core: &mut Core,
+    job: &Job,
+    group: &GroupDelivery,
+) -> Result<(), String> {
+    let mut alarms: Vec<AlarmDetails> = Vec::new();
+    for notification in &group.notifications {
+        alarms.push(notification.parse().map_err(|err| format!("invalid notification: {}", err))?);
+    }
+    let message = GroupMessage {
+        id: &group.id,
+        key: &group.key,
+        text: job.text.as_ref().map_or("", String::as_str),
+        alarms: &alarms,
+        changed: &group.changed,
+        update: group.update,
+    };
+    notifier.notify_group(client, core, &message).map_err(|err| format!("{:?}", err))
+}
+
+fn deliver(
+    config: &Config,
+    client: &Client<HttpsConnector<HttpConnector>>,
+    core: &mut Core,
+    job: &Job,
+) -> Result<(), String> {
+    let notifier = config
+        .destinations
+        .get(&job.destination)
+        .ok_or_else(|| format!("unknown destination `{}`", job.destination))?;
+    if let Some(ref group) = job.group {
+        return deliver_group(&**notifier, client, core, job, group);
+    }
+    let alarm: AlarmDetails = job.notification
+        .parse()
+        .map_err(|err| format!("invalid notification: {}", err))?;
+    let decision = RoutingDecision::from_json(&job.routing);
+    let mentions: Vec<Mention> = job.mentions.iter().filter_map(|mention| mention.parse().ok()).collect();
+
+    let text = job.text.as_ref().map(String::as_str);
+    notifier
+        .notify_routed(client, core, &alarm, &decision, text, &mentions)
+        .map_err(|err| format!("{:?}", err))
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: Vec<Job>,
+    dead: Vec<Job>,
+    // Destinations held back after a failure, until the given unix time.
+    blocked: HashMap<String, u64>,
+}
+
+// Deliveries waiting to be sent, retried with an exponential backoff until
+// `max_attempts`, then kept as dead letters until replayed.
+#[derive(Debug)]
+pub(crate) struct Queue {
+    max_attempts: u32,
+    initial_backoff_secs: u64,
+    max_backoff_secs: u64,
+    store: Option<Store>,
+    state: Mutex<State>,
+    wakeup: Condvar,
+}
+
+impl Queue {
+    pub fn open(config: &QueueConfig) -> io::Result<Queue> {
+        let mut state = State::default();
+        let store = match config.directory {
+            Some(ref directory) => {
+                let store = Store::open(directory)?;
+                state.pending = store.load_pending()?;
+                state.dead = store.load_dead()?;
+                Some(store)
+            }
+            None => None,
+        };
+
+        Ok(Queue {
+            max_attempts: config.max_attempts,
+            initial_backoff_secs: config.initial_backoff_secs,
+            max_backoff_secs: config.max_backoff_secs,
+            store: store,
+            state: Mutex::new(state),
+            wakeup: Condvar::new(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().expect("Queue lock poisoned")
+    }
+
+    // Returns once the jobs are on disk, the notification can then be
+    // acknowledged. Either every job is queued or none is, a redelivery of
+    // the notification then doesn't duplicate the ones which were.
+    pub fn enqueue(&self, jobs: Vec<Job>) -> io::Result<()> {
+        if let Some(ref store) = self.store {
+            for (saved, job) in jobs.iter().enumerate() {
+                if let Err(err) = store.save_pending(job) {
+                    for job in &jobs[..saved] {
+                        let _ = store.remove_pending(&job.id);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        self.lock().pending.extend(jobs);
+        self.wakeup.notify_all();
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn pending(&self) -> Vec<Job> {
+        self.lock().pending.clone()
+    }
+
+    pub fn dead_letters(&self) -> Vec<Job> {
+        self.lock().dead.clone()
+    }
+
+    // Queues a dead letter again with its attempts reset, `false` when there
+    // is none with this ID.
+    pub fn replay(&self, id: &str) -> io::Result<bool> {
+        let mut state = self.lock();
+        let position = match state.dead.iter().position(|job| job.id == id) {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+
+        let mut job = state.dead[position].clone();
+        job.attempts = 0;
+        job.next_attempt = 0;
+        if let Some(ref store) = self.store {
+            store.revive(&job)?;
+        }
+        state.dead.remove(position);
+        state.blocked.remove(&job.destination);
+        let position = state.pending.iter().position(|pending| pending.id > job.id).unwrap_or(state.pending.len());
+        state.pending.insert(position, job);
+        self.wakeup.notify_all();
+        Ok(true)
+    }
+
+    // Exponential, with half of the delay random so that the retries of
+    // deliveries which failed together spread out.
+    fn backoff_secs(&self, attempts: u32) -> u64 {
+        let factor = 1u64.checked_shl(attempts.saturating_sub(1)).unwrap_or(u64::max_value());
+        let delay = self.initial_backoff_secs.saturating_mul(factor).min(self.max_backoff_secs);
+        let half = delay / 2;
+        delay - half + rand::thread_rng().gen_range(0, half + 1)
+    }
+
+    fn is_blocked(state: &State, destination: &str, now: u64) -> bool {
+        state.blocked.get(destination).map_or(false, |&until| until > now)
+    }
+
+    // The destinations with jobs waiting, each has its own worker.
+    fn destinations(&self) -> HashSet<String> {
+        self.lock().pending.iter().map(|job| job.destination.clone()).collect()
+    }
+
+    fn due(&self, destination: &str, now: u64) -> Vec<Job> {
+        let state = self.lock();
+        if Queue::is_blocked(&state, destination, now) {
+            return Vec::new();
+        }
+        state
+            .pending
+            .iter()
+            .filter(|job| job.destination == destination && job.next_attempt <= now)
+            .cloned()
+            .collect()
+    }
+
+    fn delivered(&self, job: &Job) -> io::Result<()> {
+        let mut state = self.lock();
+        state.pending.retain(|pending| pending.id != job.id);
+        state.blocked.remove(&job.destination);
+        match self.store {
+            Some(ref store) => store.remove_pending(&job.id),
+            None => Ok(()),
+        }
+    }
+
+    // Every job of the destination waits for the backoff, not only the one
+    // which failed. A retried job keeps its place, deliveries to a
+    // destination stay in the order they were queued.
+    fn failed(&self, job: &Job, error: String, now: u64) -> io::Result<()> {
+        let mut state = self.lock();
+        let position = match state.pending.iter().position(|pending| pending.id == job.id) {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+
+        let mut job = job.clone();
+        job.attempts += 1;
+        job.last_error = Some(error);
+        if job.attempts >= self.max_attempts {
+            if let Some(ref store) = self.store {
+                store.bury(&job)?;
+            }
+            state.pending.remove(position);
+            state.dead.push(job);
+        } else {
+            job.next_attempt = now + self.backoff_secs(job.attempts);
+            state.blocked.insert(job.destination.clone(), job.next_attempt);
+            if let Some(ref store) = self.store {
+                store.save_pending(&job)?;
+            }
+            state.pending[position] = job;
+        }
+        Ok(())
+    }
+
+    // Attempts every job of the destination due at `now` once.
+    pub fn run_once(
+        &self,
+        config: &Config,
+        client: &Client<HttpsConnector<HttpConnector>>,
+        core: &mut Core,
+        destination: &str,
+        now: u64,
+    ) {
+        for job in self.due(destination, now) {
+            if Queue::is_blocked(&self.lock(), &job.destination, now) {
+                continue;
+            }
+            let result = match deliver(config, client, core, &job) {
+                Ok(()) => self.delivered(&job),
+                Err(err) => self.failed(&job, err, now),
+            };
+            if let Err(err) = result {
+                let _ = writeln!(io::stderr(), "cannot update queued delivery {}: {}", job.id, err);
+            }
+        }
+    }
+
+    fn wait(&self, timeout: Duration) {
+        let state = self.lock();
+        let _ = self.wakeup.wait_timeout(state, timeout);
+    }
+}
+
+// Delivers the jobs of one destination, with the configuration current at
+// each attempt.
+fn work(queue: Arc<Queue>, config: Arc<SharedConfig>, destination: String) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut core = Core::new().expect("Failed to init Tokio event loop");
+        let handle = core.handle();
+        let client = Client::configure()
+            .connector(
+                HttpsConnector::new(config.current().server.https_threads, &handle)
+                    .expect("Failed to create HTTPS connector"),
+            )
+            .build(&handle);
+
+        loop {
+            queue.run_once(&config.current(), &client, &mut core, &destination, now().as_secs());
+            queue.wait(Duration::from_secs(POLL_INTERVAL_SECS));
+        }
+    })
+}
+
+// Delivers the queued jobs in the background, a worker per destination so
+// that a slow or unreachable one doesn't hold back the others.
+pub(crate) fn start(queue: Arc<Queue>, config: Arc<SharedConfig>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut workers = HashSet::new();
+        loop {
+            for destination in queue.destinations() {
+                if !workers.contains(&destination) {
+                    work(queue.clone(), config.clone(), destination.clone());
+                    workers.insert(destination);
+                }
+            }
+            queue.wait(Duration::from_secs(POLL_INTERVAL_SECS));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::sns_notification;
+    use notifiers::NotifierError;
+    use notifiers::mock_server::{core, https_client};
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::atomic::AtomicBool;
+
+    #[derive(Clone, Default)]
+    struct Flaky {
+        up: Arc<AtomicBool>,
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl Notifier for Flaky {
+        fn notify(
+            &self,
+            _client: &Client<HttpsConnector<HttpConnector>>,
+            _core: &mut Core,
+            _alarm: &AlarmDetails,
+        ) -> Result<(), NotifierError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            if self.up.load(Ordering::SeqCst) {
+                Ok(())
+            } else {
+                Err(NotifierError::Disconnected)
+            }
+        }
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("cloudwatch_bot_test_queue_{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn queue(directory: &PathBuf) -> Queue {
+        Queue::open(&QueueConfig {
+            directory: Some(directory.clone()),
+            max_attempts: 3,
+            initial_backoff_secs: 10,
+            max_backoff_secs: 100,
+        }).unwrap()
+    }
+
+    fn setup(flaky: &Flaky) -> (Config, Vec<Job>) {
+        let mut config = Config::default();
+        config.destinations.insert("ops".to_string(), Box::new(flaky.clone()));
+        config.router.default_destinations = vec!["ops".to_string(), "gone".to_string()];
+
+        let notification = sns_notification("m1", "ALARM", "OK");
+        let alarm: AlarmDetails = notification.parse().unwrap();
+        let decision = config.router.route(&alarm);
+        let jobs = Job::all(&config, &decision, &alarm, &notification);
+        (config, jobs)
+    }
+
+    #[test]
+    fn test_backoff() {
+        let queue = queue(&directory("backoff"));
+        for _ in 0..20 {
+            let first = queue.backoff_secs(1);
+            assert_eq!(first >= 5 && first <= 10, true);
+            let third = queue.backoff_secs(3);
+            assert_eq!(third >= 20 && third <= 40, true);
+            let capped = queue.backoff_secs(70);
+            assert_eq!(capped >= 50 && capped <= 100, true);
+        }
+    }
+
+    #[test]
+    fn test_jobs_survive_restarts() {
+        let directory = directory("restart");
+        let flaky = Flaky::default();
+        flaky.up.store(true, Ordering::SeqCst);
+        let (config, jobs) = setup(&flaky);
+        let mut core = core();
+        let client = https_client(&core);
+
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].id < jobs[1].id, true);
+        queue(&directory).enqueue(jobs).unwrap();
+
+        let queue = queue(&directory);
+        assert_eq!(queue.pending().len(), 2);
+        queue.run_once(&config, &client, &mut core, "ops", 0);
+        queue.run_once(&config, &client, &mut core, "gone", 0);
+
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+        let pending = queue.pending();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].destination, "gone");
+        assert_eq!(pending[0].last_error, Some("unknown destination `gone`".to_string()));
+        assert_eq!(fs::read_dir(directory.join("pending")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_retries_then_dead_letters() {
+        let directory = directory("dead_letters");
+        let flaky = Flaky::default();
+        let (config, mut jobs) = setup(&flaky);
+        jobs.truncate(1);
+        let queue = queue(&directory);
+        let mut core = core();
+        let client = https_client(&core);
+        queue.enqueue(jobs).unwrap();
+
+        queue.run_once(&config, &client, &mut core, "ops", 1000);
+        let next_attempt = queue.pending()[0].next_attempt;
+        assert_eq!(next_attempt >= 1005 && next_attempt <= 1010, true);
+        queue.run_once(&config, &client, &mut core, "ops", 1004);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+
+        queue.run_once(&config, &client, &mut core, "ops", 2000);
+        queue.run_once(&config, &client, &mut core, "ops", 3000);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(queue.pending().is_empty(), true);
+        let dead = queue.dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].attempts, 3);
+        assert_eq!(dead[0].last_error, Some("Disconnected".to_string()));
+
+        // Dead letters are kept across restarts, and can be replayed.
+        let queue = self::queue(&directory);
+        assert_eq!(queue.dead_letters().len(), 1);
+        assert_eq!(queue.replay("nope").unwrap(), false);
+        assert_eq!(queue.replay(&dead[0].id).unwrap(), true);
+        flaky.up.store(true, Ordering::SeqCst);
+        queue.run_once(&config, &client, &mut core, "ops", 3001);
+
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(queue.pending().is_empty(), true);
+        assert_eq!(queue.dead_letters().is_empty(), true);
+        assert_eq!(fs::read_dir(directory.join("dead")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_enqueue_all_or_nothing() {
+        let directory = directory("all_or_nothing");
+        let (_, jobs) = setup(&Flaky::default());
+        let queue = queue(&directory);
+        // The second job cannot be renamed to its final name.
+        fs::create_dir(directory.join("pending").join(format!("{}.json", jobs[1].id))).unwrap();
+
+        assert_eq!(queue.enqueue(jobs.clone()).is_err(), true);
+        assert_eq!(queue.pending().is_empty(), true);
+        assert_eq!(fs::metadata(directory.join("pending").join(format!("{}.json", jobs[0].id))).is_err(), true);
+    }
+
+    #[test]
+    fn test_retried_job_keeps_its_place() {
+        let flaky = Flaky::default();
+        let (config, mut jobs) = setup(&flaky);
+        jobs.truncate(1);
+        let notification = sns_notification("m2", "OK", "ALARM");
+        jobs.push(Job::new("ops", &notification, Value::Null, None, Vec::new()));
+        let queue = Queue::open(&QueueConfig::default()).unwrap();
+        let mut core = core();
+        let client = https_client(&core);
+        queue.enqueue(jobs.clone()).unwrap();
+
+        queue.run_once(&config, &client, &mut core, "ops", 1000);
+        let pending = queue.pending();
+        assert_eq!(pending.iter().map(|job| job.attempts).collect::<Vec<_>>(), vec![1, 0]);
+        assert_eq!(pending[0].id, jobs[0].id);
+    }
+
+    #[test]
+    fn test_memory_only() {
+        let flaky = Flaky::default();
+        flaky.up.store(true, Ordering::SeqCst);
+        let (config, jobs) = setup(&flaky);
+        let queue = Queue::open(&QueueConfig::default()).unwrap();
+        let mut core = core();
+        let client = https_client(&core);
+
+        queue.enqueue(jobs).unwrap();
+        queue.run_once(&config, &client, &mut core, "ops", 0);
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(queue.pending().len(), 1);
+    }
+}
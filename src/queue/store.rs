@@ -0,0 +1,99 @@
+use serde_json;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::Job;
+
+const PENDING: &str = "pending";
+const DEAD: &str = "dead";
+
+// One JSON file per job, under `pending/` until it is delivered or under
+// `dead/` once it ran out of attempts. Files are written next to their final
+// name then renamed, a crash never leaves a truncated job behind.
+#[derive(Debug)]
+pub(crate) struct Store {
+    directory: PathBuf,
+}
+
+impl Store {
+    pub fn open(directory: &Path) -> io::Result<Store> {
+        fs::create_dir_all(directory.join(PENDING))?;
+        fs::create_dir_all(directory.join(DEAD))?;
+        Ok(Store { directory: directory.to_path_buf() })
+    }
+
+    fn path(&self, folder: &str, id: &str) -> PathBuf {
+        self.directory.join(folder).join(format!("{}.json", id))
+    }
+
+    fn write(&self, folder: &str, job: &Job) -> io::Result<()> {
+        let path = self.path(folder, &job.id);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec(job).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&json)?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp, &path)
+    }
+
+    fn remove(&self, folder: &str, id: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(folder, id)) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    // Jobs by ID, that is in the order they were queued. Unreadable files are
+    // reported and left alone.
+    fn load(&self, folder: &str) -> io::Result<Vec<Job>> {
+        let mut jobs = Vec::new();
+        for entry in fs::read_dir(self.directory.join(folder))? {
+            let path = entry?.path();
+            if path.extension().map_or(true, |extension| extension != "json") {
+                continue;
+            }
+            let job = File::open(&path).and_then(|file| {
+                serde_json::from_reader(file).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+            });
+            match job {
+                Ok(job) => jobs.push(job),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "{}: skipping queued delivery: {}", path.display(), err);
+                }
+            }
+        }
+        jobs.sort_by(|a: &Job, b: &Job| a.id.cmp(&b.id));
+        Ok(jobs)
+    }
+
+    pub fn save_pending(&self, job: &Job) -> io::Result<()> {
+        self.write(PENDING, job)
+    }
+
+    pub fn remove_pending(&self, id: &str) -> io::Result<()> {
+        self.remove(PENDING, id)
+    }
+
+    pub fn load_pending(&self) -> io::Result<Vec<Job>> {
+        self.load(PENDING)
+    }
+
+    // The dead letter is written before the pending job goes away, a crash in
+    // between means a duplicate rather than a lost delivery.
+    pub fn bury(&self, job: &Job) -> io::Result<()> {
+        self.write(DEAD, job)?;
+        self.remove(PENDING, &job.id)
+    }
+
+    pub fn revive(&self, job: &Job) -> io::Result<()> {
+        self.write(PENDING, job)?;
+        self.remove(DEAD, &job.id)
+    }
+
+    pub fn load_dead(&self) -> io::Result<Vec<Job>> {
+        self.load(DEAD)
+    }
+}
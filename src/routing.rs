@@ -0,0 +1,856 @@
+use chrono::{DateTime, Utc};
+use glob;
+use regex::{self, Regex};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use model::{AlarmDetails, AlarmState, EnumResult, ParseEnumError};
+use config::Config;
+use notifiers::Notifier;
+use oncall::{Mention, MentionTarget, Rotation};
+
+pub(crate) type Destinations = HashMap<String, Box<Notifier + Send + Sync>>;
+
+#[derive(Debug)]
+pub(crate) enum NameMatcher {
+    Glob(glob::Pattern),
+    Regex(Regex),
+}
+
+impl NameMatcher {
+    pub fn glob(pattern: &str) -> Result<NameMatcher, glob::PatternError> {
+        glob::Pattern::new(pattern).map(NameMatcher::Glob)
+    }
+
+    pub fn regex(pattern: &str) -> Result<NameMatcher, regex::Error> {
+        Regex::new(pattern).map(NameMatcher::Regex)
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match *self {
+            NameMatcher::Glob(ref pattern) => pattern.matches(name),
+            NameMatcher::Regex(ref regex) => regex.is_match(name),
+        }
+    }
+}
+
+// Every criterion must match, an empty list matches anything. A `None` value
+// in `dimensions` or `message_attributes` only requires the key.
+#[derive(Debug, Default)]
+pub(crate) struct RuleMatch {
+    pub topic_arns: Vec<String>,
+    pub accounts: Vec<String>,
+    // Region codes ("us-east-1") or names as sent by SNS ("US - N. Virginia").
+    pub regions: Vec<String>,
+    pub alarm_name: Option<NameMatcher>,
+    pub namespaces: Vec<String>,
+    pub metric_names: Vec<String>,
+    pub dimensions: Vec<(String, Option<String>)>,
+    pub new_states: Vec<AlarmState>,
+    pub previous_states: Vec<AlarmState>,
+    pub message_attributes: Vec<(String, Option<String>)>,
+}
+
+fn any_of<T: PartialEq>(allowed: &[T], value: &T) -> bool {
+    allowed.is_empty() || allowed.contains(value)
+}
+
+fn has_pair<'a, I>(pairs: I, key: &str, value: &Option<String>) -> bool
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    pairs
+        .into_iter()
+        .any(|(k, v)| k == key && value.as_ref().map_or(true, |value| v == value))
+}
+
+impl RuleMatch {
+    pub fn matches(&self, alarm: &AlarmDetails) -> bool {
+        let trigger = &alarm.trigger;
+        let envelope = &alarm.envelope;
+        let region_matches = self.regions.is_empty() ||
+            self.regions.iter().any(|region| {
+                *region == alarm.region || alarm.region_code().map_or(false, |code| region == code)
+            });
+
+        any_of(&self.topic_arns, &envelope.topic_arn) && any_of(&self.accounts, &alarm.account_id) &&
+            region_matches &&
+            self.alarm_name.as_ref().map_or(true, |m| m.matches(&alarm.name)) &&
+            any_of(&self.namespaces, &trigger.namespace) &&
+            any_of(&self.metric_names, &trigger.metric_name) &&
+            self.dimensions.iter().all(|&(ref key, ref value)| {
+                has_pair(trigger.dimensions.iter().map(|d| (d.name.as_str(), d.value.as_str())), key, value)
+            }) && any_of(&self.new_states, &alarm.new_state) &&
+            any_of(&self.previous_states, &alarm.previous_state) &&
+            self.message_attributes.iter().all(|&(ref key, ref value)| {
+                has_pair(
+                    envelope.message_attributes.iter().map(|(k, a)| (k.as_str(), a.value.as_str())),
+                    key,
+                    value,
+                )
+            })
+    }
+}
+
+// What happens once a rule matched.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum Flow {
+    Continue,
+    Stop,
+}
+
+lazy_static! {
+    static ref FLOWS: HashMap<&'static str, Flow> = {
+        let mut map = HashMap::new();
+        map.insert("continue", Flow::Continue);
+        map.insert("stop", Flow::Stop);
+        map
+    };
+}
+
+impl FromStr for Flow {
+    type Err = ParseEnumError<Self>;
+
+    fn from_str(flow: &str) -> EnumResult<Flow> {
+        FLOWS.get(flow).cloned().ok_or(ParseEnumError {
+            value: flow.to_string(),
+            mapping: FLOWS.clone(),
+        })
+    }
+}
+
+// An alarm flaps once it changed state `transitions` times within
+// `window_secs`, and settles after `window_secs` without any change.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FlappingThreshold {
+    pub transitions: usize,
+    pub window_secs: u64,
+}
+
+// What the alarms of a group have in common.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum GroupKey {
+    Namespace,
+    Account,
+    Dimension(String),
+    // The tag the alarm name starts with, "[RTB-US]".
+    NamePrefix,
+}
+
+impl GroupKey {
+    // `None` when the alarm has nothing to be grouped on.
+    pub fn value(&self, alarm: &AlarmDetails) -> Option<String> {
+        let value = match *self {
+            GroupKey::Namespace => alarm.trigger.namespace.clone(),
+            GroupKey::Account => alarm.account_id.clone(),
+            GroupKey::Dimension(ref name) => match alarm.trigger.dimensions.iter().find(|d| d.name == *name) {
+                Some(dimension) => format!("{}={}", dimension.name, dimension.value),
+                None => return None,
+            },
+            GroupKey::NamePrefix => {
+                if !alarm.name.starts_with('[') {
+                    return None;
+                }
+                match alarm.name.find(']') {
+                    Some(end) => alarm.name[..end + 1].to_string(),
+                    None => return None,
+                }
+            }
+        };
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+}
+
+impl FromStr for GroupKey {
+    type Err = String;
+
+    fn from_str(key: &str) -> Result<GroupKey, String> {
+        match key {
+            "namespace" => Ok(GroupKey::Namespace),
+            "account" => Ok(GroupKey::Account),
+            "name_prefix" => Ok(GroupKey::NamePrefix),
+            _ if key.starts_with("dimension:") && key.len() > "dimension:".len() => {
+                Ok(GroupKey::Dimension(key["dimension:".len()..].to_string()))
+            }
+            _ => Err(format!(
+                "invalid group key `{}`, expected `namespace`, `account`, `name_prefix` or `dimension:<name>`",
+                key
+            )),
+        }
+    }
+}
+
+impl Display for GroupKey {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GroupKey::Namespace => write!(fmt, "namespace"),
+            GroupKey::Account => write!(fmt, "account"),
+            GroupKey::Dimension(ref name) => write!(fmt, "dimension:{}", name),
+            GroupKey::NamePrefix => write!(fmt, "name_prefix"),
+        }
+    }
+}
+
+// The alarms sharing a key are collected for `window_secs` then sent as one
+// message, which the later members edit until the group stays quiet for as
+// long.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Grouping {
+    pub key: GroupKey,
+    pub window_secs: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Rule {
+    pub name: String,
+    pub matcher: RuleMatch,
+    pub destinations: Vec<String>,
+    // Template set used for the destinations of this rule.
+    pub template: Option<String>,
+    // Who to mention in the messages sent to the destinations of this rule,
+    // by new state. OK transitions never mention anyone.
+    pub mentions: Vec<(AlarmState, Vec<MentionTarget>)>,
+    // Who the `email` destinations of this rule mail, instead of their own
+    // recipients.
+    pub recipients: Vec<String>,
+    pub flapping: Option<FlappingThreshold>,
+    pub grouping: Option<Grouping>,
+    pub flow: Flow,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct RoutingDecision {
+    pub matched_rules: Vec<String>,
+    // Without any matching rule, the alarm goes to the default route.
+    pub default_route: bool,
+    pub destinations: Vec<String>,
+    // Template set of each destination which has one.
+    pub templates: BTreeMap<String, String>,
+    // Mentions of each destination which has some, rotations resolved.
+    pub mentions: BTreeMap<String, Vec<Mention>>,
+    // Mail recipients of each destination whose rules have some.
+    pub recipients: BTreeMap<String, Vec<String>>,
+    // Threshold of the first matching rule which has one.
+    pub flapping: Option<FlappingThreshold>,
+    // Grouping of the first matching rule which has one, with its name.
+    pub grouping: Option<(String, Grouping)>,
+}
+
+impl RoutingDecision {
+    // Reads back the output of `to_json`, for deliveries which were queued.
+    pub fn from_json(value: &Value) -> RoutingDecision {
+        let strings = |value: &Value| -> Vec<String> {
+            value
+                .as_array()
+                .map(|values| values.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+                .unwrap_or_default()
+        };
+        let templates = value["templates"]
+            .as_object()
+            .map(|templates| {
+                templates
+                    .iter()
+                    .filter_map(|(destination, template)| template.as_str().map(|t| (destination.clone(), t.to_string())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mentions = value["mentions"]
+            .as_object()
+            .map(|mentions| {
+                mentions
+                    .iter()
+                    .map(|(destination, mentions)| {
+                        (destination.clone(), strings(mentions).iter().filter_map(|m| m.parse().ok()).collect())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let recipients = value["recipients"]
+            .as_object()
+            .map(|recipients| {
+                recipients
+                    .iter()
+                    .map(|(destination, recipients)| (destination.clone(), strings(recipients)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let flapping = &value["flapping"];
+        let flapping = match (flapping["transitions"].as_u64(), flapping["window_secs"].as_u64()) {
+            (Some(transitions), Some(window_secs)) => Some(FlappingThreshold {
+                transitions: transitions as usize,
+                window_secs: window_secs,
+            }),
+            _ => None,
+        };
+        let grouping = &value["grouping"];
+        let by = grouping["by"].as_str().and_then(|by| by.parse().ok());
+        let grouping = match (grouping["rule"].as_str(), by, grouping["window_secs"].as_u64()) {
+            (Some(rule), Some(key), Some(window_secs)) => Some((
+                rule.to_string(),
+                Grouping {
+                    key: key,
+                    window_secs: window_secs,
+                },
+            )),
+            _ => None,
+        };
+
+        RoutingDecision {
+            matched_rules: strings(&value["matched_rules"]),
+            default_route: value["default_route"].as_bool().unwrap_or(false),
+            destinations: strings(&value["destinations"]),
+            templates: templates,
+            mentions: mentions,
+            recipients: recipients,
+            flapping: flapping,
+            grouping: grouping,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mentions: BTreeMap<&String, Vec<String>> = self.mentions
+            .iter()
+            .map(|(destination, mentions)| (destination, mentions.iter().map(Mention::to_string).collect()))
+            .collect();
+        let flapping = self.flapping.as_ref().map(|threshold| {
+            json!({
+                "transitions": threshold.transitions,
+                "window_secs": threshold.window_secs,
+            })
+        });
+        let grouping = self.grouping.as_ref().map(|&(ref rule, ref grouping)| {
+            json!({
+                "rule": rule,
+                "by": grouping.key.to_string(),
+                "window_secs": grouping.window_secs,
+            })
+        });
+        json!({
+            "matched_rules": self.matched_rules,
+            "default_route": self.default_route,
+            "destinations": self.destinations,
+            "templates": self.templates,
+            "mentions": mentions,
+            "recipients": self.recipients,
+            "flapping": flapping,
+            "grouping": grouping,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Router {
+    pub rules: Vec<Rule>,
+    pub default_destinations: Vec<String>,
+    // Template set of a destination when the matching rule has none.
+    pub destination_templates: HashMap<String, String>,
+    pub rotations: HashMap<String, Rotation>,
+}
+
+impl Router {
+    pub fn route(&self, alarm: &AlarmDetails) -> RoutingDecision {
+        self.route_at(alarm, Utc::now())
+    }
+
+    fn resolve(&self, target: &MentionTarget, now: DateTime<Utc>) -> Option<Mention> {
+        match *target {
+            MentionTarget::Mention(ref mention) => Some(mention.clone()),
+            MentionTarget::Rotation(ref name) => self.rotations
+                .get(name)
+                .and_then(|rotation| rotation.on_call(now))
+                .map(|member| Mention::User(member.to_string())),
+        }
+    }
+
+    // Rules are evaluated in order, until the end or the first matching rule
+    // whose flow is `Stop`. A destination is only notified once, with the
+    // mentions and recipients of every matching rule it belongs to.
+    pub fn route_at(&self, alarm: &AlarmDetails, now: DateTime<Utc>) -> RoutingDecision {
+        let mut matched_rules = Vec::new();
+        let mut destinations: Vec<String> = Vec::new();
+        let mut templates = BTreeMap::new();
+        let mut mentions: BTreeMap<String, Vec<Mention>> = BTreeMap::new();
+        let mut recipients: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut flapping = None;
+        let mut grouping = None;
+
+        for rule in self.rules.iter().filter(|rule| rule.matcher.matches(alarm)) {
+            matched_rules.push(rule.name.clone());
+            if flapping.is_none() {
+                flapping = rule.flapping.clone();
+            }
+            if grouping.is_none() {
+                grouping = rule.grouping.clone().map(|g| (rule.name.clone(), g));
+            }
+            let rule_mentions: Vec<Mention> = rule.mentions
+                .iter()
+                .filter(|&&(ref state, _)| *state == alarm.new_state && *state != AlarmState::Ok)
+                .flat_map(|&(_, ref targets)| targets.iter().filter_map(|target| self.resolve(target, now)))
+                .collect();
+
+            for destination in &rule.destinations {
+                if !destinations.contains(destination) {
+                    destinations.push(destination.clone());
+                    if let Some(ref template) = rule.template {
+                        templates.insert(destination.clone(), template.clone());
+                    }
+                }
+                for mention in &rule_mentions {
+                    let destination_mentions = mentions.entry(destination.clone()).or_insert_with(Vec::new);
+                    if !destination_mentions.contains(mention) {
+                        destination_mentions.push(mention.clone());
+                    }
+                }
+                for recipient in &rule.recipients {
+                    let destination_recipients = recipients.entry(destination.clone()).or_insert_with(Vec::new);
+                    if !destination_recipients.contains(recipient) {
+                        destination_recipients.push(recipient.clone());
+                    }
+                }
+            }
+            if rule.flow == Flow::Stop {
+                break;
+            }
+        }
+
+        let default_route = matched_rules.is_empty();
+        if default_route {
+            destinations = self.default_destinations.clone();
+        }
+        for destination in &destinations {
+            if let Some(template) = self.destination_templates.get(destination) {
+                templates.entry(destination.clone()).or_insert_with(|| template.clone());
+            }
+        }
+
+        RoutingDecision {
+            matched_rules: matched_rules,
+            default_route: default_route,
+            destinations: destinations,
+            templates: templates,
+            mentions: mentions,
+            recipients: recipients,
+            flapping: flapping,
+            grouping: grouping,
+        }
+    }
+}
+
+// What a destination is sent: the text rendered from its template, if it has
+// one, and who to mention.
+pub(crate) fn render(
+    config: &Config,
+    decision: &RoutingDecision,
+    alarm: &AlarmDetails,
+    destination: &str,
+) -> (Option<String>, Vec<Mention>) {
+    let text = decision
+        .templates
+        .get(destination)
+        .and_then(|template| config.templates.get(template))
+        .map(|template| template.render(alarm, &config.logs_insights));
+    let mentions = decision.mentions.get(destination).cloned().unwrap_or_default();
+    (text, mentions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+    use chrono::Duration;
+    use hyper::Client;
+    use hyper::client::HttpConnector;
+    use hyper_tls::HttpsConnector;
+    use notifiers::NotifierError;
+    use notifiers::mock_server::{core, https_client};
+    use serde_json;
+    use std::sync::{Arc, Mutex};
+    use templates::{Template, TemplateSet};
+    use tokio_core::reactor::Core;
+
+    fn rule(name: &str, matcher: RuleMatch, destinations: &[&str], flow: Flow) -> Rule {
+        Rule {
+            name: name.to_string(),
+            matcher: matcher,
+            destinations: destinations.iter().map(|d| d.to_string()).collect(),
+            template: None,
+            mentions: vec![],
+            recipients: vec![],
+            flapping: None,
+            grouping: None,
+            flow: flow,
+        }
+    }
+
+    fn router() -> Router {
+        Router {
+            rules: vec![
+                rule(
+                    "dba",
+                    RuleMatch {
+                        namespaces: vec!["AWS/RDS".to_string()],
+                        ..RuleMatch::default()
+                    },
+                    &["dba-slack"],
+                    Flow::Stop,
+                ),
+                rule(
+                    "rtb",
+                    RuleMatch {
+                        alarm_name: Some(NameMatcher::glob("[[]RTB-US]*").unwrap()),
+                        ..RuleMatch::default()
+                    },
+                    &["rtb-slack", "rtb-pagerduty"],
+                    Flow::Continue,
+                ),
+                rule(
+                    "alarms",
+                    RuleMatch {
+                        new_states: vec![AlarmState::Alarm],
+                        ..RuleMatch::default()
+                    },
+                    &["rtb-pagerduty", "audit"],
+                    Flow::Continue,
+                ),
+            ],
+            default_destinations: vec!["ops".to_string()],
+            destination_templates: HashMap::new(),
+            rotations: HashMap::new(),
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Vec<String>>>>);
+
+    impl Notifier for Recorder {
+        fn notify(
+            &self,
+            _client: &Client<HttpsConnector<HttpConnector>>,
+            _core: &mut Core,
+            alarm: &AlarmDetails,
+        ) -> Result<(), NotifierError> {
+            self.0.lock().unwrap().push(vec![alarm.name.clone()]);
+            Ok(())
+        }
+
+        fn notify_text(
+            &self,
+            _client: &Client<HttpsConnector<HttpConnector>>,
+            _core: &mut Core,
+            _alarm: &AlarmDetails,
+            text: &str,
+        ) -> Result<(), NotifierError> {
+            self.0.lock().unwrap().push(vec![text.to_string()]);
+            Ok(())
+        }
+
+        fn notify_mentioning(
+            &self,
+            _client: &Client<HttpsConnector<HttpConnector>>,
+            _core: &mut Core,
+            _alarm: &AlarmDetails,
+            _text: Option<&str>,
+            mentions: &[Mention],
+        ) -> Result<(), NotifierError> {
+            self.0.lock().unwrap().push(mentions.iter().map(Mention::to_string).collect());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flow_from_string() {
+        let stop: Flow = "stop".parse().expect("Should not happen");
+        let no_match: EnumResult<Flow> = "break".parse();
+
+        assert_eq!(stop, Flow::Stop);
+        assert_eq!(no_match.is_ok(), false);
+    }
+
+    #[test]
+    fn test_name_matcher() {
+        assert_eq!(NameMatcher::glob("*UnHealthy*").unwrap().matches("[RTB-US] UnHealthyHostCount"), true);
+        assert_eq!(NameMatcher::regex("^\\[RTB-(US|EU)\\]").unwrap().matches("[RTB-EU] Latency"), true);
+        assert_eq!(NameMatcher::regex("^\\[RTB-(US|EU)\\]").unwrap().matches("[DSP-US] Latency"), false);
+        assert_eq!(NameMatcher::regex("(").is_err(), true);
+    }
+
+    #[test]
+    fn test_rule_match_criteria() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.envelope.topic_arn = "arn:aws:sns:us-east-1:097958131044:jabber-all".to_string();
+        alarm.envelope.message_attributes.insert(
+            "team".to_string(),
+            serde_json::from_value(json!({"Type": "String", "Value": "rtb"})).unwrap(),
+        );
+
+        let matcher = RuleMatch {
+            topic_arns: vec!["arn:aws:sns:us-east-1:097958131044:jabber-all".to_string()],
+            accounts: vec!["097958131044".to_string()],
+            regions: vec!["us-east-1".to_string()],
+            metric_names: vec!["UnHealthyHostCount".to_string()],
+            dimensions: vec![("LoadBalancerName".to_string(), Some("rtb".to_string()))],
+            previous_states: vec![AlarmState::Ok],
+            message_attributes: vec![("team".to_string(), None)],
+            ..RuleMatch::default()
+        };
+        assert_eq!(matcher.matches(&alarm), true);
+        assert_eq!(RuleMatch::default().matches(&alarm), true);
+
+        let by_region_name = RuleMatch {
+            regions: vec!["US - N. Virginia".to_string()],
+            ..RuleMatch::default()
+        };
+        assert_eq!(by_region_name.matches(&alarm), true);
+
+        let wrong_dimension = RuleMatch {
+            dimensions: vec![("LoadBalancerName".to_string(), Some("dsp".to_string()))],
+            ..RuleMatch::default()
+        };
+        assert_eq!(wrong_dimension.matches(&alarm), false);
+
+        let wrong_attribute = RuleMatch {
+            message_attributes: vec![("team".to_string(), Some("dba".to_string()))],
+            ..RuleMatch::default()
+        };
+        assert_eq!(wrong_attribute.matches(&alarm), false);
+    }
+
+    #[test]
+    fn test_route_continue() {
+        let decision = router().route(&alarm_details("ALARM", "OK"));
+
+        assert_eq!(decision.matched_rules, vec!["rtb", "alarms"]);
+        assert_eq!(decision.default_route, false);
+        assert_eq!(decision.destinations, vec!["rtb-slack", "rtb-pagerduty", "audit"]);
+    }
+
+    #[test]
+    fn test_route_stop() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.trigger.namespace = "AWS/RDS".to_string();
+        let decision = router().route(&alarm);
+
+        assert_eq!(decision.matched_rules, vec!["dba"]);
+        assert_eq!(decision.destinations, vec!["dba-slack"]);
+    }
+
+    #[test]
+    fn test_route_default() {
+        let mut alarm = alarm_details("OK", "ALARM");
+        alarm.name = "[DSP-EU] Latency".to_string();
+        let decision = router().route(&alarm);
+
+        assert_eq!(decision.matched_rules.is_empty(), true);
+        assert_eq!(decision.default_route, true);
+        assert_eq!(decision.destinations, vec!["ops"]);
+    }
+
+    // Delivers to the configured destinations of the decision, like the queue.
+    fn deliver_all(config: &Config, decision: &RoutingDecision, alarm: &AlarmDetails) {
+        let mut core = core();
+        let client = https_client(&core);
+        for name in &decision.destinations {
+            if let Some(notifier) = config.destinations.get(name) {
+                let (text, mentions) = render(config, decision, alarm, name);
+                notifier
+                    .notify_routed(&client, &mut core, alarm, decision, text.as_ref().map(String::as_str), &mentions)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_deliver() {
+        let recorder = Recorder::default();
+        let mut config = Config::default();
+        config.destinations.insert("rtb-slack".to_string(), Box::new(recorder.clone()));
+        let alarm = alarm_details("ALARM", "OK");
+        let decision = router().route(&alarm);
+
+        deliver_all(&config, &decision, &alarm);
+
+        assert_eq!(*recorder.0.lock().unwrap(), vec![vec!["[RTB-US] UnHealthyHostCount".to_string()]]);
+    }
+
+    #[test]
+    fn test_route_templates() {
+        let mut router = router();
+        router.rules[1].template = Some("short".to_string());
+        router.destination_templates.insert("audit".to_string(), "long".to_string());
+        router.destination_templates.insert("rtb-slack".to_string(), "long".to_string());
+
+        let decision = router.route(&alarm_details("ALARM", "OK"));
+        let templates: Vec<(&str, &str)> = decision.templates.iter().map(|(d, t)| (d.as_str(), t.as_str())).collect();
+        assert_eq!(templates, vec![("audit", "long"), ("rtb-pagerduty", "short"), ("rtb-slack", "short")]);
+    }
+
+    #[test]
+    fn test_route_flapping() {
+        let mut router = router();
+        let threshold = |transitions| FlappingThreshold {
+            transitions: transitions,
+            window_secs: 3600,
+        };
+        router.rules[1].flapping = Some(threshold(4));
+        router.rules[2].flapping = Some(threshold(6));
+
+        let decision = router.route(&alarm_details("ALARM", "OK"));
+        assert_eq!(decision.flapping, Some(threshold(4)));
+        assert_eq!(RoutingDecision::from_json(&decision.to_json()), decision);
+
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.name = "[DSP-EU] Latency".to_string();
+        assert_eq!(router.route(&alarm).flapping, Some(threshold(6)));
+    }
+
+    #[test]
+    fn test_group_key() {
+        let alarm = alarm_details("ALARM", "OK");
+        let value = |key: &str| key.parse::<GroupKey>().unwrap().value(&alarm);
+
+        assert_eq!(value("namespace"), Some("AWS/ELB".to_string()));
+        assert_eq!(value("account"), Some("097958131044".to_string()));
+        assert_eq!(value("dimension:LoadBalancerName"), Some("LoadBalancerName=rtb".to_string()));
+        assert_eq!(value("dimension:DBInstanceIdentifier"), None);
+        assert_eq!(value("name_prefix"), Some("[RTB-US]".to_string()));
+        assert_eq!("dimension:LoadBalancerName".parse::<GroupKey>().unwrap().to_string(), "dimension:LoadBalancerName");
+        assert_eq!("dimension:".parse::<GroupKey>().is_err(), true);
+
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.name = "UnHealthyHostCount [RTB-US]".to_string();
+        assert_eq!(GroupKey::NamePrefix.value(&alarm), None);
+    }
+
+    #[test]
+    fn test_route_grouping() {
+        let mut router = router();
+        let grouping = |window_secs| Grouping {
+            key: GroupKey::Namespace,
+            window_secs: window_secs,
+        };
+        router.rules[2].grouping = Some(grouping(30));
+
+        let decision = router.route(&alarm_details("ALARM", "OK"));
+        assert_eq!(decision.grouping, Some(("alarms".to_string(), grouping(30))));
+        assert_eq!(RoutingDecision::from_json(&decision.to_json()), decision);
+        assert_eq!(router.route(&alarm_details("OK", "ALARM")).grouping, None);
+    }
+
+    #[test]
+    fn test_deliver_with_template() {
+        let recorder = Recorder::default();
+        let mut config = Config::default();
+        config.destinations.insert("rtb-slack".to_string(), Box::new(recorder.clone()));
+        config.templates.insert(
+            "short".to_string(),
+            TemplateSet {
+                default: Template::compile("{{state_emoji new_state}} {{name}}").unwrap(),
+                transitions: vec![],
+            },
+        );
+        let mut router = router();
+        router.rules[1].template = Some("short".to_string());
+        let alarm = alarm_details("ALARM", "OK");
+        let decision = router.route(&alarm);
+
+        deliver_all(&config, &decision, &alarm);
+
+        assert_eq!(*recorder.0.lock().unwrap(), vec![vec!["🔴 [RTB-US] UnHealthyHostCount".to_string()]]);
+    }
+
+    fn router_with_mentions() -> Router {
+        let mut router = router();
+        router.rules[1].mentions = vec![
+            (
+                AlarmState::Alarm,
+                vec![
+                    MentionTarget::Mention(Mention::User("U1".to_string())),
+                    MentionTarget::Rotation("rtb".to_string()),
+                ],
+            ),
+        ];
+        router.rules[2].mentions = vec![
+            (
+                AlarmState::Alarm,
+                vec![MentionTarget::Mention(Mention::Here), MentionTarget::Mention(Mention::User("U1".to_string()))],
+            ),
+            (AlarmState::InsufficientData, vec![MentionTarget::Mention(Mention::Group("S1".to_string()))]),
+        ];
+        router.rotations.insert(
+            "rtb".to_string(),
+            Rotation {
+                members: vec!["alice".to_string(), "bob".to_string()],
+                start: "2024-01-01T09:00:00Z".parse().unwrap(),
+                shift: Duration::weeks(1),
+                overrides: vec![],
+            },
+        );
+        router
+    }
+
+    #[test]
+    fn test_route_mentions() {
+        let router = router_with_mentions();
+        let now = "2024-01-09T12:00:00Z".parse().unwrap();
+
+        let decision = router.route_at(&alarm_details("ALARM", "OK"), now);
+        let mentions: Vec<(&str, Vec<String>)> = decision
+            .mentions
+            .iter()
+            .map(|(d, m)| (d.as_str(), m.iter().map(Mention::to_string).collect()))
+            .collect();
+        assert_eq!(
+            mentions,
+            vec![
+                ("audit", vec!["here".to_string(), "user:U1".to_string()]),
+                ("rtb-pagerduty", vec!["user:U1".to_string(), "user:bob".to_string(), "here".to_string()]),
+                ("rtb-slack", vec!["user:U1".to_string(), "user:bob".to_string()]),
+            ]
+        );
+        assert_eq!(decision.to_json()["mentions"]["audit"], json!(["here", "user:U1"]));
+        assert_eq!(RoutingDecision::from_json(&decision.to_json()), decision);
+
+        // Before the rotation starts nobody is on call.
+        let decision = router.route_at(&alarm_details("ALARM", "OK"), "2023-12-01T00:00:00Z".parse().unwrap());
+        assert_eq!(decision.mentions["rtb-slack"], vec![Mention::User("U1".to_string())]);
+
+        assert_eq!(router.route_at(&alarm_details("OK", "ALARM"), now).mentions.is_empty(), true);
+    }
+
+    #[test]
+    fn test_route_recipients() {
+        let mut router = router();
+        router.rules[1].recipients = vec!["rtb@example.org".to_string()];
+        router.rules[2].recipients = vec!["oncall@example.org".to_string(), "rtb@example.org".to_string()];
+
+        let decision = router.route(&alarm_details("ALARM", "OK"));
+        assert_eq!(decision.recipients["rtb-slack"], vec!["rtb@example.org"]);
+        assert_eq!(decision.recipients["rtb-pagerduty"], vec!["rtb@example.org", "oncall@example.org"]);
+        assert_eq!(decision.recipients["audit"], vec!["oncall@example.org", "rtb@example.org"]);
+        assert_eq!(RoutingDecision::from_json(&decision.to_json()), decision);
+
+        assert_eq!(router.route(&alarm_details("OK", "ALARM")).recipients.contains_key("audit"), false);
+    }
+
+    #[test]
+    fn test_deliver_with_mentions() {
+        let recorder = Recorder::default();
+        let mut config = Config::default();
+        config.destinations.insert("rtb-slack".to_string(), Box::new(recorder.clone()));
+        let alarm = alarm_details("ALARM", "OK");
+        let decision = router_with_mentions().route_at(&alarm, "2024-01-02T00:00:00Z".parse().unwrap());
+
+        deliver_all(&config, &decision, &alarm);
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![vec!["user:U1".to_string(), "user:alice".to_string()]]
+        );
+    }
+}
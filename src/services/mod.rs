@@ -1 +1,3 @@
 mod confirm_subscription;
+
+pub(crate) use self::confirm_subscription::confirm_subscription;
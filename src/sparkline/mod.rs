@@ -0,0 +1,216 @@
+mod png;
+
+use regex::Regex;
+
+use model::AlarmDetails;
+
+pub(crate) const FILENAME: &str = "sparkline.png";
+
+const WIDTH: usize = 480;
+const HEIGHT: usize = 160;
+const PADDING: usize = 12;
+const MARKER_RADIUS: isize = 2;
+// Dash and gap lengths of the threshold line.
+const DASH: usize = 6;
+const GAP: usize = 4;
+
+const BACKGROUND: u8 = 0;
+const BREACH: u8 = 1;
+const THRESHOLD: u8 = 2;
+const SERIES: u8 = 3;
+const MARKER: u8 = 4;
+
+const PALETTE: [[u8; 3]; 5] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xFA, 0xDB, 0xD7],
+    [0xD1, 0x32, 0x12],
+    [0x00, 0x73, 0xBB],
+    [0x00, 0x3B, 0x6F],
+];
+
+lazy_static! {
+    // "3 datapoints [5.0 (27/02/16 11:20:00), 4.0 (27/02/16 11:19:00), ...]",
+    // also used by "1 out of the last 3 datapoints [...]".
+    static ref DATAPOINT_LIST: Regex = Regex::new(r"datapoints? \[([^\]]*)\]").unwrap();
+    static ref LISTED_VALUE: Regex = Regex::new(r"(-?[0-9.]+(?:[eE][-+]?[0-9]+)?) \(").unwrap();
+    // "1 datapoint (0.4482758620689655)"
+    static ref SINGLE_DATAPOINT: Regex = Regex::new(r"datapoint \((-?[0-9.]+(?:[eE][-+]?[0-9]+)?)\)").unwrap();
+}
+
+// The datapoints quoted in the state reason, oldest first. CloudWatch lists
+// them newest first and only quotes the ones it evaluated.
+pub(crate) fn parse_datapoints(reason: &str) -> Vec<f64> {
+    if let Some(list) = DATAPOINT_LIST.captures(reason).and_then(|c| c.get(1)) {
+        let mut values: Vec<f64> = LISTED_VALUE
+            .captures_iter(list.as_str())
+            .filter_map(|c| c.get(1).and_then(|value| value.as_str().parse().ok()))
+            .collect();
+        values.reverse();
+        return values;
+    }
+    SINGLE_DATAPOINT
+        .captures(reason)
+        .and_then(|c| c.get(1))
+        .and_then(|value| value.as_str().parse().ok())
+        .into_iter()
+        .collect()
+}
+
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new() -> Canvas {
+        Canvas { pixels: vec![BACKGROUND; WIDTH * HEIGHT] }
+    }
+
+    fn set(&mut self, x: isize, y: isize, color: u8) {
+        if x >= 0 && y >= 0 && (x as usize) < WIDTH && (y as usize) < HEIGHT {
+            self.pixels[y as usize * WIDTH + x as usize] = color;
+        }
+    }
+
+    fn fill(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: u8) {
+        for y in y0..y1 + 1 {
+            for x in x0..x1 + 1 {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    // Bresenham, two pixels thick.
+    fn line(&mut self, (x0, y0): (isize, isize), (x1, y1): (isize, isize), color: u8) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        loop {
+            self.fill(x, y, x + 1, y + 1, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+}
+
+// The series with the threshold as a dashed line, the breaching datapoints
+// shaded. `None` when the state reason quotes less than two datapoints, there
+// is nothing to chart.
+pub(crate) fn render_png(alarm: &AlarmDetails) -> Option<Vec<u8>> {
+    let values = parse_datapoints(&alarm.reason);
+    if values.len() < 2 {
+        return None;
+    }
+    let trigger = &alarm.trigger;
+    let threshold = trigger.threshold;
+
+    let min = values.iter().fold(threshold, |min, &v| min.min(v));
+    let max = values.iter().fold(threshold, |max, &v| max.max(v));
+    let margin = if max > min { (max - min) * 0.1 } else { min.abs().max(1.0) * 0.1 };
+    let (low, high) = (min - margin, max + margin);
+
+    let plot_width = (WIDTH - 2 * PADDING) as f64;
+    let plot_height = (HEIGHT - 2 * PADDING) as f64;
+    let step = plot_width / (values.len() - 1) as f64;
+    let x_of = |i: usize| (PADDING as f64 + i as f64 * step).round() as isize;
+    let y_of = |value: f64| (PADDING as f64 + (high - value) / (high - low) * plot_height).round() as isize;
+
+    let mut canvas = Canvas::new();
+
+    let half_band = (step / 2.0).round() as isize;
+    for (i, &value) in values.iter().enumerate() {
+        if trigger.op.breaches(value, threshold) {
+            let x = x_of(i);
+            canvas.fill(x - half_band, 0, x + half_band, HEIGHT as isize - 1, BREACH);
+        }
+    }
+
+    let threshold_y = y_of(threshold);
+    for x in 0..WIDTH {
+        if x % (DASH + GAP) < DASH {
+            canvas.set(x as isize, threshold_y, THRESHOLD);
+        }
+    }
+
+    let points: Vec<(isize, isize)> = values.iter().enumerate().map(|(i, &v)| (x_of(i), y_of(v))).collect();
+    for pair in points.windows(2) {
+        canvas.line(pair[0], pair[1], SERIES);
+    }
+    for &(x, y) in &points {
+        canvas.fill(x - MARKER_RADIUS, y - MARKER_RADIUS, x + MARKER_RADIUS, y + MARKER_RADIUS, MARKER);
+    }
+
+    Some(png::encode(WIDTH as u32, HEIGHT as u32, &PALETTE, &canvas.pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::fixtures::alarm_details;
+
+    #[test]
+    fn test_parse_datapoints() {
+        assert_eq!(
+            parse_datapoints(
+                "Threshold Crossed: 3 datapoints [5.0 (27/02/16 11:20:00), 4.0 (27/02/16 11:19:00), \
+                 -1.5E-4 (27/02/16 11:18:00)] were greater than or equal to the threshold (1.0).",
+            ),
+            vec![-1.5E-4, 4.0, 5.0]
+        );
+        assert_eq!(
+            parse_datapoints(
+                "Threshold Crossed: 1 out of the last 2 datapoints [2.0 (27/02/16 11:20:00)] was greater \
+                 than the threshold (1.0) (minimum 1 datapoint for OK -> ALARM transition).",
+            ),
+            vec![2.0]
+        );
+        assert_eq!(
+            parse_datapoints(
+                "Threshold Crossed: 1 datapoint (0.4482758620689655) was not greater than or equal to the \
+                 threshold (1.0).",
+            ),
+            vec![0.4482758620689655]
+        );
+        assert_eq!(parse_datapoints("Insufficient Data: 1 datapoint was unknown."), Vec::<f64>::new());
+    }
+
+    #[test]
+    fn test_render_png() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        assert_eq!(render_png(&alarm), None);
+
+        alarm.reason = "Threshold Crossed: 2 datapoints [3.0 (27/02/16 11:20:00), 0.0 (27/02/16 11:19:00)] \
+                        were greater than or equal to the threshold (1.0)."
+            .to_string();
+        let png = render_png(&alarm).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 1, 0xE0, 0, 0, 0, 0xA0]);
+    }
+
+    #[test]
+    fn test_canvas_shades_breaches() {
+        let mut alarm = alarm_details("ALARM", "OK");
+        alarm.reason = "Threshold Crossed: 2 datapoints [3.0 (27/02/16 11:20:00), 0.0 (27/02/16 11:19:00)] \
+                        were greater than or equal to the threshold (1.0)."
+            .to_string();
+        let png = render_png(&alarm).unwrap();
+
+        // Stored blocks keep the scanlines as is: the first one starts with
+        // its filter byte, then the background left of the newest point's
+        // band and the band itself at the right edge.
+        let start = png.windows(4).position(|w| w == b"IDAT").unwrap() + 4 + 2 + 5;
+        assert_eq!(png[start], 0);
+        assert_eq!(png[start + 1], BACKGROUND);
+        assert_eq!(png[start + WIDTH], BREACH);
+    }
+}
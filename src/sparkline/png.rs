@@ -0,0 +1,119 @@
+// Minimal PNG encoder for 8-bit indexed images. The image data is stored in
+// uncompressed deflate blocks: sparklines are small, and it keeps the encoder
+// free of any compression code.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+// Largest length of a stored deflate block.
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+lazy_static! {
+    static ref CRC_TABLE: Vec<u32> = (0..256)
+        .map(|n| {
+            (0..8).fold(n as u32, |c, _| if c & 1 == 1 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 })
+        })
+        .collect();
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(0xFFFF_FFFF, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + u32::from(byte)) % 65_521;
+        (a, (b + a) % 65_521)
+    });
+    (b << 16) | a
+}
+
+fn push_u32(output: &mut Vec<u8>, value: u32) {
+    output.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+fn push_chunk(output: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    push_u32(output, data.len() as u32);
+    let start = output.len();
+    output.extend_from_slice(kind);
+    output.extend_from_slice(data);
+    let crc = crc32(&output[start..]);
+    push_u32(output, crc);
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window and no preset dictionary, the check bits
+    // make the header a multiple of 31.
+    let mut output = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        output.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        output.push(if last { 0x01 } else { 0x00 });
+        output.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        output.extend_from_slice(block);
+    }
+    push_u32(&mut output, adler32(data));
+    output
+}
+
+// `pixels` are palette indexes, row by row.
+pub(crate) fn encode(width: u32, height: u32, palette: &[[u8; 3]], pixels: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    push_u32(&mut header, width);
+    push_u32(&mut header, height);
+    // Bit depth 8, indexed color, default compression, filter and no
+    // interlacing.
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+    let palette: Vec<u8> = palette.iter().flat_map(|color| color.iter().cloned()).collect();
+
+    // Every scanline starts with its filter type, none here.
+    let mut scanlines = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    push_chunk(&mut png, b"IHDR", &header);
+    push_chunk(&mut png, b"PLTE", &palette);
+    push_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    push_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        let data = vec![7; MAX_STORED_BLOCK + 1];
+        let zlib = zlib_stored(&data);
+
+        assert_eq!(&zlib[..3], &[0x78, 0x01, 0x00]);
+        assert_eq!(&zlib[3..7], &[0xFF, 0xFF, 0x00, 0x00]);
+        assert_eq!(zlib[7 + MAX_STORED_BLOCK], 0x01);
+        assert_eq!(zlib.len(), 2 + 2 * 5 + data.len() + 4);
+    }
+
+    #[test]
+    fn test_encode() {
+        let png = encode(2, 1, &[[255, 255, 255], [0, 0, 0]], &[0, 1]);
+
+        assert_eq!(&png[..8], &SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+}
@@ -0,0 +1,493 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use links::{LogsInsightsQueries, alarm_console_url, logs_insights_url};
+use model::{AlarmDetails, AlarmState};
+use notifiers::alarm_json;
+
+// Template sets by name, as declared in the configuration.
+pub(crate) type Templates = HashMap<String, TemplateSet>;
+
+#[derive(Debug, PartialEq, Clone)]
+enum Helper {
+    // Seconds as "1h 30m".
+    Duration,
+    StateEmoji,
+    ConsoleUrl,
+    // Query configured for the namespace of the alarm.
+    LogsInsightsUrl,
+}
+
+impl Helper {
+    fn from_name(name: &str) -> Option<Helper> {
+        match name {
+            "duration" => Some(Helper::Duration),
+            "state_emoji" => Some(Helper::StateEmoji),
+            "console_url" => Some(Helper::ConsoleUrl),
+            "logs_insights_url" => Some(Helper::LogsInsightsUrl),
+            _ => None,
+        }
+    }
+
+    fn arity(&self) -> usize {
+        match *self {
+            Helper::Duration | Helper::StateEmoji => 1,
+            Helper::ConsoleUrl | Helper::LogsInsightsUrl => 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Argument {
+    Path(String),
+    Literal(Value),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Expression {
+    Argument(Argument),
+    Helper(Helper, Vec<Argument>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+enum Node {
+    Text(String),
+    Expression(Expression),
+    If(Expression, Vec<Node>, Vec<Node>),
+    Each(Expression, Vec<Node>),
+}
+
+// Handlebars-like template over the normalized alarm (see `alarm_json`):
+// `{{trigger.namespace}}`, `{{#if description}}...{{else}}...{{/if}}`,
+// `{{#each trigger.dimensions}}{{@key}}={{this}}{{/each}}` and the
+// `duration`, `state_emoji`, `console_url` and `logs_insights_url` helpers.
+// Nothing is escaped, the output is sent as plain text.
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct Template {
+    nodes: Vec<Node>,
+}
+
+fn tokenize(tag: &str) -> Result<Vec<Argument>, String> {
+    let mut arguments = Vec::new();
+    let mut rest = tag.trim();
+    while !rest.is_empty() {
+        if rest.starts_with('"') {
+            let end = rest[1..].find('"').ok_or_else(|| format!("unterminated string in `{{{{{}}}}}`", tag))?;
+            arguments.push(Argument::Literal(Value::String(rest[1..end + 1].to_string())));
+            rest = rest[end + 2..].trim_left();
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let token = &rest[..end];
+            arguments.push(match token.parse::<f64>() {
+                Ok(number) => Argument::Literal(json!(number)),
+                Err(_) => Argument::Path(token.to_string()),
+            });
+            rest = rest[end..].trim_left();
+        }
+    }
+    Ok(arguments)
+}
+
+fn expression(tag: &str) -> Result<Expression, String> {
+    let mut arguments = tokenize(tag)?;
+    if arguments.is_empty() {
+        return Err("empty `{{}}`".to_string());
+    }
+    let helper = match arguments[0] {
+        Argument::Path(ref name) => Helper::from_name(name),
+        Argument::Literal(_) => None,
+    };
+    match helper {
+        Some(helper) => {
+            arguments.remove(0);
+            if arguments.len() != helper.arity() {
+                return Err(format!(
+                    "`{}` expects {} argument(s), found {}",
+                    tag.split_whitespace().next().unwrap_or(""),
+                    helper.arity(),
+                    arguments.len()
+                ));
+            }
+            Ok(Expression::Helper(helper, arguments))
+        }
+        None if arguments.len() == 1 => Ok(Expression::Argument(arguments.remove(0))),
+        None => Err(format!("unknown helper `{}`", tag.split_whitespace().next().unwrap_or(""))),
+    }
+}
+
+// Blocks being parsed: the block tag, its condition and the nodes of its
+// branches so far.
+struct Block {
+    name: &'static str,
+    condition: Option<Expression>,
+    nodes: Vec<Node>,
+    else_nodes: Option<Vec<Node>>,
+}
+
+impl Block {
+    fn current(&mut self) -> &mut Vec<Node> {
+        match self.else_nodes {
+            Some(ref mut nodes) => nodes,
+            None => &mut self.nodes,
+        }
+    }
+}
+
+impl Template {
+    pub fn compile(source: &str) -> Result<Template, String> {
+        let mut stack = vec![
+            Block {
+                name: "",
+                condition: None,
+                nodes: Vec::new(),
+                else_nodes: None,
+            },
+        ];
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            let end = rest[start..]
+                .find("}}")
+                .map(|end| start + end)
+                .ok_or_else(|| "unterminated `{{`".to_string())?;
+            if start > 0 {
+                stack.last_mut().unwrap().current().push(Node::Text(rest[..start].to_string()));
+            }
+            let tag = rest[start + 2..end].trim();
+            rest = &rest[end + 2..];
+
+            if tag.starts_with('!') {
+                continue;
+            } else if tag.starts_with("#if ") || tag.starts_with("#each ") {
+                let name = if tag.starts_with("#if ") { "if" } else { "each" };
+                stack.push(Block {
+                    name: name,
+                    condition: Some(expression(&tag[name.len() + 2..])?),
+                    nodes: Vec::new(),
+                    else_nodes: None,
+                });
+            } else if tag == "else" {
+                let block = stack.last_mut().unwrap();
+                if block.name != "if" || block.else_nodes.is_some() {
+                    return Err("`{{else}}` outside of `{{#if}}`".to_string());
+                }
+                block.else_nodes = Some(Vec::new());
+            } else if tag.starts_with('/') {
+                if stack.len() == 1 || stack.last().unwrap().name != &tag[1..] {
+                    return Err(format!("unexpected `{{{{{}}}}}`", tag));
+                }
+                let block = stack.pop().unwrap();
+                let condition = block.condition.expect("Only the root block has no condition");
+                let node = match block.name {
+                    "if" => Node::If(condition, block.nodes, block.else_nodes.unwrap_or_default()),
+                    _ => Node::Each(condition, block.nodes),
+                };
+                stack.last_mut().unwrap().current().push(node);
+            } else {
+                let node = Node::Expression(expression(tag)?);
+                stack.last_mut().unwrap().current().push(node);
+            }
+        }
+        if !rest.is_empty() {
+            stack.last_mut().unwrap().current().push(Node::Text(rest.to_string()));
+        }
+
+        if stack.len() > 1 {
+            return Err(format!("unclosed `{{{{#{}}}}}`", stack.last().unwrap().name));
+        }
+        Ok(Template { nodes: stack.pop().unwrap().nodes })
+    }
+
+    pub fn render(&self, alarm: &AlarmDetails, logs_insights: &LogsInsightsQueries) -> String {
+        let context = alarm_json(alarm);
+        let mut renderer = Renderer {
+            alarm: alarm,
+            logs_insights: logs_insights,
+            scopes: vec![(Value::Null, context)],
+            output: String::new(),
+        };
+        renderer.render(&self.nodes);
+        renderer.output
+    }
+}
+
+fn display(value: &Value) -> String {
+    match *value {
+        Value::Null => String::new(),
+        Value::String(ref text) => text.clone(),
+        // Whole numbers without the trailing ".0" of their f64 form.
+        Value::Number(ref number) => match number.as_f64() {
+            Some(float) if float.fract() == 0.0 && float.abs() < 1e15 => format!("{}", float as i64),
+            _ => number.to_string(),
+        },
+        ref other => other.to_string(),
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match *value {
+        Value::Null => false,
+        Value::Bool(boolean) => boolean,
+        Value::Number(ref number) => number.as_f64().map_or(false, |n| n != 0.0),
+        Value::String(ref text) => !text.is_empty(),
+        Value::Array(ref values) => !values.is_empty(),
+        Value::Object(ref map) => !map.is_empty(),
+    }
+}
+
+pub(crate) fn format_duration(seconds: u64) -> String {
+    if seconds == 0 {
+        return "0s".to_string();
+    }
+    let units = [("d", 86_400), ("h", 3600), ("m", 60), ("s", 1)];
+    let mut remaining = seconds;
+    let mut parts = Vec::new();
+    for &(unit, size) in &units {
+        if remaining >= size {
+            parts.push(format!("{}{}", remaining / size, unit));
+            remaining %= size;
+        }
+    }
+    parts.join(" ")
+}
+
+pub(crate) fn state_emoji(state: &AlarmState) -> &'static str {
+    match *state {
+        AlarmState::Ok => "✅",
+        AlarmState::Alarm => "🔴",
+        AlarmState::InsufficientData => "⚠️",
+    }
+}
+
+struct Renderer<'a> {
+    alarm: &'a AlarmDetails,
+    logs_insights: &'a LogsInsightsQueries,
+    // Values being iterated by `#each` with their key, the whole context at
+    // the bottom.
+    scopes: Vec<(Value, Value)>,
+    output: String,
+}
+
+impl<'a> Renderer<'a> {
+    // Paths are looked up from the innermost scope outwards, `this` and
+    // `@key` refer to the current `#each` item.
+    fn lookup(&self, path: &str) -> Value {
+        let &(ref key, ref current) = self.scopes.last().expect("There is always a root scope");
+        if path == "@key" || path == "@index" {
+            return key.clone();
+        }
+        let mut parts: Vec<&str> = path.split('.').collect();
+        if parts[0] == "this" {
+            parts.remove(0);
+            return parts.iter().fold(Some(current), |v, part| v.and_then(|v| v.get(part))).cloned().unwrap_or(Value::Null);
+        }
+        self.scopes
+            .iter()
+            .rev()
+            .filter_map(|&(_, ref scope)| parts.iter().fold(Some(scope), |v, part| v.and_then(|v| v.get(part))))
+            .next()
+            .cloned()
+            .unwrap_or(Value::Null)
+    }
+
+    fn argument(&self, argument: &Argument) -> Value {
+        match *argument {
+            Argument::Path(ref path) => self.lookup(path),
+            Argument::Literal(ref value) => value.clone(),
+        }
+    }
+
+    fn evaluate(&self, expression: &Expression) -> Value {
+        match *expression {
+            Expression::Argument(ref argument) => self.argument(argument),
+            Expression::Helper(ref helper, ref arguments) => {
+                let arguments: Vec<Value> = arguments.iter().map(|a| self.argument(a)).collect();
+                match *helper {
+                    Helper::Duration => {
+                        let seconds = arguments[0]
+                            .as_f64()
+                            .or_else(|| arguments[0].as_str().and_then(|s| s.parse().ok()));
+                        seconds.map_or(Value::Null, |s: f64| Value::String(format_duration(s.max(0.0) as u64)))
+                    }
+                    Helper::StateEmoji => arguments[0]
+                        .as_str()
+                        .and_then(|state| state.parse().ok())
+                        .map_or(Value::Null, |state| Value::String(state_emoji(&state).to_string())),
+                    Helper::ConsoleUrl => alarm_console_url(self.alarm).map_or(Value::Null, Value::String),
+                    Helper::LogsInsightsUrl => self.logs_insights
+                        .get(&self.alarm.trigger.namespace)
+                        .and_then(|query| logs_insights_url(self.alarm, query))
+                        .map_or(Value::Null, Value::String),
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            match *node {
+                Node::Text(ref text) => self.output.push_str(text),
+                Node::Expression(ref expression) => {
+                    let value = self.evaluate(expression);
+                    self.output.push_str(&display(&value));
+                }
+                Node::If(ref condition, ref then_nodes, ref else_nodes) => {
+                    if truthy(&self.evaluate(condition)) {
+                        self.render(then_nodes);
+                    } else {
+                        self.render(else_nodes);
+                    }
+                }
+                Node::Each(ref expression, ref body) => {
+                    let items: Vec<(Value, Value)> = match self.evaluate(expression) {
+                        Value::Array(values) => values.into_iter().enumerate().map(|(i, v)| (json!(i), v)).collect(),
+                        Value::Object(map) => map.into_iter().map(|(k, v)| (Value::String(k), v)).collect(),
+                        _ => Vec::new(),
+                    };
+                    for item in items {
+                        self.scopes.push(item);
+                        self.render(body);
+                        self.scopes.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+// A default template with overrides for some state transitions. Transitions
+// are written "ALARM" (any previous state) or "OK->ALARM".
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct TemplateSet {
+    pub default: Template,
+    pub transitions: Vec<(Option<AlarmState>, AlarmState, Template)>,
+}
+
+pub(crate) fn parse_transition(transition: &str) -> Result<(Option<AlarmState>, AlarmState), String> {
+    let parse = |state: &str| state.trim().parse::<AlarmState>().map_err(|err| err.to_string());
+    match transition.find("->") {
+        Some(pos) => Ok((Some(parse(&transition[..pos])?), parse(&transition[pos + 2..])?)),
+        None => Ok((None, parse(transition)?)),
+    }
+}
+
+impl TemplateSet {
+    // The most specific template wins: "OK->ALARM", then "ALARM", then the
+    // default one.
+    pub fn select(&self, alarm: &AlarmDetails) -> &Template {
+        let exact = self.transitions.iter().find(|&&(ref previous, ref new, _)| {
+            previous.as_ref() == Some(&alarm.previous_state) && *new == alarm.new_state
+        });
+        let any_previous = self.transitions
+            .iter()
+            .find(|&&(ref previous, ref new, _)| previous.is_none() && *new == alarm.new_state);
+        exact
+            .or(any_previous)
+            .map_or(&self.default, |&(_, _, ref template)| template)
+    }
+
+    pub fn render(&self, alarm: &AlarmDetails, logs_insights: &LogsInsightsQueries) -> String {
+        self.select(alarm).render(alarm, logs_insights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use links::{LogsInsightsQuery, metric_graph_url};
+    use model::fixtures::alarm_details;
+
+    fn render(source: &str, alarm: &AlarmDetails) -> String {
+        Template::compile(source)
+            .expect("Template should compile")
+            .render(alarm, &LogsInsightsQueries::new())
+    }
+
+    #[test]
+    fn test_render() {
+        let alarm = alarm_details("ALARM", "OK");
+
+        assert_eq!(
+            render("{{state_emoji new_state}} {{ name }} ({{previous_state}} -> {{new_state}})", &alarm),
+            "🔴 [RTB-US] UnHealthyHostCount (OK -> ALARM)"
+        );
+        assert_eq!(
+            render("{{trigger.metric_name}} {{trigger.comparison_operator}} {{trigger.threshold}}", &alarm),
+            "UnHealthyHostCount >= 1"
+        );
+        assert_eq!(render("{{duration trigger.period}}, {{duration 5400}}", &alarm), "1m, 1h 30m");
+        assert_eq!(render("{{nope}}{{! comment }}.", &alarm), ".");
+        assert_eq!(render("{{metric_graph_url}}", &alarm), metric_graph_url(&alarm).unwrap());
+        assert_eq!(
+            render("{{console_url}}", &alarm),
+            "https://console.aws.amazon.com/cloudwatch/home?region=us-east-1#alarmsV2:alarm/%5BRTB-US%5D%20UnHealthyHostCount"
+        );
+    }
+
+    #[test]
+    fn test_render_blocks() {
+        let alarm = alarm_details("ALARM", "OK");
+
+        assert_eq!(
+            render("{{#each trigger.dimensions}}{{@key}}={{this}} ({{name}}){{/each}}", &alarm),
+            "LoadBalancerName=rtb ([RTB-US] UnHealthyHostCount)"
+        );
+        assert_eq!(render("{{#if arn}}arn{{else}}no arn{{/if}}", &alarm), "arn");
+        assert_eq!(render("{{#if envelope.subject}}subject{{else}}no subject{{/if}}", &alarm), "no subject");
+    }
+
+    #[test]
+    fn test_render_logs_insights_url() {
+        let alarm = alarm_details("ALARM", "OK");
+        let query = LogsInsightsQuery {
+            log_groups: vec!["/aws/elb/rtb".to_string()],
+            query: "fields @message".to_string(),
+        };
+        let mut logs_insights = LogsInsightsQueries::new();
+        let template = Template::compile("{{logs_insights_url}}").unwrap();
+
+        assert_eq!(template.render(&alarm, &logs_insights), "");
+        logs_insights.insert("AWS/ELB".to_string(), query.clone());
+        assert_eq!(template.render(&alarm, &logs_insights), logs_insights_url(&alarm, &query).unwrap());
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let error = |source: &str| Template::compile(source).err().expect("Template should not compile");
+
+        assert_eq!(error("{{name"), "unterminated `{{`");
+        assert_eq!(error("{{#if arn}}arn"), "unclosed `{{#if}}`");
+        assert_eq!(error("{{#if arn}}{{/each}}"), "unexpected `{{/each}}`");
+        assert_eq!(error("{{else}}"), "`{{else}}` outside of `{{#if}}`");
+        assert_eq!(error("{{upper name}}"), "unknown helper `upper`");
+        assert_eq!(error("{{duration}}"), "`duration` expects 1 argument(s), found 0");
+        assert_eq!(error("{{duration \"5}}"), "unterminated string in `{{duration \"5}}`");
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(300), "5m");
+        assert_eq!(format_duration(90_061), "1d 1h 1m 1s");
+    }
+
+    #[test]
+    fn test_select_by_transition() {
+        let template = |source: &str| Template::compile(source).unwrap();
+        let set = TemplateSet {
+            default: template("default"),
+            transitions: vec![
+                (Some(AlarmState::Alarm), AlarmState::Ok, template("recovered")),
+                (None, AlarmState::Ok, template("ok")),
+            ],
+        };
+
+        assert_eq!(set.select(&alarm_details("OK", "ALARM")), &template("recovered"));
+        assert_eq!(set.select(&alarm_details("OK", "INSUFFICIENT_DATA")), &template("ok"));
+        assert_eq!(set.select(&alarm_details("ALARM", "OK")), &template("default"));
+        assert_eq!(parse_transition("OK->ALARM"), Ok((Some(AlarmState::Ok), AlarmState::Alarm)));
+        assert_eq!(parse_transition("INSUFFICIENT_DATA"), Ok((None, AlarmState::InsufficientData)));
+        assert_eq!(parse_transition("OK->").is_err(), true);
+    }
+}